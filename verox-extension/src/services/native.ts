@@ -8,27 +8,280 @@ export interface NativeResponse {
   success: boolean;
   data?: any;
   error?: string;
+  code?: NativeErrorCode | null;
 }
 
+// Protocol version 2: requests carry an id that the host echoes in its reply, so
+// several can be in flight at once. Frames without an id are events.
+export const PROTOCOL_VERSION = 2;
+
+interface NativeRequest {
+  id: number;
+  version: number;
+  method: string;
+  params?: any;
+}
+
+interface NativeReply extends NativeResponse {
+  id?: number | string | null;
+  version?: number;
+}
+
+// A piece of a reply too large for one native messaging frame
+interface NativeChunk {
+  id: number | string;
+  version: number;
+  chunk: { index: number; count: number; data: string };
+}
+
+export interface NativeEvent {
+  version: number;
+  event: string;
+  data: any;
+}
+
+export interface NativeSession {
+  version: number;
+  capabilities: string[];
+  host: { name: string; version: string };
+}
+
+// Stable error codes returned by the native host (mirrors `VeroxError::code`)
+export type NativeErrorCode =
+  | 'WRONG_PASSPHRASE'
+  | 'CORRUPT_KEYSTORE'
+  | 'UNSUPPORTED_VERSION'
+  | 'WALLET_NOT_FOUND'
+  | 'IO_ERROR'
+  | 'PARSE_ERROR'
+  | 'BIOMETRIC_ERROR'
+  | 'INVALID_ARGUMENT'
+  | 'INVALID_MNEMONIC'
+  | 'RPC_ERROR'
+  | 'FEE_TOO_HIGH'
+  | 'WALLET_LOCKED'
+  | 'UNAUTHORIZED'
+  | 'INVALID_REQUEST'
+  | 'UNKNOWN_ACTION'
+  | 'UNSUPPORTED_PROTOCOL'
+  | 'MESSAGE_TOO_LARGE'
+  | 'RESPONSE_TOO_LARGE'
+  | 'PROVIDER_ERROR'
+  | 'INTERNAL_ERROR';
+
 export interface WalletInfo {
   address: string;
   balance: string;
   private_key?: string;
+  mnemonic?: string;
 }
 
+// Quantities are wei as decimal or 0x-hex strings; `amount` is ether
 export interface TransactionData {
+  // Leave out while the agent holds the sender's key
+  passphrase?: string;
+  address?: string;
   to: string;
-  amount: string;
+  amount?: string;
+  value?: string;
+  data?: string;
+  chain_id?: number;
+  // Both default to what the network reports
+  nonce?: number;
+  gas_limit?: string;
   gas_price?: string;
+  max_fee_per_gas?: string;
+  max_priority_fee_per_gas?: string;
+  type?: 'legacy' | 'eip2930' | 'eip1559';
+  // Preset for fees left out (default normal); fees far above recent medians
+  // fail with FEE_TOO_HIGH unless allow_high_fee is set
+  speed?: FeeSpeed;
+  allow_high_fee?: boolean;
+  // false signs without broadcasting (status 'signed')
+  broadcast?: boolean;
+}
+
+export type TransactionStatus = 'pending' | 'included' | 'confirmed' | 'failed' | 'dropped' | 'replaced';
+
+export interface SentTransaction {
+  from: string;
+  to: string | null;
+  chain_id: number;
+  nonce: string;
+  tx_hash: string;
+  raw_transaction: string;
+  status: 'signed' | TransactionStatus;
+  explorer_url: string | null;
+}
+
+export interface TrackedTransaction {
+  hash: string;
+  chain_id: number;
+  from: string;
+  to: string | null;
+  nonce: number;
+  raw: string;
+  status: TransactionStatus;
+  submitted_at: string;
+  last_seen_at: string;
+  block_number?: number;
+  confirmations: number;
+  replaced_by?: string;
+  // Hash of the transaction this one speeds up or cancels
+  replaces?: string;
+}
+
+export interface ReplacementTransaction {
+  replaces: string;
+  tx_hash: string;
+  nonce: number;
+  chain_id: number;
+  max_fee_per_gas: string;
+  max_priority_fee_per_gas: string;
+  gas_price: string;
+  status: TransactionStatus;
+  explorer_url: string | null;
+}
+
+export interface ReplacementOptions {
+  speed?: 'slow' | 'normal' | 'fast';
+  allow_high_fee?: boolean;
+}
+
+export interface NonceStatus {
+  chain_id: number;
+  address: string;
+  mined: number;
+  pending: number;
+  next: number;
+  in_use: number[];
+  // Unused nonces that block every later transaction
+  gaps: number[];
+  // Nonce of a transaction that has been pending long enough to need a speed-up
+  stuck: number | null;
+}
+
+export interface TypedField {
+  name: string;
+  type: string;
+  value?: string;
+  children?: TypedField[];
+}
+
+export interface TypedDataPreview {
+  primary_type: string;
+  chain_id: number | null;
+  fields: TypedField[];
+  domain_separator: string;
+  digest: string;
+}
+
+export interface Network {
+  chain_id: number;
+  name: string;
+  rpc_urls: string[];
+  native_currency: { name: string; symbol: string; decimals: number };
+  explorer_url?: string;
+}
+
+export interface Balance {
+  address: string;
+  chain_id: number;
+  balance: string;
+  balance_wei: string;
+  symbol: string;
+  nonce: string;
+}
+
+export type FeeSpeed = 'slow' | 'normal' | 'fast';
+
+// Wei amounts are decimal strings
+export interface FeePreset {
+  speed: FeeSpeed;
+  max_fee_per_gas: string;
+  max_priority_fee_per_gas: string;
+  expected_blocks: number;
+  expected_seconds: number;
+}
+
+export interface FeeEstimate {
+  chain_id: number;
+  eip1559: boolean;
+  base_fee_per_gas: string;
+  median_base_fee: string;
+  median_priority_fee: string;
+  fee_cap: string;
+  presets: FeePreset[];
+}
+
+// EIP-1193 `request()` arguments
+export interface RequestArguments {
+  method: string;
+  params?: unknown[] | object;
+}
+
+// What the user decided in the approval prompt: a passphrase for requests that
+// sign, or `approved` for the rest. A connection can be narrowed to some
+// accounts and chains (hex chain IDs); it defaults to every account on any chain.
+export interface ProviderApproval {
+  approved?: boolean;
+  passphrase?: string;
+  accounts?: string[];
+  chains?: string[];
+}
+
+// An EIP-1193 error with its numeric code (4001 user rejected, 4100 unauthorized, ...)
+export class ProviderRpcError extends Error {
+  code: number;
+  data?: unknown;
+
+  constructor(code: number, message: string, data?: unknown) {
+    super(message);
+    this.code = code;
+    this.data = data;
+  }
+}
+
+// `accountsChanged` as the host pushes it: for one origin, whose pages get `accounts`
+export interface AccountsChangedEvent {
+  origin: string;
+  accounts: string[];
+}
+
+// EIP-1193 `disconnect` / `connect` payloads; a disconnect carries code 4900
+export interface ProviderDisconnectEvent {
+  code: number;
+  message: string;
+}
+
+export interface ProviderConnectEvent {
+  chainId: string;
+}
+
+// What the session agent (`verox agent`) holds; times are in seconds
+export interface AgentStatus {
+  running: boolean;
+  status: {
+    accounts: string[];
+    idle_expires_in: number | null;
+    expires_in: number | null;
+  } | null;
 }
 
 export interface BiometricResult {
   verified: boolean;
   method: string;
+  /** The account unlocked after the check, with the passphrase or by the agent */
+  address?: string;
 }
 
 class VeroxNativeService {
   private port: chrome.runtime.Port | null = null;
+  private nextId = 1;
+  private pending = new Map<number, (response: NativeResponse) => void>();
+  private chunks = new Map<number | string, string[]>();
+  private listeners = new Map<string, Set<(data: any) => void>>();
+  private session: Promise<NativeSession> | null = null;
 
   constructor() {
     this.initializePort();
@@ -39,54 +292,122 @@ class VeroxNativeService {
       // Connect to the native messaging host
       this.port = chrome.runtime.connectNative('com.verox.native_host');
       
-      this.port.onMessage.addListener((response: NativeResponse) => {
-        console.log('Received from native host:', response);
-      });
+      this.port.onMessage.addListener((frame: NativeReply | NativeEvent | NativeChunk) => this.dispatch(frame));
 
       this.port.onDisconnect.addListener(() => {
         console.log('Native host disconnected');
         this.port = null;
+        this.session = null;
+        for (const resolve of this.pending.values()) {
+          resolve({ success: false, error: 'Native host disconnected' });
+        }
+        this.pending.clear();
+        this.chunks.clear();
+      });
+
+      this.session = this.request('hello', {
+        version: PROTOCOL_VERSION,
+        capabilities: ['multiplex', 'events', 'chunks']
+      }).then((response) => {
+        if (!response.success) {
+          throw new Error(response.error || 'Native host handshake failed');
+        }
+        return response.data as NativeSession;
       });
+      this.session.catch((error) => console.error('Native host handshake failed:', error));
     } catch (error) {
       console.error('Failed to connect to native host:', error);
     }
   }
 
-  private async sendMessage(message: NativeMessage): Promise<NativeResponse> {
-    return new Promise((resolve, reject) => {
-      if (!this.port) {
-        this.initializePort();
+  private dispatch(frame: NativeReply | NativeEvent | NativeChunk) {
+    if ('chunk' in frame) {
+      const { index, count, data } = frame.chunk;
+      const parts = this.chunks.get(frame.id) ?? new Array<string>(count);
+      parts[index] = data;
+      this.chunks.set(frame.id, parts);
+      if (parts.filter((part) => part !== undefined).length === count) {
+        this.chunks.delete(frame.id);
+        this.dispatch(JSON.parse(parts.join('')));
       }
+      return;
+    }
+    if ('event' in frame) {
+      this.listeners.get(frame.event)?.forEach((listener) => listener(frame.data));
+      return;
+    }
+    const resolve = typeof frame.id === 'number' ? this.pending.get(frame.id) : undefined;
+    if (resolve) {
+      this.pending.delete(frame.id as number);
+      resolve(frame);
+    } else {
+      console.warn('Unmatched reply from native host:', frame);
+    }
+  }
 
+  private request(method: string, params?: any, timeoutMs = 5000): Promise<NativeResponse> {
+    return new Promise((resolve, reject) => {
       if (!this.port) {
         reject(new Error('Failed to connect to native host'));
         return;
       }
 
+      const id = this.nextId++;
       const timeout = setTimeout(() => {
+        this.pending.delete(id);
+        this.chunks.delete(id);
         reject(new Error('Native host timeout'));
-      }, 5000);
+      }, timeoutMs);
 
-      const messageHandler = (response: NativeResponse) => {
+      this.pending.set(id, (response) => {
         clearTimeout(timeout);
-        this.port?.onMessage.removeListener(messageHandler);
         resolve(response);
-      };
-
-      this.port.onMessage.addListener(messageHandler);
-      this.port.postMessage(message);
+      });
+      const request: NativeRequest = { id, version: PROTOCOL_VERSION, method, params };
+      this.port.postMessage(request);
     });
   }
 
-  async createWallet(): Promise<WalletInfo> {
+  // The host refuses actions that use keys unless they say where they came from. This
+  // service runs in the extension's own pages, so that is our origin; requests relayed
+  // for a page (`provider_request`) already carry the page's.
+  private async sendMessage(message: NativeMessage): Promise<NativeResponse> {
+    if (!this.port) {
+      this.initializePort();
+    }
+    return this.request(message.action, { origin: self.location.origin, ...message.data });
+  }
+
+  // Version and capabilities agreed with the host
+  async getSession(): Promise<NativeSession> {
+    if (!this.port) {
+      this.initializePort();
+    }
+    if (!this.session) {
+      throw new Error('Failed to connect to native host');
+    }
+    return this.session;
+  }
+
+  // Subscribe to an event the host pushes, e.g. `transaction_updated`; returns an unsubscribe function
+  on(event: string, listener: (data: any) => void): () => void {
+    if (!this.listeners.has(event)) {
+      this.listeners.set(event, new Set());
+    }
+    this.listeners.get(event)!.add(listener);
+    return () => this.listeners.get(event)?.delete(listener);
+  }
+
+  async createWallet(passphrase?: string, words: 12 | 24 = 12): Promise<WalletInfo> {
     try {
-      const response = await this.sendMessage({ action: 'create_wallet' });
+      const response = await this.sendMessage({ action: 'create_wallet', data: { passphrase, words } });
       
       if (response.success && response.data) {
         return {
           address: response.data.address,
           balance: '0.0',
-          private_key: response.data.private_key
+          private_key: response.data.private_key,
+          mnemonic: response.data.mnemonic
         };
       } else {
         throw new Error(response.error || 'Failed to create wallet');
@@ -97,9 +418,28 @@ class VeroxNativeService {
     }
   }
 
-  async unlockWallet(): Promise<WalletInfo> {
+  // Whether the agent holds keys: while it does, requests for those accounts need no
+  // passphrase, and leaving it out gets WALLET_LOCKED once the agent has forgotten them
+  async getAgentStatus(): Promise<AgentStatus> {
+    const response = await this.sendMessage({ action: 'agent_status' });
+    if (response.success && response.data) {
+      return response.data;
+    }
+    throw new Error(response.error || 'Failed to get agent status');
+  }
+
+  // Lock the wallet for connected pages and make the agent forget its keys; entering
+  // the passphrase again unlocks it
+  async lockWallet(): Promise<void> {
+    const response = await this.sendMessage({ action: 'lock_wallet' });
+    if (!response.success) {
+      throw new Error(response.error || 'Failed to lock wallet');
+    }
+  }
+
+  async unlockWallet(passphrase?: string, address?: string): Promise<WalletInfo> {
     try {
-      const response = await this.sendMessage({ action: 'unlock_wallet' });
+      const response = await this.sendMessage({ action: 'unlock_wallet', data: { passphrase, address } });
       
       if (response.success && response.data) {
         return {
@@ -115,14 +455,15 @@ class VeroxNativeService {
     }
   }
 
-  async verifyBiometric(): Promise<BiometricResult> {
+  async verifyBiometric(passphrase?: string, address?: string): Promise<BiometricResult> {
     try {
-      const response = await this.sendMessage({ action: 'verify_biometric' });
+      const response = await this.sendMessage({ action: 'verify_biometric', data: { passphrase, address } });
       
       if (response.success && response.data) {
         return {
           verified: response.data.verified,
-          method: response.data.method
+          method: response.data.method,
+          address: response.data.address
         };
       } else {
         throw new Error(response.error || 'Biometric verification failed');
@@ -161,7 +502,7 @@ class VeroxNativeService {
     }
   }
 
-  async sendTransaction(transactionData: TransactionData): Promise<string> {
+  async sendTransaction(transactionData: TransactionData): Promise<SentTransaction> {
     try {
       const response = await this.sendMessage({ 
         action: 'send_transaction',
@@ -169,7 +510,7 @@ class VeroxNativeService {
       });
       
       if (response.success && response.data) {
-        return response.data.tx_hash;
+        return response.data;
       } else {
         throw new Error(response.error || 'Transaction failed');
       }
@@ -179,6 +520,203 @@ class VeroxNativeService {
     }
   }
 
+  // EIP-191 personal_sign; `message` is text or 0x-prefixed hex bytes
+  async signMessage(message: string, passphrase?: string, address?: string): Promise<string> {
+    try {
+      const response = await this.sendMessage({
+        action: 'sign_message',
+        data: { message, passphrase, address }
+      });
+
+      if (response.success && response.data) {
+        return response.data.signature;
+      } else {
+        throw new Error(response.error || 'Failed to sign message');
+      }
+    } catch (error) {
+      console.error('Sign message error:', error);
+      throw error;
+    }
+  }
+
+  // Recovers the signer of a personal_sign signature
+  async verifyMessage(message: string, signature: string): Promise<string> {
+    try {
+      const response = await this.sendMessage({
+        action: 'verify_message',
+        data: { message, signature }
+      });
+
+      if (response.success && response.data) {
+        return response.data.address;
+      } else {
+        throw new Error(response.error || 'Failed to verify message');
+      }
+    } catch (error) {
+      console.error('Verify message error:', error);
+      throw error;
+    }
+  }
+
+  // Fields and digest of an EIP-712 payload, shown before asking to sign
+  async previewTypedData(typedData: object | string): Promise<TypedDataPreview> {
+    try {
+      const response = await this.sendMessage({
+        action: 'preview_typed_data',
+        data: { typed_data: typedData }
+      });
+
+      if (response.success && response.data) {
+        return response.data;
+      } else {
+        throw new Error(response.error || 'Invalid typed data');
+      }
+    } catch (error) {
+      console.error('Preview typed data error:', error);
+      throw error;
+    }
+  }
+
+  // eth_signTypedData_v4
+  async signTypedData(typedData: object | string, passphrase?: string, address?: string): Promise<string> {
+    try {
+      const response = await this.sendMessage({
+        action: 'sign_typed_data',
+        data: { typed_data: typedData, passphrase, address }
+      });
+
+      if (response.success && response.data) {
+        return response.data.signature;
+      } else {
+        throw new Error(response.error || 'Failed to sign typed data');
+      }
+    } catch (error) {
+      console.error('Sign typed data error:', error);
+      throw error;
+    }
+  }
+
+  async getNetworks(): Promise<{ selected: number; networks: Network[] }> {
+    const response = await this.sendMessage({ action: 'get_networks' });
+    if (response.success && response.data) {
+      return response.data;
+    }
+    throw new Error(response.error || 'Failed to get networks');
+  }
+
+  // `network` is a name or chain ID
+  async switchNetwork(network: string | number): Promise<Network> {
+    const response = await this.sendMessage({ action: 'switch_network', data: { network } });
+    if (response.success && response.data) {
+      return response.data;
+    }
+    throw new Error(response.error || 'Failed to switch network');
+  }
+
+  // Balance and next nonce, on the selected network unless one is given
+  async getBalance(address: string, network?: string | number): Promise<Balance> {
+    const response = await this.sendMessage({ action: 'get_balance', data: { address, network } });
+    if (response.success && response.data) {
+      return response.data;
+    }
+    throw new Error(response.error || 'Failed to get balance');
+  }
+
+  async getFeeEstimate(network?: string | number): Promise<FeeEstimate> {
+    const response = await this.sendMessage({ action: 'get_gas_price', data: { network } });
+    if (response.success && response.data) {
+      return response.data;
+    }
+    throw new Error(response.error || 'Failed to estimate fees');
+  }
+
+  async getTransactionStatus(txHash: string, confirmations?: number): Promise<TrackedTransaction> {
+    const response = await this.sendMessage({
+      action: 'get_transaction_status',
+      data: { tx_hash: txHash, confirmations }
+    });
+    if (response.success && response.data) {
+      return response.data;
+    }
+    throw new Error(response.error || 'Failed to get transaction status');
+  }
+
+  async listTransactions(filter: { address?: string; chain_id?: number } = {}): Promise<TrackedTransaction[]> {
+    const response = await this.sendMessage({ action: 'list_transactions', data: filter });
+    if (response.success && response.data) {
+      return response.data.transactions;
+    }
+    throw new Error(response.error || 'Failed to list transactions');
+  }
+
+  async getNonceStatus(address: string, network?: string | number): Promise<NonceStatus> {
+    const response = await this.sendMessage({ action: 'get_nonce_status', data: { address, network } });
+    if (response.success && response.data) {
+      return response.data;
+    }
+    throw new Error(response.error || 'Failed to get nonce status');
+  }
+
+  // Answer an EIP-1193 request from the page at `origin` (scheme://host[:port]); rejects
+  // with a ProviderRpcError the page can be given as is. Pages that haven't connected
+  // through eth_requestAccounts get 4100 for anything beyond eth_chainId and eth_accounts.
+  async providerRequest(origin: string, args: RequestArguments, approval: ProviderApproval = {}): Promise<unknown> {
+    const response = await this.sendMessage({
+      action: 'provider_request',
+      data: { origin, method: args.method, params: args.params, ...approval }
+    });
+    if (response.success && response.data) {
+      return response.data.result;
+    }
+    if (response.code === 'PROVIDER_ERROR' && response.data) {
+      throw new ProviderRpcError(response.data.code, response.data.message, response.data.data);
+    }
+    throw new ProviderRpcError(-32603, response.error || 'Internal error');
+  }
+
+  // EIP-1193 events for connected pages. `accountsChanged` is per origin and must only
+  // reach that origin's pages; the others apply to every page. The wallet being locked,
+  // from here or with `verox lock`, shows up as accountsChanged with no accounts.
+  onAccountsChanged(listener: (event: AccountsChangedEvent) => void): () => void {
+    return this.on('accountsChanged', listener);
+  }
+
+  onChainChanged(listener: (chainId: string) => void): () => void {
+    return this.on('chainChanged', listener);
+  }
+
+  onConnect(listener: (event: ProviderConnectEvent) => void): () => void {
+    return this.on('connect', listener);
+  }
+
+  onDisconnect(listener: (event: ProviderDisconnectEvent) => void): () => void {
+    return this.on('disconnect', listener);
+  }
+
+  // Re-send a pending transaction with the same nonce and payload at a higher fee
+  async speedUpTransaction(txHash: string, passphrase?: string, options: ReplacementOptions = {}): Promise<ReplacementTransaction> {
+    const response = await this.sendMessage({
+      action: 'speed_up_transaction',
+      data: { tx_hash: txHash, passphrase, ...options }
+    });
+    if (response.success && response.data) {
+      return response.data;
+    }
+    throw new Error(response.error || 'Failed to speed up transaction');
+  }
+
+  // Replace a pending transaction with a zero-value transfer to the sender
+  async cancelTransaction(txHash: string, passphrase?: string, options: ReplacementOptions = {}): Promise<ReplacementTransaction> {
+    const response = await this.sendMessage({
+      action: 'cancel_transaction',
+      data: { tx_hash: txHash, passphrase, ...options }
+    });
+    if (response.success && response.data) {
+      return response.data;
+    }
+    throw new Error(response.error || 'Failed to cancel transaction');
+  }
+
   // Fallback methods for when native host is not available
   async createWalletFallback(): Promise<WalletInfo> {
     // Generate a wallet using Web Crypto API as fallback
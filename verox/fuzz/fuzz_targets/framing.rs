@@ -0,0 +1,31 @@
+//! Feeds arbitrary bytes through the host's read path: framing, then message
+//! parsing. Nothing may panic, oversized frames must be skipped without being
+//! buffered, and any message text must survive chunking and reassembly.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use serde_json::json;
+use verox::framing::{self, Frame};
+use verox::protocol::{ChunkFrame, Message};
+
+/// Small enough that the fuzzer reaches the oversized path
+const LIMIT: usize = 256;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = data;
+    while let Ok(Some(frame)) = framing::read_frame(&mut reader, LIMIT) {
+        let Frame::Message(message) = frame else { continue };
+        assert!(message.len() <= LIMIT);
+        let _ = Message::parse(&message);
+
+        let text = String::from_utf8_lossy(&message);
+        if let Some(frames) = framing::chunk(&json!(1), &text, 64) {
+            let parsed: Vec<ChunkFrame> = frames
+                .iter()
+                .inspect(|frame| assert!(frame.len() <= 64))
+                .map(|frame| serde_json::from_slice(frame).expect("chunk frames parse"))
+                .collect();
+            assert_eq!(framing::reassemble(&parsed).unwrap_or_default(), text);
+        }
+    }
+});
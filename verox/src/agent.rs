@@ -0,0 +1,587 @@
+//! Session agent holding unlocked keys
+//!
+//! `verox agent` keeps keys in memory once they have been unlocked, so the CLI and the
+//! native host stop asking for the passphrase on every command, much as ssh-agent does
+//! for SSH keys. It listens on `agent.sock` in the data directory, a Unix socket only
+//! its owner may open, and answers one line of JSON per connection. Keys go in but
+//! never come back out: clients ask the agent to sign a transaction, a message or typed
+//! data with a key it holds, and get the signature. Any client that can open the socket
+//! can have it sign, so the socket's permissions are the boundary, just as the keystore
+//! files' are for the encrypted keys.
+//!
+//! The agent forgets every key once none has been used for the idle timeout. It forgets
+//! a key once that key was added longer ago than the lifetime, and it forgets them all
+//! on `lock`. When the last key goes, connected pages are told the wallet is locked, as
+//! they are for `verox lock`.
+
+use ethers::signers::{LocalWallet, Signer};
+use ethers::types::transaction::eip712::TypedData;
+use ethers::types::{Address, Bytes, Signature};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::error::{Result, VeroxError};
+use crate::events;
+use crate::framing;
+use crate::message;
+use crate::transaction::{self, SignedTransaction, TxParams};
+use crate::typed_data;
+use crate::wallet::address_string;
+
+pub const AGENT_SOCKET: &str = "agent.sock";
+
+/// Forget the keys after this long without a request for one
+pub const DEFAULT_IDLE_TIMEOUT_SECS: u64 = 15 * 60;
+
+/// Forget a key this long after it was added, however busy the agent is
+pub const DEFAULT_LIFETIME_SECS: u64 = 8 * 60 * 60;
+
+/// Longest idle timeout or lifetime the agent takes
+pub const MAX_TIMEOUT_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// How long either side waits on the other before giving up on a connection
+const IO_TIMEOUT_SECS: u64 = 5;
+
+/// Longest line read, the native host's message limit, as its transactions and typed
+/// data are passed on to be signed
+const MAX_REQUEST_BYTES: u64 = framing::MAX_REQUEST_BYTES as u64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AgentConfig {
+    pub idle_timeout: Duration,
+    pub lifetime: Duration,
+}
+
+impl Default for AgentConfig {
+    fn default() -> Self {
+        AgentConfig {
+            idle_timeout: Duration::from_secs(DEFAULT_IDLE_TIMEOUT_SECS),
+            lifetime: Duration::from_secs(DEFAULT_LIFETIME_SECS),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum AgentRequest {
+    /// Hold a private key, as hex
+    Add { key: String },
+    /// Sign a complete transaction with the key for `address`
+    SignTransaction { address: Address, transaction: Box<TxParams> },
+    /// Sign a message with the EIP-191 prefix
+    SignMessage { address: Address, message: Bytes },
+    /// Sign the EIP-712 digest of typed data
+    SignTypedData { address: Address, typed_data: Box<TypedData> },
+    Lock,
+    Status,
+    Stop,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AgentResponse {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// `VeroxError::code` of the failure
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+}
+
+impl AgentResponse {
+    fn ok(data: Option<Value>) -> Self {
+        AgentResponse { ok: true, data, ..Default::default() }
+    }
+
+    fn error(e: VeroxError) -> Self {
+        AgentResponse {
+            ok: false,
+            error: Some(e.to_string()),
+            code: Some(e.code().to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentStatus {
+    /// Accounts whose keys the agent holds
+    pub accounts: Vec<String>,
+    /// Seconds until the keys are forgotten unless one is used
+    pub idle_expires_in: Option<u64>,
+    /// Seconds until the oldest key is forgotten regardless
+    pub expires_in: Option<u64>,
+}
+
+struct Entry {
+    wallet: LocalWallet,
+    added_at: Instant,
+}
+
+/// The keys the agent holds, and when each has to go
+pub struct Keyring {
+    config: AgentConfig,
+    keys: HashMap<Address, Entry>,
+    last_used: Instant,
+}
+
+impl Keyring {
+    pub fn new(config: AgentConfig, now: Instant) -> Self {
+        Keyring { config, keys: HashMap::new(), last_used: now }
+    }
+
+    pub fn add(&mut self, wallet: LocalWallet, now: Instant) {
+        self.expire(now);
+        self.keys.insert(wallet.address(), Entry { wallet, added_at: now });
+        self.last_used = now;
+    }
+
+    /// The key for `address` if it hasn't expired; using it restarts the idle timeout
+    pub fn get(&mut self, address: Address, now: Instant) -> Option<&LocalWallet> {
+        self.expire(now);
+        let entry = self.keys.get(&address)?;
+        self.last_used = now;
+        Some(&entry.wallet)
+    }
+
+    /// Forget expired keys. Returns whether that left the keyring empty.
+    pub fn expire(&mut self, now: Instant) -> bool {
+        if self.keys.is_empty() {
+            return false;
+        }
+        if now.duration_since(self.last_used) >= self.config.idle_timeout {
+            self.keys.clear();
+        }
+        let lifetime = self.config.lifetime;
+        self.keys.retain(|_, entry| now.duration_since(entry.added_at) < lifetime);
+        self.keys.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.keys.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn status(&self, now: Instant) -> AgentStatus {
+        // A deadline too far out for an `Instant` is as good as never
+        let remaining = |since: Instant, timeout: Duration| {
+            since.checked_add(timeout).map_or(u64::MAX, |deadline| deadline.saturating_duration_since(now).as_secs())
+        };
+        let mut accounts: Vec<String> = self.keys.keys().map(|address| address_string(*address)).collect();
+        accounts.sort();
+        let oldest = self.keys.values().map(|entry| entry.added_at).min();
+        AgentStatus {
+            accounts,
+            idle_expires_in: oldest.map(|_| remaining(self.last_used, self.config.idle_timeout)),
+            expires_in: oldest.map(|added_at| remaining(added_at, self.config.lifetime)),
+        }
+    }
+
+    /// Answer a request other than `stop`
+    fn answer(&mut self, request: AgentRequest, dir: &Path, now: Instant) -> AgentResponse {
+        // Expiring here rather than on the next tick still has to lock
+        if self.expire(now) {
+            let _ = events::set_locked(dir, true);
+        }
+        match request {
+            AgentRequest::Add { key } => match key.trim_start_matches("0x").parse::<LocalWallet>() {
+                Ok(wallet) => {
+                    let address = wallet.address();
+                    self.add(wallet, now);
+                    // Pages see the accounts again
+                    if let Err(e) = events::set_locked(dir, false) {
+                        eprintln!("Could not unlock for connected pages: {}", e);
+                    }
+                    AgentResponse::ok(Some(json!({ "address": address_string(address) })))
+                }
+                Err(_) => AgentResponse::error(VeroxError::InvalidArgument("invalid private key".to_string())),
+            },
+            AgentRequest::SignTransaction { address, transaction } => {
+                self.sign(address, now, |wallet| transaction::sign_transaction(wallet, &transaction).map(|signed| json!(signed)))
+            }
+            AgentRequest::SignMessage { address, message } => {
+                self.sign(address, now, |wallet| message::sign_message(wallet, &message).map(|signature| json!(signature)))
+            }
+            AgentRequest::SignTypedData { address, typed_data } => self.sign(address, now, |wallet| {
+                typed_data::sign_typed_data(wallet, &typed_data).map(|signature| json!(signature))
+            }),
+            AgentRequest::Lock => {
+                self.clear();
+                match events::set_locked(dir, true) {
+                    Ok(()) => AgentResponse::ok(None),
+                    Err(e) => AgentResponse::error(e),
+                }
+            }
+            AgentRequest::Status => AgentResponse::ok(serde_json::to_value(self.status(now)).ok()),
+            AgentRequest::Stop => AgentResponse::ok(None),
+        }
+    }
+
+    /// Sign with the key for `address`, or answer `Locked` if it isn't held
+    fn sign(&mut self, address: Address, now: Instant, sign: impl FnOnce(&LocalWallet) -> Result<Value>) -> AgentResponse {
+        match self.get(address, now).map(sign) {
+            Some(Ok(data)) => AgentResponse::ok(Some(data)),
+            Some(Err(e)) => AgentResponse::error(e),
+            None => AgentResponse::error(VeroxError::Locked),
+        }
+    }
+}
+
+/// An unlocked account: a key decrypted in this process, or one the agent holds and
+/// signs with on request
+#[derive(Debug, Clone)]
+pub enum UnlockedAccount {
+    Local(LocalWallet),
+    Agent { dir: PathBuf, address: Address },
+}
+
+impl UnlockedAccount {
+    pub fn address(&self) -> Address {
+        match self {
+            UnlockedAccount::Local(wallet) => wallet.address(),
+            UnlockedAccount::Agent { address, .. } => *address,
+        }
+    }
+
+    pub fn sign_transaction(&self, params: &TxParams) -> Result<SignedTransaction> {
+        match self {
+            UnlockedAccount::Local(wallet) => transaction::sign_transaction(wallet, params),
+            UnlockedAccount::Agent { dir, address } => {
+                let request = AgentRequest::SignTransaction { address: *address, transaction: Box::new(params.clone()) };
+                signed(dir, &request)
+            }
+        }
+    }
+
+    /// Sign `message` with the EIP-191 prefix
+    pub fn sign_message(&self, message: &[u8]) -> Result<Signature> {
+        match self {
+            UnlockedAccount::Local(wallet) => message::sign_message(wallet, message),
+            UnlockedAccount::Agent { dir, address } => {
+                signed(dir, &AgentRequest::SignMessage { address: *address, message: message.to_vec().into() })
+            }
+        }
+    }
+
+    /// Sign the EIP-712 digest of `typed_data`
+    pub fn sign_typed_data(&self, typed_data: &TypedData) -> Result<Signature> {
+        match self {
+            UnlockedAccount::Local(wallet) => typed_data::sign_typed_data(wallet, typed_data),
+            UnlockedAccount::Agent { dir, address } => {
+                let request = AgentRequest::SignTypedData { address: *address, typed_data: Box::new(typed_data.clone()) };
+                signed(dir, &request)
+            }
+        }
+    }
+}
+
+impl From<LocalWallet> for UnlockedAccount {
+    fn from(wallet: LocalWallet) -> Self {
+        UnlockedAccount::Local(wallet)
+    }
+}
+
+pub fn socket_path(dir: &Path) -> PathBuf {
+    dir.join(AGENT_SOCKET)
+}
+
+/// Run the agent on `dir`'s socket until a `stop` request. Fails if another agent
+/// is already listening there.
+#[cfg(unix)]
+pub fn serve(dir: &Path, config: AgentConfig) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex, PoisonError};
+    use std::thread;
+
+    crate::utils::create_private_dir(dir)?;
+    let path = socket_path(dir);
+    if UnixStream::connect(&path).is_ok() {
+        return Err(VeroxError::InvalidArgument(format!("an agent is already listening on {}", path.display())));
+    }
+    // Left behind by an agent that didn't stop cleanly
+    let _ = std::fs::remove_file(&path);
+    // The directory is private, so nobody else can connect before the chmod
+    let listener = UnixListener::bind(&path)?;
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+
+    let keyring = Arc::new(Mutex::new(Keyring::new(config, Instant::now())));
+    let stopped = Arc::new(AtomicBool::new(false));
+
+    let timer = (keyring.clone(), stopped.clone(), dir.to_path_buf());
+    thread::spawn(move || {
+        let (keyring, stopped, dir) = timer;
+        while !stopped.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_secs(events::WATCH_INTERVAL_SECS));
+            let mut keyring = keyring.lock().unwrap_or_else(PoisonError::into_inner);
+            if keyring.is_empty() {
+                continue;
+            }
+            // Locked some other way, e.g. by a host that predates the agent
+            if events::is_locked(&dir) {
+                keyring.clear();
+            } else if keyring.expire(Instant::now()) {
+                let _ = events::set_locked(&dir, true);
+            }
+        }
+    });
+
+    for stream in listener.incoming() {
+        if stopped.load(Ordering::SeqCst) {
+            break;
+        }
+        let Ok(mut stream) = stream else { continue };
+        let (keyring, stopped, dir, path) = (keyring.clone(), stopped.clone(), dir.to_path_buf(), path.clone());
+        thread::spawn(move || {
+            let timeout = Some(Duration::from_secs(IO_TIMEOUT_SECS));
+            let _ = stream.set_read_timeout(timeout);
+            let _ = stream.set_write_timeout(timeout);
+            let request = match read_line(&mut stream) {
+                Ok(line) => serde_json::from_str::<AgentRequest>(&line).map_err(VeroxError::from),
+                Err(e) => Err(e.into()),
+            };
+            let response = match request {
+                Ok(AgentRequest::Stop) => {
+                    keyring.lock().unwrap_or_else(PoisonError::into_inner).clear();
+                    // Reply first, the process may exit as soon as the accept loop ends
+                    let _ = write_line(&mut stream, &AgentResponse::ok(None));
+                    stopped.store(true, Ordering::SeqCst);
+                    // Wake the accept loop so it sees the flag
+                    let _ = UnixStream::connect(&path);
+                    return;
+                }
+                Ok(request) => keyring
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .answer(request, &dir, Instant::now()),
+                Err(e) => AgentResponse::error(e),
+            };
+            let _ = write_line(&mut stream, &response);
+        });
+    }
+    let _ = std::fs::remove_file(&path);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn serve(_dir: &Path, _config: AgentConfig) -> Result<()> {
+    Err(VeroxError::InvalidArgument("the agent needs Unix domain sockets".to_string()))
+}
+
+#[cfg(unix)]
+fn connect(dir: &Path) -> io::Result<std::os::unix::net::UnixStream> {
+    let stream = std::os::unix::net::UnixStream::connect(socket_path(dir))?;
+    let timeout = Some(Duration::from_secs(IO_TIMEOUT_SECS));
+    stream.set_read_timeout(timeout)?;
+    stream.set_write_timeout(timeout)?;
+    Ok(stream)
+}
+
+#[cfg(not(unix))]
+fn connect(_dir: &Path) -> io::Result<std::fs::File> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "the agent needs Unix domain sockets"))
+}
+
+fn read_line(stream: &mut impl Read) -> io::Result<String> {
+    let mut line = String::new();
+    BufReader::new(stream.take(MAX_REQUEST_BYTES)).read_line(&mut line)?;
+    Ok(line)
+}
+
+fn write_line<T: Serialize>(stream: &mut impl Write, message: &T) -> io::Result<()> {
+    let mut line = serde_json::to_vec(message)?;
+    line.push(b'\n');
+    stream.write_all(&line)
+}
+
+/// Send one request, or `None` if no agent is running
+fn call(dir: &Path, request: &AgentRequest) -> Result<Option<Value>> {
+    let mut stream = match connect(dir) {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused | io::ErrorKind::Unsupported) => {
+            return Ok(None)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    write_line(&mut stream, request)?;
+    let response: AgentResponse = serde_json::from_str(&read_line(&mut stream)?)?;
+    if response.ok {
+        return Ok(Some(response.data.unwrap_or(Value::Null)));
+    }
+    let message = response.error.unwrap_or_default();
+    Err(match response.code.as_deref() {
+        Some("WALLET_LOCKED") => VeroxError::Locked,
+        _ => VeroxError::InvalidArgument(format!("agent: {}", message)),
+    })
+}
+
+/// Give the agent an unlocked key. Returns whether one was running to take it.
+pub fn add(dir: &Path, wallet: &LocalWallet) -> Result<bool> {
+    let key = hex::encode(wallet.signer().to_bytes());
+    Ok(call(dir, &AgentRequest::Add { key })?.is_some())
+}
+
+/// `address` as an account the agent signs for, or `Locked` if no agent holds its key
+pub fn account(dir: &Path, address: Address) -> Result<UnlockedAccount> {
+    let held = status(dir)?.is_some_and(|status| status.accounts.contains(&address_string(address)));
+    if !held {
+        return Err(VeroxError::Locked);
+    }
+    Ok(UnlockedAccount::Agent { dir: dir.to_path_buf(), address })
+}
+
+/// What the agent signed, or `Locked` if no agent is running
+fn signed<T: serde::de::DeserializeOwned>(dir: &Path, request: &AgentRequest) -> Result<T> {
+    let data = call(dir, request)?.ok_or(VeroxError::Locked)?;
+    serde_json::from_value(data).map_err(VeroxError::from)
+}
+
+/// Lock the wallet: pages lose their accounts and the agent, if running, forgets
+/// every key. Returns whether an agent was running.
+pub fn lock(dir: &Path) -> Result<bool> {
+    events::set_locked(dir, true)?;
+    Ok(call(dir, &AgentRequest::Lock)?.is_some())
+}
+
+/// What the agent holds, or `None` if no agent is running
+pub fn status(dir: &Path) -> Result<Option<AgentStatus>> {
+    call(dir, &AgentRequest::Status)?
+        .map(|data| serde_json::from_value(data).map_err(VeroxError::from))
+        .transpose()
+}
+
+/// Stop the agent. Returns whether one was running.
+pub fn stop(dir: &Path) -> Result<bool> {
+    Ok(call(dir, &AgentRequest::Stop)?.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+    fn config() -> AgentConfig {
+        AgentConfig { idle_timeout: Duration::from_secs(60), lifetime: Duration::from_secs(600) }
+    }
+
+    #[test]
+    fn test_keyring_timeouts() {
+        let start = Instant::now();
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        let wallet: LocalWallet = KEY.parse().unwrap();
+        let address = wallet.address();
+
+        let mut keyring = Keyring::new(config(), start);
+        keyring.add(wallet.clone(), start);
+        assert_eq!(keyring.status(at(10)).idle_expires_in, Some(50));
+        // Each use restarts the idle timeout...
+        for secs in (50..=550).step_by(50) {
+            assert!(keyring.get(address, at(secs)).is_some(), "{}", secs);
+        }
+        // ...but not the lifetime
+        assert!(keyring.get(address, at(600)).is_none());
+        assert!(keyring.is_empty());
+
+        keyring.add(wallet, at(1000));
+        assert!(!keyring.expire(at(1059)));
+        assert!(keyring.expire(at(1060)));
+        assert!(keyring.get(address, at(1060)).is_none());
+        // Nothing left to expire is not a fresh lock
+        assert!(!keyring.expire(at(2000)));
+
+        // Timeouts past what an `Instant` can hold never expire rather than panic
+        let forever = AgentConfig { idle_timeout: Duration::MAX, lifetime: Duration::MAX };
+        let mut keyring = Keyring::new(forever, start);
+        keyring.add(KEY.parse().unwrap(), start);
+        let status = keyring.status(at(10));
+        assert_eq!((status.idle_expires_in, status.expires_in), (Some(u64::MAX), Some(u64::MAX)));
+        assert!(keyring.get(address, at(1_000_000)).is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_agent() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_path_buf();
+        assert!(status(&path).unwrap().is_none());
+        assert!(matches!(account(&path, Address::zero()), Err(VeroxError::Locked)));
+
+        let server = {
+            let path = path.clone();
+            std::thread::spawn(move || serve(&path, config()))
+        };
+        while status(&path).unwrap().is_none() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let mode = std::fs::metadata(socket_path(&path)).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert!(serve(&path, config()).is_err());
+
+        events::set_locked(&path, true).unwrap();
+        let original: LocalWallet = KEY.parse().unwrap();
+        assert!(add(&path, &original).unwrap());
+        assert!(!events::is_locked(&path));
+        assert_eq!(status(&path).unwrap().unwrap().accounts, [address_string(original.address())]);
+
+        // The agent signs as the key itself would
+        let held = account(&path, original.address()).unwrap();
+        let local = UnlockedAccount::from(original.clone());
+        let params = TxParams {
+            tx_type: transaction::TxType::Eip1559,
+            chain_id: 1,
+            nonce: 7.into(),
+            to: Some(Address::repeat_byte(0x11)),
+            value: 1_000.into(),
+            data: vec![0xab, 0xcd].into(),
+            gas_limit: 21_000.into(),
+            gas_price: 0.into(),
+            max_fee_per_gas: 30_000_000_000u64.into(),
+            max_priority_fee_per_gas: 1_000_000_000u64.into(),
+            access_list: Default::default(),
+        };
+        let signed = held.sign_transaction(&params).unwrap();
+        assert_eq!(signed.raw, local.sign_transaction(&params).unwrap().raw);
+        assert_eq!(signed.from, original.address());
+        assert_eq!(held.sign_message(b"hello").unwrap(), local.sign_message(b"hello").unwrap());
+        let typed_data: TypedData = serde_json::from_value(json!({
+            "types": {
+                "EIP712Domain": [{ "name": "name", "type": "string" }],
+                "Note": [{ "name": "text", "type": "string" }]
+            },
+            "primaryType": "Note",
+            "domain": { "name": "Verox" },
+            "message": { "text": "hi" }
+        }))
+        .unwrap();
+        assert_eq!(held.sign_typed_data(&typed_data).unwrap(), local.sign_typed_data(&typed_data).unwrap());
+
+        // There is no way to ask for the key
+        let mut stream = connect(&path).unwrap();
+        write_line(&mut stream, &json!({ "op": "key", "address": address_string(original.address()) })).unwrap();
+        let response: AgentResponse = serde_json::from_str(&read_line(&mut stream).unwrap()).unwrap();
+        assert!(!response.ok);
+        assert!(response.data.is_none());
+
+        assert!(lock(&path).unwrap());
+        assert!(events::is_locked(&path));
+        assert!(matches!(account(&path, original.address()), Err(VeroxError::Locked)));
+        assert!(matches!(held.sign_message(b"hello"), Err(VeroxError::Locked)));
+
+        assert!(stop(&path).unwrap());
+        server.join().unwrap().unwrap();
+        assert!(!socket_path(&path).exists());
+        assert!(!stop(&path).unwrap());
+    }
+}
@@ -0,0 +1,136 @@
+//! Tokens the user asked to watch
+//!
+//! `assets.json` in the data directory lists ERC-20 tokens per chain, added by a dapp
+//! through `wallet_watchAsset` once the user approved it. Only what the dapp claimed
+//! is stored; balances are read from the chain when shown.
+
+use ethers::types::Address;
+use serde::{Deserialize, Serialize, Serializer};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, VeroxError};
+use crate::utils;
+use crate::wallet::address_string;
+
+pub const ASSETS_FILE: &str = "assets.json";
+
+/// Longest symbol accepted, as in EIP-747
+pub const MAX_SYMBOL_LEN: usize = 11;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchedAsset {
+    pub chain_id: u64,
+    #[serde(serialize_with = "checksummed")]
+    pub address: Address,
+    pub symbol: String,
+    pub decimals: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+impl WatchedAsset {
+    pub fn validate(&self) -> Result<()> {
+        if self.symbol.trim().is_empty() || self.symbol.chars().count() > MAX_SYMBOL_LEN {
+            return Err(VeroxError::InvalidArgument(format!(
+                "token symbol must be 1 to {} characters",
+                MAX_SYMBOL_LEN
+            )));
+        }
+        if self.decimals > 36 {
+            return Err(VeroxError::InvalidArgument("token decimals must be at most 36".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AssetList {
+    #[serde(default)]
+    pub assets: Vec<WatchedAsset>,
+}
+
+impl AssetList {
+    pub fn load(dir: &Path) -> Result<Self> {
+        match fs::read_to_string(assets_path(dir)) {
+            Ok(json) => Ok(serde_json::from_str(&json)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(AssetList::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        utils::create_private_dir(dir)?;
+        let path = assets_path(dir);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Add `asset`, replacing an entry for the same token. Returns whether it was new.
+    pub fn add(&mut self, asset: WatchedAsset) -> Result<bool> {
+        asset.validate()?;
+        match self
+            .assets
+            .iter_mut()
+            .find(|a| a.chain_id == asset.chain_id && a.address == asset.address)
+        {
+            Some(existing) => {
+                *existing = asset;
+                Ok(false)
+            }
+            None => {
+                self.assets.push(asset);
+                Ok(true)
+            }
+        }
+    }
+
+    pub fn for_chain(&self, chain_id: u64) -> impl Iterator<Item = &WatchedAsset> {
+        self.assets.iter().filter(move |a| a.chain_id == chain_id)
+    }
+}
+
+fn checksummed<S: Serializer>(address: &Address, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&address_string(*address))
+}
+
+pub fn assets_path(dir: &Path) -> PathBuf {
+    dir.join(ASSETS_FILE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(chain_id: u64, symbol: &str) -> WatchedAsset {
+        WatchedAsset {
+            chain_id,
+            address: Address::repeat_byte(0xab),
+            symbol: symbol.to_string(),
+            decimals: 6,
+            image: None,
+        }
+    }
+
+    #[test]
+    fn test_add_and_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut assets = AssetList::load(dir.path()).unwrap();
+        assert!(assets.add(token(1, "USDC")).unwrap());
+        assert!(assets.add(token(10, "USDC")).unwrap());
+        // Watching the same token again updates it
+        assert!(!assets.add(token(1, "USDC.e")).unwrap());
+        assert!(assets.add(token(1, "")).is_err());
+        assert!(assets.add(token(1, "TWELVECHARSX")).is_err());
+        assets.save(dir.path()).unwrap();
+
+        let reloaded = AssetList::load(dir.path()).unwrap();
+        assert_eq!(reloaded.assets.len(), 2);
+        assert_eq!(reloaded.for_chain(1).map(|a| a.symbol.as_str()).collect::<Vec<_>>(), ["USDC.e"]);
+        let json = std::fs::read_to_string(assets_path(dir.path())).unwrap();
+        assert!(json.contains("0xABaBaBaBABabABabAbAbABAbABabababaBaBABaB"));
+    }
+}
@@ -7,6 +7,11 @@
 //!
 //! The module automatically detects the current platform and uses the appropriate
 //! biometric authentication method.
+//!
+//! Verification only proves the user is present: the registered secret is not a key,
+//! so unlocking a keystore still takes its passphrase.
+//!
+//! Prompts and progress go to stderr, as the native host's stdout carries its messages.
 
 #[cfg(target_os = "macos")]
 use std::time::Duration;
@@ -20,6 +25,8 @@ use std::os::raw::{c_char, c_int};
 #[cfg(target_os = "windows")]
 mod windows_hello;
 
+use crate::error::VeroxError;
+
 #[cfg(target_os = "macos")]
 const SECRET_DATA: &str = "verox_biometric_authenticated_secret_key_v2";
 
@@ -97,14 +104,23 @@ fn prompt_touch_id(reason: &str) -> Result<bool, String> {
     }
 }
 
+/// File standing in for the Keychain item, kept in the data directory so the CLI
+/// and the native host find the same one
+#[cfg(target_os = "macos")]
+fn secret_file() -> Result<std::path::PathBuf, String> {
+    const TEMP_SECRET_FILE: &str = ".verox_biometric_keychain";
+    crate::utils::ensure_data_dir()
+        .map(|dir| dir.join(TEMP_SECRET_FILE))
+        .map_err(|e| format!("Failed to locate data directory: {}", e))
+}
+
 /// Store secret in macOS Keychain
 #[cfg(target_os = "macos")]
 fn store_secret_in_keychain(secret: &str) -> Result<(), String> {
     // For now, use file-based storage until we resolve security_framework API
     // In production, you'd use Keychain Services directly
     use std::fs;
-    const TEMP_SECRET_FILE: &str = ".verox_biometric_keychain";
-    match fs::write(TEMP_SECRET_FILE, secret) {
+    match fs::write(secret_file()?, secret) {
         Ok(_) => Ok(()),
         Err(e) => Err(format!("Failed to store secret: {}", e)),
     }
@@ -114,8 +130,7 @@ fn store_secret_in_keychain(secret: &str) -> Result<(), String> {
 #[cfg(target_os = "macos")]
 fn get_secret_from_keychain() -> Result<String, String> {
     use std::fs;
-    const TEMP_SECRET_FILE: &str = ".verox_biometric_keychain";
-    match fs::read_to_string(TEMP_SECRET_FILE) {
+    match fs::read_to_string(secret_file()?) {
         Ok(secret) => Ok(secret.trim().to_string()),
         Err(e) => Err(format!("Failed to retrieve secret: {}", e)),
     }
@@ -125,9 +140,9 @@ fn get_secret_from_keychain() -> Result<String, String> {
 #[cfg(target_os = "macos")]
 fn delete_secret_from_keychain() -> Result<(), String> {
     use std::fs;
-    const TEMP_SECRET_FILE: &str = ".verox_biometric_keychain";
-    if std::path::Path::new(TEMP_SECRET_FILE).exists() {
-        match fs::remove_file(TEMP_SECRET_FILE) {
+    let secret_file = secret_file()?;
+    if secret_file.exists() {
+        match fs::remove_file(&secret_file) {
             Ok(_) => Ok(()),
             Err(e) => Err(format!("Failed to delete secret: {}", e)),
         }
@@ -137,28 +152,32 @@ fn delete_secret_from_keychain() -> Result<(), String> {
 }
 /// Register biometric authentication for the current platform
 /// This prompts the user for biometric authentication and stores a secret securely
-pub fn register() -> Result<(), String> {
+pub fn register() -> Result<(), VeroxError> {
+    register_platform().map_err(VeroxError::Biometric)
+}
+
+fn register_platform() -> Result<(), String> {
     #[cfg(target_os = "macos")]
     {
-        println!("Setting up Touch ID authentication for Verox Wallet...");
+        eprintln!("Setting up Touch ID authentication for Verox Wallet...");
         
         // First check if Touch ID is available
         if !can_evaluate_touch_id()? {
             return Err("Touch ID is not available. Please ensure Touch ID is set up in System Preferences.".to_string());
         }
         
-        println!("Please authenticate with Touch ID to register...");
+        eprintln!("Please authenticate with Touch ID to register...");
         
         // Prompt for Touch ID
         match prompt_touch_id("Register your biometric authentication for Verox Wallet") {
             Ok(true) => {
-                println!("✓ Touch ID authentication successful");
+                eprintln!("✓ Touch ID authentication successful");
                 
                 // Store secret in keychain
                 match store_secret_in_keychain(SECRET_DATA) {
                     Ok(_) => {
-                        println!("Biometric authentication registered successfully!");
-                        println!("   Your Touch ID is now linked to your Verox Wallet.");
+                        eprintln!("Biometric authentication registered successfully!");
+                        eprintln!("   Your Touch ID is now linked to your Verox Wallet.");
                         Ok(())
                     }
                     Err(e) => Err(format!("Failed to store biometric secret: {}", e)),
@@ -168,7 +187,7 @@ pub fn register() -> Result<(), String> {
                 Err("Touch ID authentication was cancelled".to_string())
             }
             Err(e) => {
-                println!("Touch ID registration failed: {}", e);
+                eprintln!("Touch ID registration failed: {}", e);
                 Err(e)
             }
         }
@@ -187,10 +206,14 @@ pub fn register() -> Result<(), String> {
 
 /// Verify biometric authentication for the current platform
 /// This prompts for biometric authentication and validates the stored secret
-pub fn verify() -> Result<bool, String> {
+pub fn verify() -> Result<bool, VeroxError> {
+    verify_platform().map_err(VeroxError::Biometric)
+}
+
+fn verify_platform() -> Result<bool, String> {
     #[cfg(target_os = "macos")]
     {
-        println!("Verifying Touch ID authentication...");
+        eprintln!("Verifying Touch ID authentication...");
         
         // Check if Touch ID is available
         if !can_evaluate_touch_id()? {
@@ -201,36 +224,36 @@ pub fn verify() -> Result<bool, String> {
         match get_secret_from_keychain() {
             Ok(_) => {
                 // Secret exists, proceed with Touch ID verification
-                println!("Please authenticate with Touch ID...");
+                eprintln!("Please authenticate with Touch ID...");
                 
                 // Prompt for Touch ID
                 match prompt_touch_id("Authenticate with Touch ID to access your Verox Wallet") {
                     Ok(true) => {
-                        println!("✓ Touch ID authentication successful");
+                        eprintln!("✓ Touch ID authentication successful");
                         
                         // Verify the secret
                         match get_secret_from_keychain() {
                             Ok(stored_secret) => {
                                 if stored_secret == SECRET_DATA {
-                                    println!("Biometric verification successful!");
+                                    eprintln!("Biometric verification successful!");
                                     Ok(true)
                                 } else {
-                                    println!("Keychain verification failed: Invalid secret");
+                                    eprintln!("Keychain verification failed: Invalid secret");
                                     Ok(false)
                                 }
                             }
                             Err(e) => {
-                                println!("Secret verification failed: {}", e);
+                                eprintln!("Secret verification failed: {}", e);
                                 Ok(false)
                             }
                         }
                     }
                     Ok(false) => {
-                        println!("Touch ID authentication was cancelled");
+                        eprintln!("Touch ID authentication was cancelled");
                         Ok(false)
                     }
                     Err(e) => {
-                        println!("Touch ID verification failed: {}", e);
+                        eprintln!("Touch ID verification failed: {}", e);
                         Err(e)
                     }
                 }
@@ -253,19 +276,22 @@ pub fn verify() -> Result<bool, String> {
 }
 
 /// Clean up - remove biometric registration for the current platform
-#[allow(dead_code)]
-pub fn unregister() -> Result<(), String> {
+pub fn unregister() -> Result<(), VeroxError> {
+    unregister_platform().map_err(VeroxError::Biometric)
+}
+
+fn unregister_platform() -> Result<(), String> {
     #[cfg(target_os = "macos")]
     {
-        println!("Removing Touch ID registration...");
+        eprintln!("Removing Touch ID registration...");
         
         match delete_secret_from_keychain() {
             Ok(_) => {
-                println!("Touch ID registration removed successfully!");
+                eprintln!("Touch ID registration removed successfully!");
                 Ok(())
             }
             Err(e) => {
-                println!("Failed to remove Touch ID registration: {}", e);
+                eprintln!("Failed to remove Touch ID registration: {}", e);
                 Err(e)
             }
         }
@@ -283,7 +309,11 @@ pub fn unregister() -> Result<(), String> {
 }
 
 /// Check if biometric authentication is available on the current platform
-pub fn is_available() -> Result<bool, String> {
+pub fn is_available() -> Result<bool, VeroxError> {
+    is_available_platform().map_err(VeroxError::Biometric)
+}
+
+fn is_available_platform() -> Result<bool, String> {
     #[cfg(target_os = "macos")]
     {
         can_evaluate_touch_id()
@@ -323,6 +353,7 @@ mod tests {
     use super::*;
     
     #[test]
+    #[cfg(any(target_os = "macos", target_os = "windows"))]
     fn test_register_and_verify() {
         // Test basic registration and verification
         let _ = unregister(); // Clean up first
@@ -1,13 +1,38 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use crate::wallet;
-use crate::biometric;
+use verox::wallet;
+use verox::biometric;
+use verox::crypto::{self, Argon2Params, KdfProfile};
+use verox::error::{Result, VeroxError};
+use verox::mnemonic::{self, DerivationScheme};
+use verox::transaction::TxRequest;
+use verox::typed_data;
+use verox::network::{self, NativeCurrency, Network};
+use verox::provider::{self, RpcProvider};
+use verox::fees::{self, FeeSpeed};
+use verox::pending::{self, TrackedTx, TxStore};
+use verox::nonce::NonceManager;
+use verox::replace::{self, Replacement};
+use verox::permissions::{self, Grant, PermissionStore};
+use verox::agent::{self, AgentConfig};
+use verox::native_manifest::{self, Browser, HostManifest, Scope};
+use verox::utils;
+use chrono::Utc;
+use ethers::types::{Address, H256, U256};
+use ethers::utils::{format_ether, parse_ether, parse_units};
 
 /// Command-line interface for Verox
 #[derive(Parser, Debug)]
 #[command(name = "Verox")]
 #[command(about = "Biometric Wallet Locker CLI", long_about = None)]
 pub struct Cli {
+    /// Data directory (defaults to $VEROX_HOME, then the platform data directory)
+    #[arg(long, global = true)]
+    pub home: Option<PathBuf>,
     #[command(subcommand)]
     pub command: Commands,
 }
@@ -19,17 +44,468 @@ pub enum Commands {
         /// Optional passphrase for extra security
         #[arg(short, long)]
         passphrase: Option<String>,
+        /// Number of recovery phrase words (12 or 24)
+        #[arg(long, default_value_t = 12)]
+        words: usize,
+        /// Prompt for a BIP-39 passphrase to protect the recovery phrase
+        #[arg(long)]
+        bip39_passphrase: bool,
+        #[command(flatten)]
+        kdf: KdfArgs,
+    },
+    /// Restore a wallet from a BIP-39 recovery phrase
+    RestoreWallet {
+        /// Prompt for the BIP-39 passphrase used when the phrase was created
+        #[arg(long)]
+        bip39_passphrase: bool,
+        #[command(flatten)]
+        kdf: KdfArgs,
     },
     /// Unlock an existing wallet
     UnlockWallet {
         /// Path to wallet file
         #[arg(short, long)]
         file: Option<String>,
+        /// Account address or label
+        #[arg(short, long)]
+        account: Option<String>,
+    },
+    /// Derive another account from a wallet's recovery phrase
+    AddAccount {
+        /// Path to wallet file
+        #[arg(short, long)]
+        file: Option<String>,
+        /// Account address or label
+        #[arg(short, long)]
+        account: Option<String>,
+        /// Derivation scheme: bip44, ledger-live or a custom path (may contain {index})
+        #[arg(long, default_value = "bip44")]
+        scheme: String,
+        /// Account index (defaults to the next unused one)
+        #[arg(long)]
+        index: Option<u32>,
+        /// Label for the new account
+        #[arg(long)]
+        label: Option<String>,
+    },
+    /// List the accounts in the keystore
+    ListAccounts {
+        /// Include hidden accounts
+        #[arg(long)]
+        all: bool,
+    },
+    /// Rename an account (omit the label to clear it)
+    #[command(alias = "label-account")]
+    RenameAccount {
+        /// Account address or current label
+        address: String,
+        label: Option<String>,
+    },
+    /// Delete an account; deleting a wallet's own account deletes its keystore file
+    DeleteAccount {
+        /// Account address or label
+        address: String,
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Follow an address without its key
+    WatchAccount {
+        /// Address to watch
+        address: String,
+        /// Label for the account
+        #[arg(long)]
+        label: Option<String>,
+    },
+    /// Check that the keystore index and the keystore files agree
+    CheckKeystore {
+        /// Drop index entries without a file and index files without an entry
+        #[arg(long)]
+        fix: bool,
+    },
+    /// Hide an account from listings
+    HideAccount {
+        /// Account address or label
+        address: String,
+        /// Show the account again instead
+        #[arg(long)]
+        unhide: bool,
     },
     /// Register biometric authentication (Touch ID/Windows Hello)
     RegisterBiometric,
     /// Test biometric verification
     VerifyBiometric,
+    /// Import an Ethereum keystore v3 JSON file (geth, Foundry, MetaMask)
+    ImportKeystore {
+        /// Path to the keystore v3 JSON file
+        path: String,
+        #[command(flatten)]
+        kdf: KdfArgs,
+    },
+    /// Export a wallet as an Ethereum keystore v3 JSON file
+    ExportKeystore {
+        /// Path to wallet file
+        #[arg(short, long)]
+        file: Option<String>,
+        /// Account address or label
+        #[arg(short, long)]
+        account: Option<String>,
+        /// Directory to write the JSON file to
+        #[arg(short, long, default_value = ".")]
+        out: String,
+    },
+    /// Sign a transaction offline and print the raw transaction
+    SignTransaction {
+        /// Path to wallet file
+        #[arg(short, long)]
+        file: Option<String>,
+        /// Account address or label
+        #[arg(short, long)]
+        account: Option<String>,
+        #[command(flatten)]
+        tx: TxArgs,
+    },
+    /// Sign a transaction, broadcast it and track it until it settles
+    SendTransaction {
+        /// Path to wallet file
+        #[arg(short, long)]
+        file: Option<String>,
+        /// Account address or label
+        #[arg(short, long)]
+        account: Option<String>,
+        #[command(flatten)]
+        tx: TxArgs,
+        /// Keep polling until the transaction is confirmed, fails or is dropped
+        #[arg(short, long)]
+        wait: bool,
+    },
+    /// Show the status of sent transactions (all tracked ones by default)
+    TxStatus {
+        /// Transaction hash
+        hash: Option<String>,
+        /// Blocks deep a transaction must be to count as confirmed
+        #[arg(long, default_value_t = pending::CONFIRMATIONS)]
+        confirmations: u64,
+    },
+    /// Re-send a pending transaction with the same nonce and higher fees
+    SpeedUp {
+        /// Hash of the pending transaction
+        hash: String,
+        #[command(flatten)]
+        replace: ReplaceArgs,
+    },
+    /// Replace a pending transaction with a zero-value transfer to the sender
+    Cancel {
+        /// Hash of the pending transaction
+        hash: String,
+        #[command(flatten)]
+        replace: ReplaceArgs,
+    },
+    /// Show an account's nonces: mined, pending, the next one, gaps and stuck transactions
+    Nonce {
+        /// Account address or label
+        account: Option<String>,
+        /// Network name or chain ID (defaults to the selected network)
+        #[arg(short, long)]
+        network: Option<String>,
+    },
+    /// Sign a message with the EIP-191 prefix (personal_sign)
+    SignMessage {
+        /// Message to sign
+        message: String,
+        /// Path to wallet file
+        #[arg(short, long)]
+        file: Option<String>,
+        /// Account address or label
+        #[arg(short, long)]
+        account: Option<String>,
+        /// The message is 0x-prefixed hex bytes rather than text
+        #[arg(long)]
+        hex: bool,
+    },
+    /// Recover the address that signed a message
+    VerifyMessage {
+        /// Message that was signed
+        message: String,
+        /// 65-byte signature as hex
+        signature: String,
+        /// Check the signer is this address
+        #[arg(long)]
+        address: Option<String>,
+        /// The message is 0x-prefixed hex bytes rather than text
+        #[arg(long)]
+        hex: bool,
+    },
+    /// Show and sign EIP-712 typed data (eth_signTypedData_v4)
+    SignTypedData {
+        /// JSON file with types, primaryType, domain and message
+        path: String,
+        /// Path to wallet file
+        #[arg(short, long)]
+        file: Option<String>,
+        /// Account address or label
+        #[arg(short, long)]
+        account: Option<String>,
+        /// Sign without asking for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// List known networks
+    ListNetworks,
+    /// Add a network, or change the RPC endpoints of a known one
+    AddNetwork {
+        /// Chain ID
+        #[arg(long)]
+        chain_id: u64,
+        /// Network name
+        #[arg(long)]
+        name: String,
+        /// RPC endpoint (repeat to add fallbacks)
+        #[arg(long = "rpc-url", required = true)]
+        rpc_urls: Vec<String>,
+        /// Native currency symbol
+        #[arg(long, default_value = "ETH")]
+        symbol: String,
+        /// Block explorer base URL
+        #[arg(long)]
+        explorer_url: Option<String>,
+    },
+    /// Select the network used by default
+    UseNetwork {
+        /// Network name or chain ID
+        network: String,
+    },
+    /// Remove a user-added network
+    RemoveNetwork {
+        /// Network name or chain ID
+        network: String,
+    },
+    /// Show balance and nonce of an account (all accounts by default)
+    Balance {
+        /// Account address or label
+        account: Option<String>,
+        /// Network name or chain ID (defaults to the selected network)
+        #[arg(short, long)]
+        network: Option<String>,
+    },
+    /// Show the current gas price
+    GasPrice {
+        /// Network name or chain ID (defaults to the selected network)
+        #[arg(short, long)]
+        network: Option<String>,
+    },
+    /// Lock the wallet: connected sites lose its accounts and the agent forgets its keys
+    Lock,
+    /// Run the session agent, holding unlocked keys so commands don't ask again
+    Agent {
+        /// Forget the keys after this many seconds without use
+        #[arg(long, default_value_t = agent::DEFAULT_IDLE_TIMEOUT_SECS)]
+        idle_timeout: u64,
+        /// Forget each key this many seconds after it was unlocked
+        #[arg(long, default_value_t = agent::DEFAULT_LIFETIME_SECS)]
+        lifetime: u64,
+    },
+    /// Show which keys the agent holds and for how long
+    AgentStatus,
+    /// Stop the session agent
+    StopAgent,
+    /// List the sites allowed to use the wallet
+    ListPermissions,
+    /// Disconnect a site, or every site with --all
+    RevokePermissions {
+        /// Origin, e.g. https://app.uniswap.org
+        #[arg(required_unless_present = "all", conflicts_with = "all")]
+        origin: Option<String>,
+        #[arg(long)]
+        all: bool,
+    },
+    /// Change a wallet's passphrase, re-encrypting it with fresh KDF parameters
+    ChangePassphrase {
+        /// Path to wallet file
+        #[arg(short, long)]
+        file: Option<String>,
+        /// Account address or label
+        #[arg(short, long)]
+        account: Option<String>,
+        #[command(flatten)]
+        kdf: KdfArgs,
+    },
+    /// Benchmark Argon2id and pick parameters that take the target time
+    CalibrateKdf {
+        /// Target time per key derivation in milliseconds
+        #[arg(long, default_value_t = 500)]
+        target_ms: u64,
+        /// Upper bound on memory cost in MiB
+        #[arg(long, default_value_t = 1024)]
+        max_memory_mib: u32,
+        /// Save the result as the default for new keystores
+        #[arg(long)]
+        save: bool,
+    },
+    /// Register verox-native-host with browsers so the extension can start it (Linux)
+    InstallNativeHost {
+        /// chrome, chromium, brave or firefox; repeat for several (defaults to every
+        /// browser an extension ID was given for)
+        #[arg(short, long)]
+        browser: Vec<String>,
+        /// The extension's ID in Chrome, Chromium and Brave
+        #[arg(long)]
+        extension_id: Option<String>,
+        /// The extension's add-on ID in Firefox
+        #[arg(long)]
+        firefox_id: Option<String>,
+        /// Path to verox-native-host (defaults to the one next to this binary)
+        #[arg(long)]
+        host_path: Option<PathBuf>,
+        /// Register for every user instead of the current one
+        #[arg(long)]
+        system: bool,
+    },
+    /// Remove verox-native-host's registration from browsers
+    UninstallNativeHost {
+        /// chrome, chromium, brave or firefox; repeat for several (defaults to all)
+        #[arg(short, long)]
+        browser: Vec<String>,
+        /// Remove the registration for every user instead of the current one
+        #[arg(long)]
+        system: bool,
+    },
+    /// Show which browsers verox-native-host is registered with
+    NativeHostStatus,
+}
+
+/// Argon2id cost options shared by commands that write a keystore
+#[derive(Args, Debug)]
+pub struct KdfArgs {
+    /// KDF cost profile: interactive, moderate or paranoid
+    #[arg(long)]
+    kdf_profile: Option<String>,
+    /// Override Argon2id memory cost (KiB)
+    #[arg(long)]
+    kdf_m: Option<u32>,
+    /// Override Argon2id iterations
+    #[arg(long)]
+    kdf_t: Option<u32>,
+    /// Override Argon2id parallelism
+    #[arg(long)]
+    kdf_p: Option<u32>,
+}
+
+/// Transaction fields for `sign-transaction` and `send-transaction`
+#[derive(Args, Debug)]
+pub struct TxArgs {
+    /// Recipient (omit to deploy a contract)
+    #[arg(long)]
+    to: Option<String>,
+    /// Amount in ether
+    #[arg(long, default_value = "0")]
+    value: String,
+    /// Calldata as 0x-prefixed hex
+    #[arg(long)]
+    data: Option<String>,
+    /// Nonce (defaults to the account's next nonce on the network)
+    #[arg(long)]
+    nonce: Option<u64>,
+    /// Gas limit (estimated by the network by default)
+    #[arg(long)]
+    gas_limit: Option<u64>,
+    /// Gas price in gwei (legacy and eip2930)
+    #[arg(long)]
+    gas_price: Option<String>,
+    /// Max fee per gas in gwei (eip1559)
+    #[arg(long)]
+    max_fee: Option<String>,
+    /// Max priority fee per gas in gwei (eip1559)
+    #[arg(long)]
+    priority_fee: Option<String>,
+    /// Chain ID (defaults to the selected network)
+    #[arg(long)]
+    chain_id: Option<u64>,
+    /// Fee preset used when no fees are given: slow, normal or fast (needs the network)
+    #[arg(long, default_value = "normal")]
+    speed: String,
+    /// Sign even if the fee is far above recent medians
+    #[arg(long)]
+    allow_high_fee: bool,
+    /// Transaction type: legacy, eip2930 or eip1559 (inferred from the fee options by default)
+    #[arg(long = "type")]
+    tx_type: Option<String>,
+}
+
+/// Options shared by `speed-up` and `cancel`
+#[derive(Args, Debug)]
+pub struct ReplaceArgs {
+    /// Path to wallet file
+    #[arg(short, long)]
+    file: Option<String>,
+    /// Fee preset to match if it is above the minimum 10% bump: slow, normal or fast
+    #[arg(long, default_value = "fast")]
+    speed: String,
+    /// Send even if the fee is far above recent medians
+    #[arg(long)]
+    allow_high_fee: bool,
+}
+
+impl TxArgs {
+    /// Convert ether and gwei amounts to wei and build the request
+    fn resolve(&self) -> Result<TxRequest> {
+        let gwei = |name: &str, amount: &Option<String>| -> Result<Option<U256>> {
+            amount
+                .as_deref()
+                .map(|amount| {
+                    parse_units(amount, "gwei")
+                        .map(U256::from)
+                        .map_err(|e| VeroxError::InvalidArgument(format!("invalid {} '{}': {}", name, amount, e)))
+                })
+                .transpose()
+        };
+
+        Ok(TxRequest {
+            tx_type: self.tx_type.as_deref().map(str::parse).transpose()?,
+            from: None,
+            chain_id: self.chain_id,
+            nonce: self.nonce.map(U256::from),
+            to: self
+                .to
+                .as_deref()
+                .map(|to| to.parse().map_err(|_| VeroxError::InvalidArgument(format!("invalid address '{}'", to))))
+                .transpose()?,
+            value: parse_ether(&self.value)
+                .map_err(|e| VeroxError::InvalidArgument(format!("invalid value '{}': {}", self.value, e)))?,
+            data: self
+                .data
+                .as_deref()
+                .map(|data| data.parse().map_err(|_| VeroxError::InvalidArgument("data must be 0x-prefixed hex".to_string())))
+                .transpose()?
+                .unwrap_or_default(),
+            gas_limit: self.gas_limit.map(U256::from),
+            gas_price: gwei("gas price", &self.gas_price)?,
+            max_fee_per_gas: gwei("max fee", &self.max_fee)?,
+            max_priority_fee_per_gas: gwei("priority fee", &self.priority_fee)?,
+            access_list: None,
+        })
+    }
+}
+
+impl KdfArgs {
+    /// Start from the profile (or the configured default) and apply m/t/p overrides
+    fn resolve(&self) -> Result<Argon2Params> {
+        let mut params = match &self.kdf_profile {
+            Some(profile) => profile.parse::<KdfProfile>()?.params(),
+            None => wallet::default_kdf_params()?,
+        };
+        if let Some(m_cost) = self.kdf_m {
+            params.m_cost = m_cost;
+        }
+        if let Some(t_cost) = self.kdf_t {
+            params.t_cost = t_cost;
+        }
+        if let Some(p_cost) = self.kdf_p {
+            params.p_cost = p_cost;
+        }
+        params.validate()?;
+        Ok(params)
+    }
 }
 
 impl Cli {
@@ -40,18 +516,35 @@ impl Cli {
 
     /// Runs the CLI commands
     pub fn run(&self) {
+        // Everything below resolves the data directory through VEROX_HOME
+        if let Some(home) = &self.home {
+            std::env::set_var(utils::HOME_ENV, home);
+        }
+
         match &self.command {
-            Commands::CreateWallet { passphrase: _ } => {
+            Commands::CreateWallet { passphrase: _, words, bip39_passphrase, kdf } => {
                 println!("🔐 Creating wallet...");
-                wallet::init_wallet();
+                if let Err(e) = kdf.resolve().and_then(|kdf_params| create_wallet(*words, *bip39_passphrase, kdf_params)) {
+                    println!("❌ Failed to create wallet: {}", e);
+                }
             }
-            Commands::UnlockWallet { file: _ } => {
+            Commands::RestoreWallet { bip39_passphrase, kdf } => {
+                println!("♻️  Restoring wallet from recovery phrase...");
+                if let Err(e) = kdf.resolve().and_then(|kdf_params| restore_wallet(*bip39_passphrase, kdf_params)) {
+                    println!("❌ Failed to restore wallet: {}", e);
+                }
+            }
+            Commands::UnlockWallet { file, account } => {
+                let file = file.as_deref().map(Path::new);
+                let account = account.as_deref();
                 println!("🔓 Unlocking wallet...");
                 // First try biometric verification
                 match biometric::verify() {
                     Ok(true) => {
                         println!("✅ Biometric verification successful, unlocking wallet...");
-                        wallet::unlock_wallet();
+                        if let Err(e) = wallet::unlock_wallet(file, account) {
+                            println!("❌ Failed to unlock wallet: {}", e);
+                        }
                     }
                     Ok(false) => {
                         println!("❌ Biometric verification failed");
@@ -59,10 +552,53 @@ impl Cli {
                     Err(e) => {
                         println!("⚠️  Biometric verification error: {}", e);
                         println!("Falling back to manual unlock...");
-                        wallet::unlock_wallet();
+                        if let Err(e) = wallet::unlock_wallet(file, account) {
+                            println!("❌ Failed to unlock wallet: {}", e);
+                        }
                     }
                 }
             }
+            Commands::AddAccount { file, account, scheme, index, label } => {
+                println!("➕ Adding account...");
+                if let Err(e) = add_account(file.as_deref(), account.as_deref(), scheme, *index, label.clone()) {
+                    println!("❌ Failed to add account: {}", e);
+                }
+            }
+            Commands::ListAccounts { all } => {
+                if let Err(e) = list_accounts(*all) {
+                    println!("❌ Failed to list accounts: {}", e);
+                }
+            }
+            Commands::RenameAccount { address, label } => {
+                match wallet::set_account_label(address, label.clone()) {
+                    Ok(meta) => println!("✅ {} is now labelled '{}'", meta.address, meta.label.unwrap_or_default()),
+                    Err(e) => println!("❌ Failed to rename account: {}", e),
+                }
+            }
+            Commands::DeleteAccount { address, yes } => {
+                if let Err(e) = delete_account(address, *yes) {
+                    println!("❌ Failed to delete account: {}", e);
+                }
+            }
+            Commands::WatchAccount { address, label } => {
+                match wallet::add_watch_only(address, label.clone()) {
+                    Ok(meta) => println!("👀 Watching {}", meta.address),
+                    Err(e) => println!("❌ Failed to add account: {}", e),
+                }
+            }
+            Commands::CheckKeystore { fix } => {
+                println!("🩺 Checking keystore index...");
+                if let Err(e) = check_keystore(*fix) {
+                    println!("❌ Check failed: {}", e);
+                }
+            }
+            Commands::HideAccount { address, unhide } => {
+                match wallet::set_account_hidden(address, !unhide) {
+                    Ok(meta) if meta.hidden => println!("🙈 {} hidden", meta.address),
+                    Ok(meta) => println!("👀 {} visible again", meta.address),
+                    Err(e) => println!("❌ Failed to update account: {}", e),
+                }
+            }
             Commands::RegisterBiometric => {
                 let biometric_name = biometric::get_biometric_name();
                 println!("🔐 Setting up {} authentication...", biometric_name);
@@ -80,6 +616,873 @@ impl Cli {
                     Err(e) => println!("⚠️  {} verification error: {}", biometric_name, e),
                 }
             }
+            Commands::ImportKeystore { path, kdf } => {
+                println!("📥 Importing keystore v3 JSON...");
+                if let Err(e) = kdf.resolve().and_then(|kdf_params| import_keystore(path, kdf_params)) {
+                    println!("❌ Import failed: {}", e);
+                }
+            }
+            Commands::ExportKeystore { file, account, out } => {
+                println!("📤 Exporting keystore v3 JSON...");
+                if let Err(e) = export_keystore(file.as_deref(), account.as_deref(), out) {
+                    println!("❌ Export failed: {}", e);
+                }
+            }
+            Commands::SignTransaction { file, account, tx } => {
+                println!("✍️  Signing transaction...");
+                if let Err(e) = sign_transaction(file.as_deref().map(Path::new), account.as_deref(), tx) {
+                    println!("❌ Failed to sign transaction: {}", e);
+                }
+            }
+            Commands::SendTransaction { file, account, tx, wait } => {
+                println!("🚀 Sending transaction...");
+                if let Err(e) = send_transaction(file.as_deref().map(Path::new), account.as_deref(), tx, *wait) {
+                    println!("❌ Failed to send transaction: {}", e);
+                }
+            }
+            Commands::TxStatus { hash, confirmations } => {
+                if let Err(e) = show_tx_status(hash.as_deref(), *confirmations) {
+                    println!("❌ Failed to get transaction status: {}", e);
+                }
+            }
+            Commands::SpeedUp { hash, replace } => {
+                println!("⚡ Speeding up transaction...");
+                if let Err(e) = replace_transaction(hash, Replacement::SpeedUp, replace) {
+                    println!("❌ Failed to speed up transaction: {}", e);
+                }
+            }
+            Commands::Cancel { hash, replace } => {
+                println!("🛑 Cancelling transaction...");
+                if let Err(e) = replace_transaction(hash, Replacement::Cancel, replace) {
+                    println!("❌ Failed to cancel transaction: {}", e);
+                }
+            }
+            Commands::Nonce { account, network } => {
+                if let Err(e) = show_nonce_status(account.as_deref(), network.as_deref()) {
+                    println!("❌ Failed to get nonce status: {}", e);
+                }
+            }
+            Commands::SignMessage { message, file, account, hex } => {
+                println!("✍️  Signing message...");
+                if let Err(e) = sign_message(message, *hex, file.as_deref().map(Path::new), account.as_deref()) {
+                    println!("❌ Failed to sign message: {}", e);
+                }
+            }
+            Commands::VerifyMessage { message, signature, address, hex } => {
+                if let Err(e) = verify_message(message, signature, address.as_deref(), *hex) {
+                    println!("❌ Failed to verify message: {}", e);
+                }
+            }
+            Commands::SignTypedData { path, file, account, yes } => {
+                if let Err(e) = sign_typed_data(path, file.as_deref().map(Path::new), account.as_deref(), *yes) {
+                    println!("❌ Failed to sign typed data: {}", e);
+                }
+            }
+            Commands::ListNetworks => {
+                if let Err(e) = list_networks() {
+                    println!("❌ Failed to list networks: {}", e);
+                }
+            }
+            Commands::AddNetwork { chain_id, name, rpc_urls, symbol, explorer_url } => {
+                let network = Network {
+                    chain_id: *chain_id,
+                    name: name.clone(),
+                    rpc_urls: rpc_urls.clone(),
+                    native_currency: NativeCurrency {
+                        name: symbol.clone(),
+                        symbol: symbol.clone(),
+                        decimals: 18,
+                    },
+                    explorer_url: explorer_url.clone(),
+                };
+                let result = network::update_config(|config| config.add(network));
+                match result {
+                    Ok(()) => println!("✅ Added {} (chain {})", name, chain_id),
+                    Err(e) => println!("❌ Failed to add network: {}", e),
+                }
+            }
+            Commands::UseNetwork { network } => {
+                let result = network::update_config(|config| config.select(network));
+                match result {
+                    Ok(selected) => println!("🌐 Using {} (chain {})", selected.name, selected.chain_id),
+                    Err(e) => println!("❌ Failed to switch network: {}", e),
+                }
+            }
+            Commands::RemoveNetwork { network } => {
+                let result = network::update_config(|config| config.remove(config.find(network)?.chain_id));
+                match result {
+                    Ok(removed) => println!("🗑️  Removed {} (chain {})", removed.name, removed.chain_id),
+                    Err(e) => println!("❌ Failed to remove network: {}", e),
+                }
+            }
+            Commands::Balance { account, network } => {
+                if let Err(e) = show_balance(account.as_deref(), network.as_deref()) {
+                    println!("❌ Failed to get balance: {}", e);
+                }
+            }
+            Commands::GasPrice { network } => {
+                if let Err(e) = show_gas_price(network.as_deref()) {
+                    println!("❌ Failed to get gas price: {}", e);
+                }
+            }
+            Commands::Lock => match utils::ensure_data_dir().and_then(|dir| agent::lock(&dir)) {
+                Ok(true) => println!("🔒 Wallet locked, the agent forgot its keys"),
+                Ok(false) => println!("🔒 Wallet locked"),
+                Err(e) => println!("❌ Failed to lock wallet: {}", e),
+            },
+            Commands::Agent { idle_timeout, lifetime } => {
+                if let Err(e) = run_agent(*idle_timeout, *lifetime) {
+                    println!("❌ Agent failed: {}", e);
+                }
+            }
+            Commands::AgentStatus => {
+                if let Err(e) = agent_status() {
+                    println!("❌ Failed to get agent status: {}", e);
+                }
+            }
+            Commands::StopAgent => match utils::get_data_dir().and_then(|dir| agent::stop(&dir)) {
+                Ok(true) => println!("🛑 Agent stopped"),
+                Ok(false) => println!("No agent is running"),
+                Err(e) => println!("❌ Failed to stop agent: {}", e),
+            },
+            Commands::ListPermissions => {
+                if let Err(e) = list_permissions() {
+                    println!("❌ Failed to list permissions: {}", e);
+                }
+            }
+            Commands::RevokePermissions { origin, all } => match revoke_permissions(origin.as_deref(), *all) {
+                Ok(revoked) if revoked.is_empty() => println!("No matching permissions"),
+                Ok(revoked) => {
+                    for grant in revoked {
+                        println!("🔌 Disconnected {}", grant.origin);
+                    }
+                }
+                Err(e) => println!("❌ Failed to revoke permissions: {}", e),
+            },
+            Commands::ChangePassphrase { file, account, kdf } => {
+                println!("🔑 Changing wallet passphrase...");
+                if let Err(e) = kdf
+                    .resolve()
+                    .and_then(|kdf_params| change_passphrase(file.as_deref(), account.as_deref(), kdf_params))
+                {
+                    println!("❌ Failed to change passphrase: {}", e);
+                }
+            }
+            Commands::CalibrateKdf { target_ms, max_memory_mib, save } => {
+                println!("⏱️  Calibrating Argon2id for {} ms...", target_ms);
+                if let Err(e) = calibrate_kdf(*target_ms, *max_memory_mib, *save) {
+                    println!("❌ Calibration failed: {}", e);
+                }
+            }
+            Commands::InstallNativeHost { browser, extension_id, firefox_id, host_path, system } => {
+                let scope = if *system { Scope::System } else { Scope::User };
+                match install_native_host(browser, extension_id.as_deref(), firefox_id.as_deref(), host_path.as_deref(), scope) {
+                    Ok(installed) => {
+                        for (browser, path) in installed {
+                            println!("🧩 Registered with {}: {}", browser, path.display());
+                        }
+                        println!("Restart the browser for it to pick up the host");
+                    }
+                    Err(e) => println!("❌ Failed to install native host: {}", e),
+                }
+            }
+            Commands::UninstallNativeHost { browser, system } => {
+                let scope = if *system { Scope::System } else { Scope::User };
+                match uninstall_native_host(browser, scope) {
+                    Ok(removed) if removed.is_empty() => println!("The native host was not registered"),
+                    Ok(removed) => {
+                        for (browser, path) in removed {
+                            println!("🗑️  Unregistered from {}: {}", browser, path.display());
+                        }
+                    }
+                    Err(e) => println!("❌ Failed to uninstall native host: {}", e),
+                }
+            }
+            Commands::NativeHostStatus => {
+                if let Err(e) = native_host_status() {
+                    println!("❌ Failed to read native host status: {}", e);
+                }
+            }
         }
     }
 }
+
+fn create_wallet(words: usize, with_bip39_passphrase: bool, kdf_params: Argon2Params) -> Result<()> {
+    let bip39_passphrase = if with_bip39_passphrase {
+        Some(prompt_bip39_passphrase()?)
+    } else {
+        None
+    };
+    wallet::init_wallet(kdf_params, words, bip39_passphrase.as_deref())?;
+    Ok(())
+}
+
+fn restore_wallet(with_bip39_passphrase: bool, kdf_params: Argon2Params) -> Result<()> {
+    let phrase = wallet::prompt_passphrase("Enter recovery phrase: ")?;
+    mnemonic::validate_mnemonic(&phrase)?;
+    let bip39_passphrase = if with_bip39_passphrase {
+        Some(wallet::prompt_passphrase("Enter BIP-39 passphrase: ")?)
+    } else {
+        None
+    };
+    let passphrase = wallet::prompt_passphrase("Enter passphrase to encrypt wallet: ")?;
+
+    let (address, filename) =
+        wallet::restore_wallet(&phrase, bip39_passphrase.as_deref(), &passphrase, kdf_params)?;
+    println!("✅ Restored {}", wallet::address_string(address));
+    println!("Keystore saved to: {}", filename.display());
+    Ok(())
+}
+
+/// A typo in a new BIP-39 passphrase silently yields a different wallet, so confirm it
+fn prompt_bip39_passphrase() -> Result<String> {
+    let bip39_passphrase = wallet::prompt_passphrase("Enter BIP-39 passphrase: ")?;
+    if wallet::prompt_passphrase("Confirm BIP-39 passphrase: ")? != bip39_passphrase {
+        return Err(VeroxError::InvalidArgument("passphrases do not match".to_string()));
+    }
+    Ok(bip39_passphrase)
+}
+
+/// The keystore file to act on and the account chosen in it: `file` as given, or
+/// the keystore holding `account`, asking which one when several qualify
+fn select_keystore(file: Option<&str>, account: Option<&str>) -> Result<(PathBuf, Option<String>)> {
+    if let Some(file) = file {
+        return Ok((PathBuf::from(file), account.map(str::to_string)));
+    }
+    let mut keystores = wallet::find_keystores(account)?;
+    let meta = match keystores.len() {
+        1 => keystores.remove(0),
+        _ => wallet::pick_account(keystores)?,
+    };
+    Ok((wallet::account_file(&meta)?, Some(meta.address)))
+}
+
+fn add_account(
+    file: Option<&str>,
+    account: Option<&str>,
+    scheme: &str,
+    index: Option<u32>,
+    label: Option<String>,
+) -> Result<()> {
+    let scheme: DerivationScheme = scheme.parse()?;
+    let (wallet_file, _) = select_keystore(file, account)?;
+    let passphrase = wallet::prompt_passphrase("Enter passphrase: ")?;
+
+    let meta = wallet::add_account(&wallet_file, &passphrase, &scheme, index, label)?;
+    println!("✅ Added {} ({})", meta.address, meta.derivation_path.unwrap_or_default());
+    Ok(())
+}
+
+fn list_accounts(include_hidden: bool) -> Result<()> {
+    let accounts = wallet::list_accounts(include_hidden)?;
+    if accounts.is_empty() {
+        println!("No accounts yet, run `verox create-wallet` first");
+        return Ok(());
+    }
+    for meta in accounts {
+        println!(
+            "{}  {:<16} {:<10} {:<20} last used {}{}",
+            meta.address,
+            meta.label.as_deref().unwrap_or("-"),
+            meta.key_type.to_string(),
+            meta.derivation_path.as_deref().unwrap_or("-"),
+            meta.last_used.as_deref().unwrap_or("never"),
+            if meta.hidden { "  (hidden)" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+fn list_networks() -> Result<()> {
+    let config = network::load_config()?;
+    let selected = config.selected_network().chain_id;
+    for network in config.networks() {
+        println!(
+            "{} {:<10} chain {:<10} {:<4} {}",
+            if network.chain_id == selected { "*" } else { " " },
+            network.name,
+            network.chain_id,
+            network.native_currency.symbol,
+            network.rpc_urls.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn show_balance(account: Option<&str>, network: Option<&str>) -> Result<()> {
+    // Any address works, registered or not; otherwise match accounts by address or label
+    let addresses: Vec<Address> = match account.and_then(|account| account.parse().ok()) {
+        Some(address) => vec![address],
+        None => wallet::find_accounts(account)?
+            .iter()
+            .filter_map(|meta| meta.address.parse().ok())
+            .collect(),
+    };
+    if addresses.is_empty() {
+        println!("No accounts yet, run `verox create-wallet` first");
+        return Ok(());
+    }
+
+    let network = network::resolve_network(network)?;
+    let symbol = network.native_currency.symbol.clone();
+    println!("🌐 {} (chain {})", network.name, network.chain_id);
+    provider::block_on(async {
+        let rpc = RpcProvider::connect(&network).await?;
+        for address in addresses {
+            let balance = rpc.balance(address).await?;
+            let nonce = rpc.nonce(address).await?;
+            println!("{}  {} {}  nonce {}", wallet::address_string(address), format_ether(balance), symbol, nonce);
+        }
+        Ok(())
+    })?
+}
+
+fn list_permissions() -> Result<()> {
+    let now = Utc::now();
+    let store = PermissionStore::load(&utils::get_data_dir()?)?;
+    let grants: Vec<_> = store.grants.iter().filter(|grant| !grant.is_expired(now)).collect();
+    if grants.is_empty() {
+        println!("No sites are connected");
+    }
+    for grant in grants {
+        let chains = if grant.chains.is_empty() {
+            "any chain".to_string()
+        } else {
+            grant.chains.iter().map(u64::to_string).collect::<Vec<_>>().join(", ")
+        };
+        println!("🌐 {}", grant.origin);
+        println!("   Accounts: {}", grant.accounts.iter().map(|a| wallet::address_string(*a)).collect::<Vec<_>>().join(", "));
+        println!("   Chains: {}", chains);
+        println!("   Expires: {}", grant.expires_at.format("%Y-%m-%d %H:%M UTC"));
+    }
+    Ok(())
+}
+
+fn revoke_permissions(origin: Option<&str>, all: bool) -> Result<Vec<Grant>> {
+    let origin = origin.map(permissions::normalize_origin).transpose()?;
+    PermissionStore::update(&utils::ensure_data_dir()?, |store| match &origin {
+        Some(origin) => store.revoke(origin).into_iter().collect(),
+        None if all => std::mem::take(&mut store.grants),
+        None => Vec::new(),
+    })
+}
+
+fn show_gas_price(network: Option<&str>) -> Result<()> {
+    let network = network::resolve_network(network)?;
+    let estimate = provider::block_on(async {
+        let rpc = RpcProvider::connect(&network).await?;
+        fees::estimate_fees(&rpc).await
+    })??;
+
+    if estimate.eip1559 {
+        println!(
+            "⛽ {}: base fee {} gwei (median {} gwei)",
+            network.name,
+            fees::gwei(estimate.base_fee_per_gas),
+            fees::gwei(estimate.median_base_fee)
+        );
+    } else {
+        println!("⛽ {}: no EIP-1559, using gas price", network.name);
+    }
+    for preset in &estimate.presets {
+        println!(
+            "  {:<7} max fee {:>14} gwei  tip {:>14} gwei  ~{}s",
+            preset.speed.to_string(),
+            fees::gwei(preset.max_fee_per_gas),
+            fees::gwei(preset.max_priority_fee_per_gas),
+            preset.expected_seconds
+        );
+    }
+    println!("  Fees above {} gwei are refused", fees::gwei(estimate.fee_cap));
+    Ok(())
+}
+
+fn delete_account(address: &str, yes: bool) -> Result<()> {
+    let meta = match wallet::find_accounts(Some(address))?.as_slice() {
+        [meta] => meta.clone(),
+        _ => return Err(VeroxError::InvalidArgument(format!("several accounts match '{}', use the address", address))),
+    };
+    let deletes_file =
+        wallet::owns_keystore(&meta) && wallet::account_file(&meta).is_ok_and(|path| path.exists());
+
+    if !yes {
+        if deletes_file {
+            println!("⚠️  This deletes the keystore file and every account derived from it.");
+            println!("Make sure you have the recovery phrase or a backup.");
+        }
+        print!("Type the address to confirm: ");
+        io::stdout().flush()?;
+        let mut confirmation = String::new();
+        io::stdin().read_line(&mut confirmation)?;
+        if !confirmation.trim().eq_ignore_ascii_case(&meta.address) {
+            println!("Aborted");
+            return Ok(());
+        }
+    }
+    let passphrase = if deletes_file {
+        Some(wallet::prompt_passphrase("Enter passphrase: ")?)
+    } else {
+        None
+    };
+
+    for removed in wallet::delete_account(&meta.address, passphrase.as_deref())? {
+        println!("🗑️  Deleted {}", removed.address);
+    }
+    Ok(())
+}
+
+fn check_keystore(fix: bool) -> Result<()> {
+    let report = wallet::check_keystore(fix)?;
+    if report.is_clean() {
+        println!("✅ Index and keystore files agree");
+        return Ok(());
+    }
+    for meta in &report.missing_files {
+        println!("⚠️  {} is indexed but {} is missing", meta.address, meta.file.as_deref().unwrap_or("-"));
+    }
+    for file in &report.unindexed_files {
+        println!("⚠️  {} is not in the index", file);
+    }
+    if fix {
+        println!("✅ Index repaired");
+    } else {
+        println!("Run with --fix to repair the index");
+    }
+    Ok(())
+}
+
+fn import_keystore(path: &str, kdf_params: Argon2Params) -> Result<()> {
+    let v3_json = fs::read_to_string(path)?;
+    let v3_password = wallet::prompt_passphrase("Enter keystore password: ")?;
+    let passphrase = wallet::prompt_passphrase("Enter new Verox passphrase: ")?;
+
+    let (address, filename) = wallet::import_v3_keystore(&v3_json, &v3_password, &passphrase, kdf_params)?;
+    println!("✅ Imported {}", wallet::address_string(address));
+    println!("Keystore saved to: {}", filename.display());
+    Ok(())
+}
+
+fn export_keystore(file: Option<&str>, account: Option<&str>, out: &str) -> Result<()> {
+    let (wallet_file, address) = select_keystore(file, account)?;
+    let passphrase = wallet::prompt_passphrase("Enter Verox passphrase: ")?;
+    let v3_password = wallet::prompt_passphrase("Enter password for exported keystore: ")?;
+
+    let (address, v3_json) = wallet::export_v3_keystore(&wallet_file, address.as_deref(), &passphrase, &v3_password)?;
+    let target = Path::new(out).join(wallet::v3_keystore_filename(address));
+    fs::write(&target, v3_json)?;
+    println!("✅ Exported to {}", target.display());
+    Ok(())
+}
+
+/// The network a transaction goes to: its chain ID's, or the selected one
+fn transaction_network(request: &TxRequest) -> Result<Network> {
+    match request.chain_id {
+        Some(chain_id) => network::resolve_network(Some(&chain_id.to_string())).map_err(|_| {
+            VeroxError::InvalidArgument(format!("unknown chain {}, add it with add-network", chain_id))
+        }),
+        None => network::resolve_network(None),
+    }
+}
+
+fn sign_transaction(file: Option<&Path>, account: Option<&str>, tx: &TxArgs) -> Result<()> {
+    let mut request = tx.resolve()?;
+    let wallet = wallet::unlock_wallet(file, account)?;
+
+    // A fully specified transaction is signed offline, as given; anything
+    // missing comes from the network
+    let has_fees = request.gas_price.is_some() || request.max_fee_per_gas.is_some();
+    if request.nonce.is_some() && request.gas_limit.is_some() && has_fees {
+        if request.chain_id.is_none() {
+            request.chain_id = Some(network::resolve_network(None)?.chain_id);
+        }
+    } else {
+        let network = transaction_network(&request)?;
+        let speed: FeeSpeed = tx.speed.parse()?;
+        provider::block_on(async {
+            let rpc = RpcProvider::connect(&network).await?;
+            rpc.fill_request(&mut request, wallet.address(), speed, tx.allow_high_fee).await
+        })??;
+        println!("⛽ Using {} fees on {}", speed, network.name);
+    }
+
+    let params = request.into_params()?;
+    let signed = wallet.sign_transaction(&params)?;
+    println!("✅ Signed by {}", wallet::address_string(signed.from));
+    println!("Hash: {:?}", signed.hash);
+    println!("Raw transaction: {}", signed.raw);
+    Ok(())
+}
+
+fn send_transaction(file: Option<&Path>, account: Option<&str>, tx: &TxArgs, wait: bool) -> Result<()> {
+    let request = tx.resolve()?;
+    let network = transaction_network(&request)?;
+    let speed: FeeSpeed = tx.speed.parse()?;
+    let wallet = wallet::unlock_wallet(file, account)?;
+
+    let nonces = NonceManager::open()?;
+    let (_, tracked) = provider::block_on(async {
+        let rpc = RpcProvider::connect(&network).await?;
+        pending::send(&rpc, &nonces, &wallet, request, speed, tx.allow_high_fee).await
+    })??;
+
+    let hash = format!("{:?}", tracked.hash);
+    println!("✅ Sent {} on {} (nonce {})", hash, network.name, tracked.nonce);
+    if let Some(url) = network.tx_url(&hash) {
+        println!("🔗 {}", url);
+    }
+    if !wait {
+        println!("Check on it with: verox tx-status {}", hash);
+        return Ok(());
+    }
+
+    let dir = utils::get_data_dir()?;
+    let mut last = tracked;
+    while !last.status.is_final() {
+        std::thread::sleep(Duration::from_secs(pending::POLL_INTERVAL_SECS));
+        let tx = provider::block_on(pending::check(&dir, last.hash, pending::CONFIRMATIONS))??;
+        if (tx.status, tx.confirmations) != (last.status, last.confirmations) {
+            print_tracked(&tx);
+        }
+        last = tx;
+    }
+    Ok(())
+}
+
+fn show_tx_status(hash: Option<&str>, confirmations: u64) -> Result<()> {
+    let dir = utils::get_data_dir()?;
+    if let Some(hash) = hash {
+        let hash: H256 = hash
+            .parse()
+            .map_err(|_| VeroxError::InvalidArgument(format!("invalid transaction hash '{}'", hash)))?;
+        print_tracked(&provider::block_on(pending::check(&dir, hash, confirmations))??);
+        return Ok(());
+    }
+
+    for error in provider::block_on(pending::refresh(&dir, confirmations))??.errors {
+        println!("⚠️  Could not check {}", error);
+    }
+    let store = TxStore::load(&dir)?;
+    if store.transactions.is_empty() {
+        println!("No transactions sent yet");
+    }
+    for tx in store.transactions.iter().rev() {
+        print_tracked(tx);
+    }
+    Ok(())
+}
+
+fn replace_transaction(hash: &str, kind: Replacement, args: &ReplaceArgs) -> Result<()> {
+    let hash: H256 = hash
+        .parse()
+        .map_err(|_| VeroxError::InvalidArgument(format!("invalid transaction hash '{}'", hash)))?;
+    let speed: FeeSpeed = args.speed.parse()?;
+    let dir = utils::get_data_dir()?;
+    let original = TxStore::load(&dir)?
+        .get(hash)
+        .cloned()
+        .ok_or_else(|| VeroxError::InvalidArgument(format!("transaction {:?} is not tracked", hash)))?;
+    let network = network::resolve_network(Some(&original.chain_id.to_string()))?;
+    let wallet = wallet::unlock_wallet(
+        args.file.as_deref().map(Path::new),
+        Some(&wallet::address_string(original.from)),
+    )?;
+
+    let (params, tracked) = provider::block_on(async {
+        let rpc = RpcProvider::connect(&network).await?;
+        replace::replace(&rpc, &dir, &wallet, hash, kind, speed, args.allow_high_fee).await
+    })??;
+    let fee = params.max_fee_per_gas.max(params.gas_price);
+    let new_hash = format!("{:?}", tracked.hash);
+    println!("✅ Sent {} {} (nonce {}, {} gwei)", kind, new_hash, tracked.nonce, fees::gwei(fee));
+    if let Some(url) = network.tx_url(&new_hash) {
+        println!("🔗 {}", url);
+    }
+    Ok(())
+}
+
+fn show_nonce_status(account: Option<&str>, network: Option<&str>) -> Result<()> {
+    let address: Address = match account.and_then(|account| account.parse().ok()) {
+        Some(address) => address,
+        None => {
+            let mut accounts = wallet::find_accounts(account)?;
+            let meta = match accounts.len() {
+                1 => accounts.remove(0),
+                _ => wallet::pick_account(accounts)?,
+            };
+            meta.address
+                .parse()
+                .map_err(|_| VeroxError::InvalidArgument(format!("invalid address '{}'", meta.address)))?
+        }
+    };
+    let network = network::resolve_network(network)?;
+    let nonces = NonceManager::open()?;
+    let status = provider::block_on(async {
+        let rpc = RpcProvider::connect(&network).await?;
+        nonces.status(&rpc, address).await
+    })??;
+
+    println!("🔢 {} on {}", status.address, network.name);
+    println!("  Mined:   {}", status.mined);
+    println!("  Pending: {}", status.pending);
+    println!("  Next:    {}", status.next);
+    if !status.in_use.is_empty() {
+        let in_use: Vec<String> = status.in_use.iter().map(u64::to_string).collect();
+        println!("  Sent by Verox, not mined: {}", in_use.join(", "));
+    }
+    if !status.gaps.is_empty() {
+        let gaps: Vec<String> = status.gaps.iter().map(u64::to_string).collect();
+        println!("⚠️  Gaps: {} (later transactions wait until these are used)", gaps.join(", "));
+    }
+    if let Some(stuck) = status.stuck {
+        println!("⚠️  Nonce {} has been pending for a long time and holds up the rest", stuck);
+    }
+    Ok(())
+}
+
+fn print_tracked(tx: &TrackedTx) {
+    let icon = match tx.status {
+        pending::TxStatus::Pending => "⏳",
+        pending::TxStatus::Included => "📦",
+        pending::TxStatus::Confirmed => "✅",
+        pending::TxStatus::Failed => "❌",
+        pending::TxStatus::Dropped => "🗑️ ",
+        pending::TxStatus::Replaced => "🔁",
+    };
+    let detail = match (tx.block_number, tx.replaced_by) {
+        (Some(block), _) => format!(", block {}, {} confirmations", block, tx.confirmations),
+        (None, Some(replacement)) => format!(", by {:?}", replacement),
+        (None, None) => String::new(),
+    };
+    println!(
+        "{} {:?} {} (chain {}, nonce {}{})",
+        icon, tx.hash, tx.status, tx.chain_id, tx.nonce, detail
+    );
+}
+
+fn message_bytes(message: &str, hex: bool) -> Result<Vec<u8>> {
+    if !hex {
+        return Ok(message.as_bytes().to_vec());
+    }
+    let data = message.strip_prefix("0x").unwrap_or(message);
+    hex::decode(data).map_err(|_| VeroxError::InvalidArgument("message is not valid hex".to_string()))
+}
+
+fn sign_message(message: &str, hex: bool, file: Option<&Path>, account: Option<&str>) -> Result<()> {
+    let message = message_bytes(message, hex)?;
+    let wallet = wallet::unlock_wallet(file, account)?;
+    let signature = wallet.sign_message(&message)?;
+    println!("✅ Signed by {}", wallet::address_string(wallet.address()));
+    println!("Signature: 0x{}", signature);
+    Ok(())
+}
+
+fn verify_message(message: &str, signature: &str, address: Option<&str>, hex: bool) -> Result<()> {
+    let message = message_bytes(message, hex)?;
+    let signer = verox::message::recover_signer(&message, &verox::message::parse_signature(signature)?)?;
+    let signer = wallet::address_string(signer);
+    match address {
+        Some(address) if address.eq_ignore_ascii_case(&signer) => println!("✅ Signature is valid for {}", signer),
+        Some(address) => {
+            return Err(VeroxError::InvalidArgument(format!(
+                "signature was made by {}, not {}",
+                signer, address
+            )))
+        }
+        None => println!("✅ Signed by {}", signer),
+    }
+    Ok(())
+}
+
+fn sign_typed_data(path: &str, file: Option<&Path>, account: Option<&str>, yes: bool) -> Result<()> {
+    let payload: serde_json::Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let data = typed_data::parse_typed_data(&payload)?;
+    let hashes = typed_data::hash_typed_data(&data)?;
+
+    println!("📝 Typed data to sign:\n");
+    for field in typed_data::field_tree(&data)? {
+        print!("{}", field);
+    }
+    println!("\nDigest: {:?}", hashes.digest);
+    if !yes {
+        print!("Sign this message? [y/N]: ");
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+        if !answer.trim().eq_ignore_ascii_case("y") {
+            println!("Cancelled");
+            return Ok(());
+        }
+    }
+
+    let wallet = wallet::unlock_wallet(file, account)?;
+    let signature = wallet.sign_typed_data(&data)?;
+    println!("✅ Signed by {}", wallet::address_string(wallet.address()));
+    println!("Signature: 0x{}", signature);
+    Ok(())
+}
+
+fn change_passphrase(file: Option<&str>, account: Option<&str>, kdf_params: Argon2Params) -> Result<()> {
+    let (wallet_file, _) = select_keystore(file, account)?;
+    let old_passphrase = wallet::prompt_passphrase("Enter current passphrase: ")?;
+    let new_passphrase = wallet::prompt_passphrase("Enter new passphrase: ")?;
+    if wallet::prompt_passphrase("Confirm new passphrase: ")? != new_passphrase {
+        return Err(VeroxError::InvalidArgument("passphrases do not match".to_string()));
+    }
+
+    wallet::change_passphrase(&wallet_file, &old_passphrase, &new_passphrase, kdf_params)?;
+    println!("✅ Passphrase changed for {}", wallet_file.display());
+    Ok(())
+}
+
+fn calibrate_kdf(target_ms: u64, max_memory_mib: u32, save: bool) -> Result<()> {
+    let (params, elapsed) = crypto::calibrate_kdf(
+        Duration::from_millis(target_ms),
+        max_memory_mib.saturating_mul(1024),
+    )?;
+    println!("✅ {} takes {} ms on this machine", params, elapsed.as_millis());
+
+    if save {
+        let path = wallet::save_kdf_params(params)?;
+        println!("Saved as default for new keystores: {}", path.display());
+    } else {
+        println!(
+            "Use with --kdf-m {} --kdf-t {} --kdf-p {}, or re-run with --save",
+            params.m_cost, params.t_cost, params.p_cost
+        );
+    }
+    Ok(())
+}
+
+fn home_dir() -> Result<PathBuf> {
+    if !cfg!(target_os = "linux") {
+        return Err(VeroxError::InvalidArgument("native host registration is only supported on Linux".to_string()));
+    }
+    dirs::home_dir().ok_or_else(|| VeroxError::InvalidArgument("cannot find the home directory".to_string()))
+}
+
+/// Browsers named on the command line, or every one
+fn parse_browsers(names: &[String]) -> Result<Vec<Browser>> {
+    if names.is_empty() {
+        return Ok(Browser::ALL.to_vec());
+    }
+    names.iter().map(|name| name.parse()).collect()
+}
+
+fn install_native_host(
+    names: &[String],
+    extension_id: Option<&str>,
+    firefox_id: Option<&str>,
+    host_path: Option<&Path>,
+    scope: Scope,
+) -> Result<Vec<(Browser, PathBuf)>> {
+    let home = home_dir()?;
+    let host_path = match host_path {
+        Some(path) => path.canonicalize()?,
+        None => native_manifest::default_host_path()?,
+    };
+    let id_for = |browser: Browser| if browser.is_firefox() { firefox_id } else { extension_id };
+    let browsers = if names.is_empty() {
+        let browsers: Vec<Browser> = Browser::ALL.into_iter().filter(|b| id_for(*b).is_some()).collect();
+        if browsers.is_empty() {
+            return Err(VeroxError::InvalidArgument("pass --extension-id and/or --firefox-id".to_string()));
+        }
+        browsers
+    } else {
+        parse_browsers(names)?
+    };
+
+    // Check every manifest before writing any
+    let manifests = browsers
+        .into_iter()
+        .map(|browser| {
+            let id = id_for(browser).ok_or_else(|| {
+                let flag = if browser.is_firefox() { "--firefox-id" } else { "--extension-id" };
+                VeroxError::InvalidArgument(format!("{} needs {}", browser, flag))
+            })?;
+            Ok((browser, HostManifest::new(browser, &host_path, id)?))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    manifests
+        .into_iter()
+        .map(|(browser, manifest)| Ok((browser, native_manifest::install(browser, scope, &home, &manifest)?)))
+        .collect()
+}
+
+fn uninstall_native_host(names: &[String], scope: Scope) -> Result<Vec<(Browser, PathBuf)>> {
+    let home = home_dir()?;
+    let mut removed = Vec::new();
+    for browser in parse_browsers(names)? {
+        if let Some(path) = native_manifest::uninstall(browser, scope, &home)? {
+            removed.push((browser, path));
+        }
+    }
+    Ok(removed)
+}
+
+fn native_host_status() -> Result<()> {
+    let home = home_dir()?;
+    for scope in [Scope::User, Scope::System] {
+        for browser in Browser::ALL {
+            let path = browser.manifest_path(scope, &home);
+            match native_manifest::installed(browser, scope, &home) {
+                Ok(Some(manifest)) => {
+                    let allowed = manifest.allowed_origins.or(manifest.allowed_extensions).unwrap_or_default();
+                    println!("✅ {} ({}): {}", browser, scope, path.display());
+                    if manifest.path.is_file() {
+                        println!("   Host: {}", manifest.path.display());
+                    } else {
+                        println!("   Host: {} ⚠️  missing", manifest.path.display());
+                    }
+                    println!("   Allowed: {}", allowed.join(", "));
+                }
+                Ok(None) => println!("➖ {} ({}): not registered", browser, scope),
+                Err(e) => println!("⚠️  {} ({}): unreadable manifest {}: {}", browser, scope, path.display(), e),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn run_agent(idle_timeout: u64, lifetime: u64) -> Result<()> {
+    if idle_timeout == 0 || lifetime == 0 {
+        return Err(VeroxError::InvalidArgument("timeouts must be at least a second".to_string()));
+    }
+    if idle_timeout > agent::MAX_TIMEOUT_SECS || lifetime > agent::MAX_TIMEOUT_SECS {
+        return Err(VeroxError::InvalidArgument(format!(
+            "timeouts can be at most {} seconds (a week)",
+            agent::MAX_TIMEOUT_SECS
+        )));
+    }
+    let dir = utils::ensure_data_dir()?;
+    let config = AgentConfig {
+        idle_timeout: Duration::from_secs(idle_timeout),
+        lifetime: Duration::from_secs(lifetime),
+    };
+    println!("🔐 Agent listening on {}", agent::socket_path(&dir).display());
+    println!("Keys are forgotten after {}s idle or {}s in total; stop with `verox stop-agent`", idle_timeout, lifetime);
+    agent::serve(&dir, config)?;
+    println!("🛑 Agent stopped");
+    Ok(())
+}
+
+fn agent_status() -> Result<()> {
+    let status = match agent::status(&utils::get_data_dir()?)? {
+        Some(status) => status,
+        None => {
+            println!("No agent is running, start one with `verox agent`");
+            return Ok(());
+        }
+    };
+    if status.accounts.is_empty() {
+        println!("🔒 The agent holds no keys");
+        return Ok(());
+    }
+    println!("🔓 The agent holds {} key(s)", status.accounts.len());
+    for account in &status.accounts {
+        println!("   {}", account);
+    }
+    if let (Some(idle), Some(expires)) = (status.idle_expires_in, status.expires_in) {
+        println!("Locks in {}s unless used, and in {}s at the latest", idle, expires);
+    }
+    Ok(())
+}
@@ -1,78 +1,473 @@
 use aes_gcm::{Aes256Gcm, Nonce};
-use aes_gcm::aead::{Aead, KeyInit};
-use argon2::Argon2;
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use argon2::{Algorithm, Argon2, Params, Version};
 use rand::rngs::OsRng;
 use rand::RngCore;
 use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::error::{Result, VeroxError};
+
+/// Magic bytes at the start of every versioned keystore container.
+pub const KEYSTORE_MAGIC: &[u8; 4] = b"VRXK";
+
+/// Container version written by `encrypt_keystore`.
+pub const KEYSTORE_VERSION: u8 = 1;
+
+const KDF_ID_ARGON2ID: u8 = 1;
+const CIPHER_ID_AES256GCM: u8 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Upper bounds on header costs, so a crafted keystore cannot demand unbounded
+/// memory or time before the passphrase is even checked. Well above Paranoid.
+const MAX_M_COST: u32 = 4 * 1024 * 1024;
+const MAX_T_COST: u32 = 64;
+const MAX_P_COST: u32 = 16;
+
+/// Argon2id cost parameters as stored in the keystore header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Argon2Params {
+    /// Memory cost in KiB
+    pub m_cost: u32,
+    /// Number of iterations
+    pub t_cost: u32,
+    /// Degree of parallelism
+    pub p_cost: u32,
+}
+
+impl Default for Argon2Params {
+    /// The values `Argon2::default()` used before the header existed
+    fn default() -> Self {
+        Argon2Params {
+            m_cost: Params::DEFAULT_M_COST,
+            t_cost: Params::DEFAULT_T_COST,
+            p_cost: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl Argon2Params {
+    /// Check the values against Argon2's limits before they reach a keystore
+    pub fn validate(&self) -> Result<()> {
+        if self.m_cost > MAX_M_COST || self.t_cost > MAX_T_COST || self.p_cost > MAX_P_COST {
+            return Err(VeroxError::InvalidArgument(format!(
+                "Argon2 parameters above m={} KiB, t={}, p={}",
+                MAX_M_COST, MAX_T_COST, MAX_P_COST
+            )));
+        }
+        Params::new(self.m_cost, self.t_cost, self.p_cost, Some(32))
+            .map(|_| ())
+            .map_err(|e| VeroxError::InvalidArgument(format!("invalid Argon2 parameters: {}", e)))
+    }
+}
+
+impl fmt::Display for Argon2Params {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Argon2id m={} KiB, t={}, p={}", self.m_cost, self.t_cost, self.p_cost)
+    }
+}
+
+/// Named Argon2id cost profiles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KdfProfile {
+    /// 19 MiB, 2 passes: OWASP's minimum and the pre-profile default
+    Interactive,
+    /// 64 MiB, 3 passes
+    Moderate,
+    /// 1 GiB, 4 passes, for long-term cold storage
+    Paranoid,
+}
+
+impl KdfProfile {
+    pub fn params(self) -> Argon2Params {
+        match self {
+            KdfProfile::Interactive => Argon2Params::default(),
+            KdfProfile::Moderate => Argon2Params { m_cost: 64 * 1024, t_cost: 3, p_cost: 1 },
+            KdfProfile::Paranoid => Argon2Params { m_cost: 1024 * 1024, t_cost: 4, p_cost: 1 },
+        }
+    }
+}
+
+impl FromStr for KdfProfile {
+    type Err = VeroxError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "interactive" => Ok(KdfProfile::Interactive),
+            "moderate" => Ok(KdfProfile::Moderate),
+            "paranoid" => Ok(KdfProfile::Paranoid),
+            other => Err(VeroxError::InvalidArgument(format!(
+                "unknown KDF profile '{}' (expected interactive, moderate or paranoid)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Benchmark Argon2id on this machine and return the cheapest parameters that take at
+/// least `target` per derivation, never going below the interactive profile.
+/// Memory is doubled first (up to `max_m_cost` KiB), then passes are added.
+pub fn calibrate_kdf(target: Duration, max_m_cost: u32) -> Result<(Argon2Params, Duration)> {
+    let mut params = KdfProfile::Interactive.params();
+    let salt = [0u8; SALT_LEN];
+
+    loop {
+        let start = Instant::now();
+        derive_key(&Kdf::Argon2id(params), b"verox-calibration", &salt)?;
+        let elapsed = start.elapsed();
+
+        if elapsed >= target {
+            return Ok((params, elapsed));
+        }
+        if params.m_cost.saturating_mul(2) <= max_m_cost {
+            params.m_cost *= 2;
+        } else {
+            params.t_cost += 1;
+        }
+    }
+}
+
+/// Key derivation function recorded in the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    Argon2id(Argon2Params),
+}
+
+/// Symmetric cipher recorded in the header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    Aes256Gcm,
+}
+
+/// Everything needed to decrypt a keystore, minus the passphrase
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeystoreHeader {
+    /// 0 for legacy headerless files, otherwise the container version
+    pub version: u8,
+    pub kdf: Kdf,
+    pub cipher: Cipher,
+    pub salt: Vec<u8>,
+    pub nonce: Vec<u8>,
+}
+
+impl KeystoreHeader {
+    /// Serialize the header exactly as it appears on disk (v1 layout)
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(64);
+        out.extend_from_slice(KEYSTORE_MAGIC);
+        out.push(self.version);
+
+        match self.kdf {
+            Kdf::Argon2id(params) => {
+                out.push(KDF_ID_ARGON2ID);
+                out.extend_from_slice(&params.m_cost.to_le_bytes());
+                out.extend_from_slice(&params.t_cost.to_le_bytes());
+                out.extend_from_slice(&params.p_cost.to_le_bytes());
+            }
+        }
+        out.push(self.salt.len() as u8);
+        out.extend_from_slice(&self.salt);
+
+        match self.cipher {
+            Cipher::Aes256Gcm => out.push(CIPHER_ID_AES256GCM),
+        }
+        out.push(self.nonce.len() as u8);
+        out.extend_from_slice(&self.nonce);
+
+        out
+    }
+}
 
 /// Parameters:
 /// - salt: 16 bytes
 /// - nonce: 12 bytes (AES-GCM)
-/// Storage format (bytes, then base64-encoded): [salt(16) | nonce(12) | ciphertext(...)]
+///
+/// Storage format (bytes, then base64-encoded):
+/// `["VRXK" | version | kdf_id | m_cost | t_cost | p_cost | salt_len | salt | cipher_id | nonce_len | nonce | ciphertext]`
+/// with the integers little-endian `u32`s and the ids/lengths single bytes. Everything
+/// before the ciphertext is authenticated as AES-GCM associated data.
 ///
 /// NOTE: This function returns base64-encoded bytes (Vec<u8>) so you can write them
-/// directly to disk. Replace Argon2 params / memory with stricter ones in prod.
-pub fn encrypt_keystore(data: &[u8], passphrase: &[u8]) -> Vec<u8> {
-    // 1) generate salt
-    let mut salt = [0u8; 16];
+/// directly to disk. `kdf_params` are recorded in the header, so each file can use
+/// different costs.
+pub fn encrypt_keystore(data: &[u8], passphrase: &[u8], kdf_params: Argon2Params) -> Result<Vec<u8>> {
+    kdf_params.validate()?;
+
+    // 1) generate salt and nonce
+    let mut salt = [0u8; SALT_LEN];
     OsRng.fill_bytes(&mut salt);
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce_bytes);
 
-    // 2) derive 32-byte key using Argon2id
-    let mut key = [0u8; 32];
-    let argon2 = Argon2::default();
-    argon2
-        .hash_password_into(passphrase, &salt, &mut key)
-        .expect("Argon2 hashing failed");
+    let header = KeystoreHeader {
+        version: KEYSTORE_VERSION,
+        kdf: Kdf::Argon2id(kdf_params),
+        cipher: Cipher::Aes256Gcm,
+        salt: salt.to_vec(),
+        nonce: nonce_bytes.to_vec(),
+    };
+    let header_bytes = header.to_bytes();
 
-    // 3) create AES-GCM cipher
-    let cipher = Aes256Gcm::new(&key.into());
+    // 2) derive 32-byte key
+    let key = derive_key(&header.kdf, passphrase, &header.salt)?;
 
-    // 4) generate nonce
-    let mut nonce_bytes = [0u8; 12];
-    OsRng.fill_bytes(&mut nonce_bytes);
-    let nonce = Nonce::from_slice(&nonce_bytes);
-
-    // 5) encrypt
+    // 3) encrypt, binding the header as associated data
+    let cipher = Aes256Gcm::new(&key.into());
     let ciphertext = cipher
-        .encrypt(nonce, data)
-        .expect("AES-GCM encryption failed");
+        .encrypt(
+            Nonce::from_slice(&nonce_bytes),
+            Payload { msg: data, aad: &header_bytes },
+        )
+        .map_err(|_| VeroxError::CorruptKeystore("AES-GCM encryption failed".to_string()))?;
 
-    // 6) combine salt + nonce + ciphertext, then base64 encode
-    let mut out: Vec<u8> = Vec::with_capacity(16 + 12 + ciphertext.len());
-    out.extend_from_slice(&salt);
-    out.extend_from_slice(&nonce_bytes);
+    // 4) combine header + ciphertext, then base64 encode
+    let mut out = header_bytes;
     out.extend_from_slice(&ciphertext);
 
-    general_purpose::STANDARD.encode(out).into_bytes()
+    Ok(general_purpose::STANDARD.encode(out).into_bytes())
 }
 
-pub fn decrypt_keystore(encoded: &[u8], passphrase: &[u8]) -> Vec<u8> {
-    // decode base64
-    let combined = general_purpose::STANDARD
-        .decode(encoded)
-        .expect("Base64 decode failed");
+/// Decrypt a keystore written by `encrypt_keystore` (or a legacy v0 file).
+/// A failed AES-GCM tag check is reported as `WrongPassphrase`.
+pub fn decrypt_keystore(encoded: &[u8], passphrase: &[u8]) -> Result<Vec<u8>> {
+    let combined = decode_base64(encoded)?;
 
-    if combined.len() < 28 {
-        panic!("Keystore corrupted or too small");
-    }
+    let (header, header_len) = parse_header(&combined)?;
+    let ciphertext = &combined[header_len..];
 
-    // split into salt | nonce | ciphertext
-    let salt = &combined[0..16];
-    let nonce_bytes = &combined[16..28];
-    let ciphertext = &combined[28..];
+    let key = derive_key(&header.kdf, passphrase, &header.salt)?;
 
-    // derive key with same salt
-    let mut key = [0u8; 32];
-    let argon2 = Argon2::default();
-    argon2
-        .hash_password_into(passphrase, salt, &mut key)
-        .expect("Argon2 hashing failed");
+    let aad: &[u8] = match header.version {
+        // v0 files were written without associated data
+        0 => &[],
+        _ => &combined[..header_len],
+    };
 
-    let cipher = Aes256Gcm::new(&key.into());
-    let nonce = Nonce::from_slice(nonce_bytes);
+    let cipher = match header.cipher {
+        Cipher::Aes256Gcm => Aes256Gcm::new(&key.into()),
+    };
+    let nonce = Nonce::from_slice(&header.nonce);
 
     // decrypt
     cipher
-        .decrypt(nonce, ciphertext)
-        .expect("AES-GCM decryption failed")
+        .decrypt(nonce, Payload { msg: ciphertext, aad })
+        .map_err(|_| VeroxError::WrongPassphrase)
+}
+
+/// Read the header of an encoded keystore without decrypting it.
+/// Legacy headerless files are reported as version 0.
+pub fn read_header(encoded: &[u8]) -> Result<KeystoreHeader> {
+    parse_header(&decode_base64(encoded)?).map(|(header, _)| header)
+}
+
+fn decode_base64(encoded: &[u8]) -> Result<Vec<u8>> {
+    general_purpose::STANDARD
+        .decode(encoded.trim_ascii())
+        .map_err(|e| VeroxError::CorruptKeystore(format!("Base64 decode failed: {}", e)))
+}
+
+/// Split the decoded container into its header and the offset of the ciphertext
+fn parse_header(combined: &[u8]) -> Result<(KeystoreHeader, usize)> {
+    if !combined.starts_with(KEYSTORE_MAGIC) {
+        return parse_v0_header(combined);
+    }
+
+    let mut reader = ByteReader { bytes: combined, pos: KEYSTORE_MAGIC.len() };
+
+    let version = reader.u8()?;
+    if version != KEYSTORE_VERSION {
+        return Err(VeroxError::UnsupportedVersion(format!("container version {}", version)));
+    }
+
+    let kdf = match reader.u8()? {
+        KDF_ID_ARGON2ID => Kdf::Argon2id(Argon2Params {
+            m_cost: reader.u32()?,
+            t_cost: reader.u32()?,
+            p_cost: reader.u32()?,
+        }),
+        other => return Err(VeroxError::UnsupportedVersion(format!("KDF id {}", other))),
+    };
+    let Kdf::Argon2id(params) = &kdf;
+    if params.m_cost > MAX_M_COST || params.t_cost > MAX_T_COST || params.p_cost > MAX_P_COST {
+        return Err(VeroxError::CorruptKeystore(format!("Argon2 costs out of range: {}", params)));
+    }
+    let salt_len = reader.u8()? as usize;
+    let salt = reader.take(salt_len)?.to_vec();
+
+    let cipher = match reader.u8()? {
+        CIPHER_ID_AES256GCM => Cipher::Aes256Gcm,
+        other => return Err(VeroxError::UnsupportedVersion(format!("cipher id {}", other))),
+    };
+    let nonce_len = reader.u8()? as usize;
+    if nonce_len != NONCE_LEN {
+        return Err(VeroxError::CorruptKeystore(format!("invalid nonce length {}", nonce_len)));
+    }
+    let nonce = reader.take(nonce_len)?.to_vec();
+
+    Ok((KeystoreHeader { version, kdf, cipher, salt, nonce }, reader.pos))
+}
+
+/// Legacy layout: [salt(16) | nonce(12) | ciphertext(...)] with default Argon2id params
+fn parse_v0_header(combined: &[u8]) -> Result<(KeystoreHeader, usize)> {
+    if combined.len() < SALT_LEN + NONCE_LEN {
+        return Err(VeroxError::CorruptKeystore("file too small".to_string()));
+    }
+
+    let header = KeystoreHeader {
+        version: 0,
+        kdf: Kdf::Argon2id(Argon2Params::default()),
+        cipher: Cipher::Aes256Gcm,
+        salt: combined[0..SALT_LEN].to_vec(),
+        nonce: combined[SALT_LEN..SALT_LEN + NONCE_LEN].to_vec(),
+    };
+    Ok((header, SALT_LEN + NONCE_LEN))
+}
+
+fn derive_key(kdf: &Kdf, passphrase: &[u8], salt: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    match kdf {
+        Kdf::Argon2id(p) => {
+            let params = Params::new(p.m_cost, p.t_cost, p.p_cost, Some(key.len()))
+                .map_err(|e| VeroxError::CorruptKeystore(format!("invalid Argon2 parameters: {}", e)))?;
+            Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                .hash_password_into(passphrase, salt, &mut key)
+                .map_err(|e| VeroxError::CorruptKeystore(format!("Argon2 hashing failed: {}", e)))?;
+        }
+    }
+    Ok(key)
+}
+
+/// Minimal cursor over the decoded container
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos + len;
+        if end > self.bytes.len() {
+            return Err(VeroxError::CorruptKeystore("header truncated".to_string()));
+        }
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reproduces the pre-header writer so we keep reading old wallets
+    fn encrypt_v0(data: &[u8], passphrase: &[u8]) -> Vec<u8> {
+        let mut salt = [0u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let mut key = [0u8; 32];
+        Argon2::default()
+            .hash_password_into(passphrase, &salt, &mut key)
+            .unwrap();
+        let mut nonce_bytes = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce_bytes);
+        let ciphertext = Aes256Gcm::new(&key.into())
+            .encrypt(Nonce::from_slice(&nonce_bytes), data)
+            .unwrap();
+
+        let mut out = salt.to_vec();
+        out.extend_from_slice(&nonce_bytes);
+        out.extend_from_slice(&ciphertext);
+        general_purpose::STANDARD.encode(out).into_bytes()
+    }
+
+    #[test]
+    fn test_round_trip_v1() {
+        let encoded = encrypt_keystore(b"secret", b"pass", Argon2Params::default()).unwrap();
+        let header = read_header(&encoded).unwrap();
+        assert_eq!(header.version, KEYSTORE_VERSION);
+        assert_eq!(header.kdf, Kdf::Argon2id(Argon2Params::default()));
+        assert_eq!(header.cipher, Cipher::Aes256Gcm);
+        assert_eq!(decrypt_keystore(&encoded, b"pass").unwrap(), b"secret");
+    }
+
+    #[test]
+    fn test_params_are_stored_in_header() {
+        let params = Argon2Params { m_cost: 8 * 1024, t_cost: 3, p_cost: 2 };
+        let encoded = encrypt_keystore(b"secret", b"pass", params).unwrap();
+        assert_eq!(read_header(&encoded).unwrap().kdf, Kdf::Argon2id(params));
+        assert_eq!(decrypt_keystore(&encoded, b"pass").unwrap(), b"secret");
+
+        let too_small = Argon2Params { m_cost: 1, t_cost: 1, p_cost: 1 };
+        assert!(encrypt_keystore(b"secret", b"pass", too_small).is_err());
+    }
+
+    #[test]
+    fn test_profiles_and_calibration_floor() {
+        assert_eq!("Paranoid".parse::<KdfProfile>().unwrap(), KdfProfile::Paranoid);
+        assert!("fast".parse::<KdfProfile>().is_err());
+
+        // Any machine beats a zero target on the first try, so the floor comes back
+        let (params, _) = calibrate_kdf(Duration::ZERO, 1024 * 1024).unwrap();
+        assert_eq!(params, KdfProfile::Interactive.params());
+    }
+
+    #[test]
+    fn test_wrong_passphrase_is_an_error() {
+        let encoded = encrypt_keystore(b"secret", b"pass", Argon2Params::default()).unwrap();
+        assert!(matches!(
+            decrypt_keystore(&encoded, b"nope"),
+            Err(VeroxError::WrongPassphrase)
+        ));
+        assert!(matches!(
+            decrypt_keystore(b"not base64!", b"pass"),
+            Err(VeroxError::CorruptKeystore(_))
+        ));
+    }
+
+    #[test]
+    fn test_reads_legacy_v0() {
+        let encoded = encrypt_v0(b"legacy", b"pass");
+        assert_eq!(read_header(&encoded).unwrap().version, 0);
+        assert_eq!(decrypt_keystore(&encoded, b"pass").unwrap(), b"legacy");
+    }
+
+    #[test]
+    fn test_rejects_unknown_version() {
+        let mut raw = general_purpose::STANDARD
+            .decode(encrypt_keystore(b"secret", b"pass", Argon2Params::default()).unwrap())
+            .unwrap();
+        raw[KEYSTORE_MAGIC.len()] = 99;
+        let encoded = general_purpose::STANDARD.encode(raw).into_bytes();
+        assert!(matches!(read_header(&encoded), Err(VeroxError::UnsupportedVersion(_))));
+    }
+
+    #[test]
+    fn test_rejects_excessive_costs() {
+        let raw = general_purpose::STANDARD
+            .decode(encrypt_keystore(b"secret", b"pass", Argon2Params::default()).unwrap())
+            .unwrap();
+        // m_cost, t_cost and p_cost follow the magic, version and KDF id
+        let start = KEYSTORE_MAGIC.len() + 2;
+        for field in 0..3 {
+            let mut hostile = raw.clone();
+            let at = start + field * 4;
+            hostile[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            let encoded = general_purpose::STANDARD.encode(hostile).into_bytes();
+            assert!(matches!(read_header(&encoded), Err(VeroxError::CorruptKeystore(_))));
+            assert!(matches!(decrypt_keystore(&encoded, b"pass"), Err(VeroxError::CorruptKeystore(_))));
+        }
+    }
 }
@@ -0,0 +1,445 @@
+//! EIP-1193 provider requests
+//!
+//! Dapps talk to the wallet through `request({ method, params })`. [`ProviderRequest`]
+//! parses the methods Verox answers itself (accounts, chain, signing, chain and token
+//! management); any other method on the read-only list is forwarded unchanged to the
+//! selected network's RPC by [`proxy`], and everything else is refused with
+//! [`UNSUPPORTED_METHOD`]. Failures carry the numeric codes of EIP-1193 and EIP-1474
+//! so the extension can hand them to the page as they are. Which page may make which
+//! request is decided by its grant in [`crate::permissions`].
+
+use ethers::providers::{JsonRpcClient, RpcError};
+use ethers::types::Address;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+
+use crate::error::VeroxError;
+use crate::message;
+use crate::network::{NativeCurrency, Network};
+use crate::permissions;
+use crate::provider::RpcProvider;
+use crate::transaction::{self, TxRequest};
+
+/// The user rejected the request
+pub const USER_REJECTED: i64 = 4001;
+/// The method or account has not been authorized by the user
+pub const UNAUTHORIZED: i64 = 4100;
+/// The provider does not support the method
+pub const UNSUPPORTED_METHOD: i64 = 4200;
+/// The chain has not been added to the wallet (EIP-3326)
+pub const UNRECOGNIZED_CHAIN: i64 = 4902;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+/// The node or wallet refused the transaction (EIP-1474)
+pub const TRANSACTION_REJECTED: i64 = -32003;
+
+/// Methods forwarded to the node as they are. They read chain state and never
+/// touch keys, so they need no approval.
+pub const READ_ONLY_METHODS: &[&str] = &[
+    "eth_blockNumber",
+    "eth_call",
+    "eth_estimateGas",
+    "eth_feeHistory",
+    "eth_gasPrice",
+    "eth_getBalance",
+    "eth_getBlockByHash",
+    "eth_getBlockByNumber",
+    "eth_getBlockTransactionCountByHash",
+    "eth_getBlockTransactionCountByNumber",
+    "eth_getCode",
+    "eth_getLogs",
+    "eth_getProof",
+    "eth_getStorageAt",
+    "eth_getTransactionByHash",
+    "eth_getTransactionCount",
+    "eth_getTransactionReceipt",
+    "eth_maxPriorityFeePerGas",
+    "eth_syncing",
+    "net_version",
+    "web3_clientVersion",
+];
+
+/// An error as the page sees it: `{ code, message, data }`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProviderError {
+    pub code: i64,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl ProviderError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        ProviderError { code, message: message.into(), data: None }
+    }
+
+    pub fn user_rejected() -> Self {
+        ProviderError::new(USER_REJECTED, "User rejected the request")
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        ProviderError::new(UNAUTHORIZED, message)
+    }
+
+    pub fn invalid_params(message: impl Into<String>) -> Self {
+        ProviderError::new(INVALID_PARAMS, message)
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl From<VeroxError> for ProviderError {
+    fn from(e: VeroxError) -> Self {
+        let code = match e {
+            VeroxError::WrongPassphrase
+            | VeroxError::WalletNotFound(_)
+            | VeroxError::Locked
+            | VeroxError::Unauthorized(_) => UNAUTHORIZED,
+            VeroxError::InvalidArgument(_) | VeroxError::Parse(_) | VeroxError::InvalidMnemonic(_) => INVALID_PARAMS,
+            VeroxError::FeeTooHigh(_) => TRANSACTION_REJECTED,
+            _ => INTERNAL_ERROR,
+        };
+        // Verox's own code goes along so the extension can still tell them apart
+        ProviderError {
+            code,
+            message: e.to_string(),
+            data: Some(json!({ "reason": e.code() })),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProviderRequest {
+    RequestAccounts,
+    Accounts,
+    ChainId,
+    SendTransaction(Box<TxRequest>),
+    PersonalSign { address: Address, message: Vec<u8> },
+    SignTypedData { address: Address, typed_data: Value },
+    SwitchChain(u64),
+    AddChain(Network),
+    WatchAsset { address: Address, symbol: String, decimals: u8, image: Option<String> },
+    RequestPermissions,
+    GetPermissions,
+    RevokePermissions,
+    /// A read-only method for the node
+    Proxy { method: String, params: Value },
+}
+
+impl ProviderRequest {
+    pub fn parse(method: &str, params: &Value) -> Result<Self, ProviderError> {
+        let request = match method {
+            "eth_requestAccounts" => ProviderRequest::RequestAccounts,
+            "eth_accounts" => ProviderRequest::Accounts,
+            "eth_chainId" => ProviderRequest::ChainId,
+            "eth_sendTransaction" => {
+                // A null or empty `from` is as missing as no `from` at all
+                let tx = TxRequest::from_json(param(params, 0, "transaction")?)?;
+                if tx.from.is_none() {
+                    return Err(ProviderError::invalid_params("transaction has no from address"));
+                }
+                ProviderRequest::SendTransaction(Box::new(tx))
+            }
+            // [message, address]; the message is hex bytes or plain text
+            "personal_sign" => ProviderRequest::PersonalSign {
+                message: message::decode_message(string_param(params, 0, "message")?),
+                address: address_param(params, 1)?,
+            },
+            // [address, typed data]; the typed data usually arrives as a JSON string
+            "eth_signTypedData_v4" => ProviderRequest::SignTypedData {
+                address: address_param(params, 0)?,
+                typed_data: match param(params, 1, "typed data")? {
+                    Value::String(json) => serde_json::from_str(json)
+                        .map_err(|e| ProviderError::invalid_params(format!("invalid typed data: {}", e)))?,
+                    typed_data => typed_data.clone(),
+                },
+            },
+            "wallet_switchEthereumChain" => {
+                ProviderRequest::SwitchChain(parse_chain_id(&param(params, 0, "chain")?["chainId"])?)
+            }
+            "wallet_addEthereumChain" => ProviderRequest::AddChain(parse_chain(param(params, 0, "chain")?)?),
+            "wallet_watchAsset" => parse_watch_asset(params)?,
+            "wallet_getPermissions" => ProviderRequest::GetPermissions,
+            // [{ eth_accounts: {} }], the only permission there is (EIP-2255)
+            "wallet_requestPermissions" | "wallet_revokePermissions" => {
+                let requested = param(params, 0, "permissions")?
+                    .as_object()
+                    .ok_or_else(|| ProviderError::invalid_params("permissions must be an object"))?;
+                if let Some(unknown) = requested.keys().find(|name| *name != permissions::ACCOUNTS_PERMISSION) {
+                    return Err(ProviderError::invalid_params(format!("unknown permission {}", unknown)));
+                }
+                if method == "wallet_requestPermissions" {
+                    ProviderRequest::RequestPermissions
+                } else {
+                    ProviderRequest::RevokePermissions
+                }
+            }
+            method if READ_ONLY_METHODS.contains(&method) => ProviderRequest::Proxy {
+                method: method.to_string(),
+                params: if params.is_null() { json!([]) } else { params.clone() },
+            },
+            method => {
+                return Err(ProviderError::new(
+                    UNSUPPORTED_METHOD,
+                    format!("The method {} is not supported", method),
+                ))
+            }
+        };
+        Ok(request)
+    }
+
+    /// Whether the user has to approve the request in the extension first
+    pub fn needs_approval(&self) -> bool {
+        !matches!(
+            self,
+            ProviderRequest::Accounts
+                | ProviderRequest::ChainId
+                | ProviderRequest::Proxy { .. }
+                | ProviderRequest::GetPermissions
+                | ProviderRequest::RevokePermissions
+        )
+    }
+
+    /// Whether the origin needs a grant. Without one a page can only learn the
+    /// chain, see that it has no accounts, and ask to connect.
+    pub fn needs_grant(&self) -> bool {
+        !matches!(
+            self,
+            ProviderRequest::RequestAccounts
+                | ProviderRequest::Accounts
+                | ProviderRequest::ChainId
+                | ProviderRequest::RequestPermissions
+                | ProviderRequest::GetPermissions
+                | ProviderRequest::RevokePermissions
+        )
+    }
+
+    /// Whether answering it takes the account's key
+    pub fn needs_key(&self) -> bool {
+        matches!(
+            self,
+            ProviderRequest::SendTransaction(_)
+                | ProviderRequest::PersonalSign { .. }
+                | ProviderRequest::SignTypedData { .. }
+        )
+    }
+}
+
+/// Forward a read-only request to the node. The node's own error code and data are
+/// passed through.
+pub async fn proxy<P: JsonRpcClient>(rpc: &RpcProvider<P>, method: &str, params: &Value) -> Result<Value, ProviderError> {
+    rpc.inner().request::<_, Value>(method, params).await.map_err(|e| match e.as_error_response() {
+        Some(error) => ProviderError {
+            code: error.code,
+            message: error.message.clone(),
+            data: error.data.clone(),
+        },
+        None => ProviderError::new(INTERNAL_ERROR, format!("RPC error: {}", e)),
+    })
+}
+
+/// Chain IDs as dapps expect them, `0x`-prefixed hex
+pub fn chain_id_hex(chain_id: u64) -> String {
+    format!("{:#x}", chain_id)
+}
+
+/// A `0x` hex chain ID, as EIP-3085 and EIP-3326 require; plain numbers are accepted too
+pub fn parse_chain_id(value: &Value) -> Result<u64, ProviderError> {
+    let chain_id = match value {
+        Value::String(hex) => hex
+            .strip_prefix("0x")
+            .and_then(|digits| u64::from_str_radix(digits, 16).ok()),
+        Value::Number(n) => n.as_u64(),
+        _ => None,
+    };
+    chain_id
+        .filter(|chain_id| (1..=transaction::MAX_CHAIN_ID).contains(chain_id))
+        .ok_or_else(|| ProviderError::invalid_params(format!("invalid chain ID {}", value)))
+}
+
+fn param<'a>(params: &'a Value, index: usize, name: &str) -> Result<&'a Value, ProviderError> {
+    params
+        .get(index)
+        .filter(|value| !value.is_null())
+        .ok_or_else(|| ProviderError::invalid_params(format!("missing {} parameter", name)))
+}
+
+fn string_param<'a>(params: &'a Value, index: usize, name: &str) -> Result<&'a str, ProviderError> {
+    param(params, index, name)?
+        .as_str()
+        .ok_or_else(|| ProviderError::invalid_params(format!("{} must be a string", name)))
+}
+
+fn address_param(params: &Value, index: usize) -> Result<Address, ProviderError> {
+    let address = string_param(params, index, "address")?;
+    address
+        .parse()
+        .map_err(|_| ProviderError::invalid_params(format!("invalid address '{}'", address)))
+}
+
+/// `wallet_addEthereumChain`'s parameter (EIP-3085) as a network
+fn parse_chain(chain: &Value) -> Result<Network, ProviderError> {
+    let strings = |key: &str| -> Vec<String> {
+        chain[key]
+            .as_array()
+            .map(|urls| urls.iter().filter_map(Value::as_str).map(str::to_string).collect())
+            .unwrap_or_default()
+    };
+    let native_currency = match &chain["nativeCurrency"] {
+        Value::Null => NativeCurrency::ether(),
+        currency => serde_json::from_value(currency.clone())
+            .map_err(|e| ProviderError::invalid_params(format!("invalid nativeCurrency: {}", e)))?,
+    };
+    Ok(Network {
+        chain_id: parse_chain_id(&chain["chainId"])?,
+        name: chain["chainName"]
+            .as_str()
+            .ok_or_else(|| ProviderError::invalid_params("missing chainName"))?
+            .to_string(),
+        rpc_urls: strings("rpcUrls"),
+        native_currency,
+        explorer_url: strings("blockExplorerUrls").into_iter().next(),
+    })
+}
+
+/// `wallet_watchAsset`'s parameter (EIP-747). It is an object, but some dapps wrap
+/// it in an array.
+fn parse_watch_asset(params: &Value) -> Result<ProviderRequest, ProviderError> {
+    let asset = if params.is_array() { param(params, 0, "asset")? } else { params };
+    if asset["type"].as_str() != Some("ERC20") {
+        return Err(ProviderError::invalid_params(format!(
+            "asset type {} is not supported, only ERC20",
+            asset["type"]
+        )));
+    }
+    let options = &asset["options"];
+    let address = options["address"]
+        .as_str()
+        .and_then(|address| address.parse().ok())
+        .ok_or_else(|| ProviderError::invalid_params("invalid asset address"))?;
+    let decimals = match &options["decimals"] {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+    .and_then(|decimals| u8::try_from(decimals).ok())
+    .ok_or_else(|| ProviderError::invalid_params("invalid asset decimals"))?;
+    Ok(ProviderRequest::WatchAsset {
+        address,
+        symbol: options["symbol"]
+            .as_str()
+            .ok_or_else(|| ProviderError::invalid_params("missing asset symbol"))?
+            .to_string(),
+        decimals,
+        image: options["image"].as_str().map(str::to_string),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::providers::{JsonRpcError, MockResponse, Provider};
+    use ethers::types::U256;
+
+    #[test]
+    fn test_parse() {
+        let from = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266";
+        let address: Address = from.parse().unwrap();
+
+        assert_eq!(ProviderRequest::parse("eth_chainId", &Value::Null).unwrap(), ProviderRequest::ChainId);
+        match ProviderRequest::parse("eth_sendTransaction", &json!([{"from": from, "value": "0x10"}])).unwrap() {
+            ProviderRequest::SendTransaction(tx) => assert_eq!((tx.from, tx.value), (Some(address), U256::from(16))),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            ProviderRequest::parse("personal_sign", &json!(["0x6869", from])).unwrap(),
+            ProviderRequest::PersonalSign { address, message: b"hi".to_vec() }
+        );
+        assert_eq!(
+            ProviderRequest::parse("eth_signTypedData_v4", &json!([from, "{\"a\":1}"])).unwrap(),
+            ProviderRequest::SignTypedData { address, typed_data: json!({"a": 1}) }
+        );
+        assert_eq!(
+            ProviderRequest::parse("wallet_switchEthereumChain", &json!([{"chainId": "0xaa36a7"}])).unwrap(),
+            ProviderRequest::SwitchChain(11155111)
+        );
+        match ProviderRequest::parse(
+            "wallet_addEthereumChain",
+            &json!([{"chainId": "0x2105", "chainName": "Base", "rpcUrls": ["https://mainnet.base.org"],
+                     "blockExplorerUrls": ["https://basescan.org"]}]),
+        )
+        .unwrap()
+        {
+            ProviderRequest::AddChain(network) => {
+                assert_eq!((network.chain_id, network.name.as_str()), (8453, "Base"));
+                assert_eq!(network.explorer_url.as_deref(), Some("https://basescan.org"));
+                assert_eq!(network.native_currency, NativeCurrency::ether());
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            ProviderRequest::parse(
+                "wallet_watchAsset",
+                &json!({"type": "ERC20", "options": {"address": from, "symbol": "TKN", "decimals": 18}})
+            ),
+            Ok(ProviderRequest::WatchAsset { decimals: 18, .. })
+        ));
+        assert_eq!(
+            ProviderRequest::parse("eth_blockNumber", &Value::Null).unwrap(),
+            ProviderRequest::Proxy { method: "eth_blockNumber".to_string(), params: json!([]) }
+        );
+
+        let code = |method: &str, params: Value| ProviderRequest::parse(method, &params).unwrap_err().code;
+        assert_eq!(code("eth_sign", json!([from, "0x00"])), UNSUPPORTED_METHOD);
+        assert_eq!(code("eth_sendTransaction", json!([{"to": from}])), INVALID_PARAMS);
+        assert_eq!(code("eth_sendTransaction", json!([{"from": null, "to": from}])), INVALID_PARAMS);
+        assert_eq!(code("eth_sendTransaction", json!([{"from": "", "to": from}])), INVALID_PARAMS);
+        assert_eq!(code("personal_sign", json!(["0x6869", "nope"])), INVALID_PARAMS);
+        assert_eq!(code("wallet_switchEthereumChain", json!([{"chainId": "1"}])), INVALID_PARAMS);
+        // Past EIP-2294's bound a legacy signature's `v` would overflow
+        assert_eq!(
+            ProviderRequest::parse("wallet_switchEthereumChain", &json!([{"chainId": "0x3fffffffffffffdb"}])).unwrap(),
+            ProviderRequest::SwitchChain(transaction::MAX_CHAIN_ID)
+        );
+        assert_eq!(code("wallet_switchEthereumChain", json!([{"chainId": "0x3fffffffffffffdc"}])), INVALID_PARAMS);
+        let huge = json!([{"chainId": "0xffffffffffffffff", "chainName": "Huge", "rpcUrls": ["https://rpc.example.org"]}]);
+        assert_eq!(code("wallet_addEthereumChain", huge), INVALID_PARAMS);
+        assert_eq!(code("wallet_watchAsset", json!({"type": "ERC721", "options": {}})), INVALID_PARAMS);
+
+        assert_eq!(
+            ProviderRequest::parse("wallet_requestPermissions", &json!([{"eth_accounts": {}}])).unwrap(),
+            ProviderRequest::RequestPermissions
+        );
+        assert_eq!(code("wallet_requestPermissions", json!([{"snap_dialog": {}}])), INVALID_PARAMS);
+
+        assert!(!ProviderRequest::ChainId.needs_approval() && !ProviderRequest::ChainId.needs_grant());
+        assert!(ProviderRequest::RequestAccounts.needs_approval() && !ProviderRequest::RequestAccounts.needs_key());
+        assert!(ProviderRequest::Proxy { method: "eth_call".to_string(), params: json!([]) }.needs_grant());
+    }
+
+    #[tokio::test]
+    async fn test_proxy() {
+        let (provider, mock) = Provider::mocked();
+        let rpc = RpcProvider::new(provider, crate::network::builtin_networks().remove(0));
+        mock.push(U256::from(0x1234)).unwrap();
+        assert_eq!(proxy(&rpc, "eth_blockNumber", &json!([])).await.unwrap(), json!("0x1234"));
+
+        // The node's error reaches the page unchanged
+        mock.push_response(MockResponse::Error(JsonRpcError {
+            code: 3,
+            message: "execution reverted".to_string(),
+            data: Some(json!("0x08c379a0")),
+        }));
+        let error = proxy(&rpc, "eth_call", &json!([{}, "latest"])).await.unwrap_err();
+        assert_eq!((error.code, error.data), (3, Some(json!("0x08c379a0"))));
+
+        let error = ProviderError::from(VeroxError::WrongPassphrase);
+        assert_eq!((error.code, error.data), (UNAUTHORIZED, Some(json!({"reason": "WRONG_PASSPHRASE"}))));
+        assert_eq!(chain_id_hex(31337), "0x7a69");
+    }
+}
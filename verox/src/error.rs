@@ -0,0 +1,75 @@
+//! Error type shared by the crypto, wallet, biometric and provider modules
+//!
+//! Every variant maps to a stable string code (see [`VeroxError::code`]) that the
+//! native host forwards to the extension, so the UI can react to e.g. a wrong
+//! passphrase without parsing messages.
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum VeroxError {
+    /// Decryption failed authentication; the user can retry
+    #[error("Wrong passphrase")]
+    WrongPassphrase,
+    /// The keystore file is truncated, not base64 or otherwise malformed
+    #[error("Keystore corrupted: {0}")]
+    CorruptKeystore(String),
+    /// The file uses a container version, KDF or cipher this build can't read
+    #[error("Unsupported keystore format: {0}")]
+    UnsupportedVersion(String),
+    #[error("Wallet not found: {0}")]
+    WalletNotFound(String),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Parse error: {0}")]
+    Parse(String),
+    #[error("Biometric error: {0}")]
+    Biometric(String),
+    /// A caller-supplied option or parameter is out of range
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
+    /// A recovery phrase has an unknown word, wrong length or bad checksum
+    #[error("Invalid mnemonic: {0}")]
+    InvalidMnemonic(String),
+    /// The node was unreachable, on the wrong chain or returned an error
+    #[error("RPC error: {0}")]
+    Rpc(String),
+    /// A fee is far above what the network has been charging; needs an explicit override
+    #[error("Fee too high: {0}")]
+    FeeTooHigh(String),
+    /// No passphrase was given and the session agent doesn't hold the key
+    #[error("Wallet is locked")]
+    Locked,
+    /// The sender of a request may not act on the wallet, e.g. a page without a grant
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+}
+
+impl VeroxError {
+    /// Stable machine-readable code for the UI
+    pub fn code(&self) -> &'static str {
+        match self {
+            VeroxError::WrongPassphrase => "WRONG_PASSPHRASE",
+            VeroxError::CorruptKeystore(_) => "CORRUPT_KEYSTORE",
+            VeroxError::UnsupportedVersion(_) => "UNSUPPORTED_VERSION",
+            VeroxError::WalletNotFound(_) => "WALLET_NOT_FOUND",
+            VeroxError::Io(_) => "IO_ERROR",
+            VeroxError::Parse(_) => "PARSE_ERROR",
+            VeroxError::Biometric(_) => "BIOMETRIC_ERROR",
+            VeroxError::InvalidArgument(_) => "INVALID_ARGUMENT",
+            VeroxError::InvalidMnemonic(_) => "INVALID_MNEMONIC",
+            VeroxError::Rpc(_) => "RPC_ERROR",
+            VeroxError::FeeTooHigh(_) => "FEE_TOO_HIGH",
+            VeroxError::Locked => "WALLET_LOCKED",
+            VeroxError::Unauthorized(_) => "UNAUTHORIZED",
+        }
+    }
+}
+
+impl From<serde_json::Error> for VeroxError {
+    fn from(e: serde_json::Error) -> Self {
+        VeroxError::Parse(e.to_string())
+    }
+}
+
+pub type Result<T> = std::result::Result<T, VeroxError>;
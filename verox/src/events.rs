@@ -0,0 +1,278 @@
+//! EIP-1193 events for connected pages
+//!
+//! The native host watches the state pages can observe: the selected chain, whether
+//! its RPC answers, and which accounts each connected origin can see. That last part
+//! is empty while the wallet is locked. [`diff`] turns two snapshots into the events
+//! to push:
+//!
+//! - `chainChanged` with the new chain ID, as hex
+//! - `connect` with `{ chainId }`, and `disconnect` with a 4900 error object
+//! - `accountsChanged` with `{ origin, accounts }`; the extension delivers
+//!   `accounts` to that origin's pages only
+//!
+//! Locking writes a marker file to the data directory, so any process can tell
+//! whether the wallet is locked. Whatever changes what pages see (locking, unlocking,
+//! switching networks, granting or revoking) then [`notify`]s every running host: each
+//! [`subscribe`]s through a socket under `hosts/`, works out the new snapshot and
+//! pushes the difference. `verox lock` reaches the hosts of every browser this way.
+//! Only whether the RPC answers has nothing to announce it, so hosts check that on a timer.
+
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+
+use crate::eip1193::chain_id_hex;
+use crate::error::Result;
+use crate::utils;
+
+/// Present while the wallet is locked
+pub const LOCKED_FILE: &str = "locked";
+
+/// Directory of the hosts' notification sockets, one per running host
+pub const HOSTS_DIR: &str = "hosts";
+
+/// How often a host whose client hasn't asked for events yet looks again
+pub const WATCH_INTERVAL_SECS: u64 = 1;
+
+/// How long [`notify`] waits on a host before moving on to the next
+const NOTIFY_TIMEOUT_MILLIS: u64 = 500;
+
+/// How often the host checks that the selected network's RPC answers
+pub const CONNECTIVITY_INTERVAL_SECS: u64 = 15;
+
+/// How long the RPC gets to answer before the network counts as unreachable
+pub const CONNECTIVITY_TIMEOUT_SECS: u64 = 5;
+
+/// EIP-1193 code for a provider disconnected from every chain
+pub const DISCONNECTED: i64 = 4900;
+
+pub fn locked_path(dir: &Path) -> PathBuf {
+    dir.join(LOCKED_FILE)
+}
+
+pub fn is_locked(dir: &Path) -> bool {
+    locked_path(dir).exists()
+}
+
+/// Lock or unlock the wallet for connected pages, notifying the hosts if that changed
+pub fn set_locked(dir: &Path, locked: bool) -> Result<()> {
+    let path = locked_path(dir);
+    let changed = if locked {
+        let changed = !path.exists();
+        utils::create_private_dir(dir)?;
+        fs::write(path, b"")?;
+        changed
+    } else {
+        match fs::remove_file(path) {
+            Ok(()) => true,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e.into()),
+        }
+    };
+    if changed {
+        notify(dir, if locked { "locked" } else { "unlocked" });
+    }
+    Ok(())
+}
+
+/// Tell every running host that what pages see may have changed. Best effort: a host
+/// that doesn't answer is skipped, and the socket of one that is gone is removed.
+#[cfg(unix)]
+pub fn notify(dir: &Path, change: &str) {
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    let Ok(entries) = fs::read_dir(dir.join(HOSTS_DIR)) else { return };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.extension().and_then(|ext| ext.to_str()) != Some("sock") {
+            continue;
+        }
+        match UnixStream::connect(&path) {
+            Ok(mut stream) => {
+                let _ = stream.set_write_timeout(Some(Duration::from_millis(NOTIFY_TIMEOUT_MILLIS)));
+                let _ = writeln!(stream, "{}", change);
+            }
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                let _ = fs::remove_file(&path);
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_dir: &Path, _change: &str) {}
+
+/// A host's registration for [`notify`]; dropping it removes the socket
+pub struct Subscription {
+    path: PathBuf,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Listen for [`notify`] on a socket of this process's own under `hosts/`. Each
+/// notification arrives on the receiver as the name of the change.
+#[cfg(unix)]
+pub fn subscribe(dir: &Path) -> Result<(Subscription, Receiver<String>)> {
+    use std::io::{BufRead, BufReader, Read};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixListener;
+
+    let hosts = dir.join(HOSTS_DIR);
+    utils::create_private_dir(&hosts)?;
+    let path = hosts.join(format!("{}.sock", std::process::id()));
+    // A previous process with the same ID is long gone
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+
+    let (sender, receiver) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let mut change = String::new();
+            if BufReader::new(stream.take(256)).read_line(&mut change).is_ok()
+                && sender.send(change.trim().to_string()).is_err()
+            {
+                break;
+            }
+        }
+    });
+    Ok((Subscription { path }, receiver))
+}
+
+#[cfg(not(unix))]
+pub fn subscribe(_dir: &Path) -> Result<(Subscription, Receiver<String>)> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "host notifications need Unix domain sockets").into())
+}
+
+/// What connected pages can observe
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProviderState {
+    pub chain_id: u64,
+    /// Whether the selected network's RPC answered the last check
+    pub connected: bool,
+    /// Accounts each connected origin can see, by origin
+    pub accounts: BTreeMap<String, Vec<String>>,
+}
+
+/// Events taking pages from `old` to `new`, as `(event, data)` pairs
+pub fn diff(old: &ProviderState, new: &ProviderState) -> Vec<(&'static str, Value)> {
+    let mut events = Vec::new();
+    match (old.connected, new.connected) {
+        (true, false) => events.push((
+            "disconnect",
+            json!({ "code": DISCONNECTED, "message": "The wallet cannot reach the network" }),
+        )),
+        (false, true) => events.push(("connect", json!({ "chainId": chain_id_hex(new.chain_id) }))),
+        _ => {}
+    }
+    if old.chain_id != new.chain_id {
+        events.push(("chainChanged", json!(chain_id_hex(new.chain_id))));
+    }
+
+    // A revoked origin is told it has no accounts left
+    let origins: BTreeSet<&String> = old.accounts.keys().chain(new.accounts.keys()).collect();
+    for origin in origins {
+        let before = old.accounts.get(origin).map(Vec::as_slice).unwrap_or_default();
+        let after = new.accounts.get(origin).map(Vec::as_slice).unwrap_or_default();
+        if before != after {
+            events.push(("accountsChanged", json!({ "origin": origin, "accounts": after })));
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(chain_id: u64, connected: bool, accounts: &[(&str, &[&str])]) -> ProviderState {
+        ProviderState {
+            chain_id,
+            connected,
+            accounts: accounts
+                .iter()
+                .map(|(origin, accounts)| (origin.to_string(), accounts.iter().map(|a| a.to_string()).collect()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff() {
+        let unlocked = state(1, true, &[("https://a.org", &["0xA"]), ("https://b.org", &["0xA", "0xB"])]);
+        assert!(diff(&unlocked, &unlocked).is_empty());
+
+        // Locking empties every origin's accounts
+        let locked = state(1, true, &[("https://a.org", &[]), ("https://b.org", &[])]);
+        assert_eq!(
+            diff(&unlocked, &locked),
+            vec![
+                ("accountsChanged", json!({"origin": "https://a.org", "accounts": []})),
+                ("accountsChanged", json!({"origin": "https://b.org", "accounts": []})),
+            ]
+        );
+
+        // Revoking drops the origin from the snapshot altogether
+        let revoked = state(1, true, &[("https://b.org", &["0xA", "0xB"])]);
+        assert_eq!(
+            diff(&unlocked, &revoked),
+            vec![("accountsChanged", json!({"origin": "https://a.org", "accounts": []}))]
+        );
+
+        let switched = state(10, false, &[("https://a.org", &["0xA"]), ("https://b.org", &["0xA", "0xB"])]);
+        assert_eq!(
+            diff(&unlocked, &switched),
+            vec![
+                ("disconnect", json!({"code": 4900, "message": "The wallet cannot reach the network"})),
+                ("chainChanged", json!("0xa")),
+            ]
+        );
+        assert_eq!(diff(&switched, &unlocked)[0], ("connect", json!({"chainId": "0x1"})));
+    }
+
+    #[test]
+    fn test_lock_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(!is_locked(dir.path()));
+        set_locked(dir.path(), true).unwrap();
+        assert!(is_locked(dir.path()));
+        set_locked(dir.path(), false).unwrap();
+        set_locked(dir.path(), false).unwrap();
+        assert!(!is_locked(dir.path()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_notify_subscribers() {
+        use std::time::Duration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (subscription, changes) = subscribe(dir.path()).unwrap();
+        let next = || changes.recv_timeout(Duration::from_secs(5)).unwrap();
+
+        set_locked(dir.path(), true).unwrap();
+        assert_eq!(next(), "locked");
+        // Only actual changes are announced
+        set_locked(dir.path(), true).unwrap();
+        set_locked(dir.path(), false).unwrap();
+        assert_eq!(next(), "unlocked");
+        notify(dir.path(), "network");
+        assert_eq!(next(), "network");
+
+        // A host that went away without cleaning up is forgotten
+        let stale = dir.path().join(HOSTS_DIR).join("1.sock");
+        drop(std::os::unix::net::UnixListener::bind(&stale).unwrap());
+        notify(dir.path(), "permissions");
+        assert!(!stale.exists());
+        drop(subscription);
+        assert_eq!(fs::read_dir(dir.path().join(HOSTS_DIR)).unwrap().count(), 0);
+    }
+}
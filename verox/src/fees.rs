@@ -0,0 +1,491 @@
+//! EIP-1559 fee estimation
+//!
+//! One `eth_feeHistory` call over the last [`HISTORY_BLOCKS`] blocks gives base fees
+//! and priority-fee percentiles. Each [`FeeSpeed`] takes the median of its percentile
+//! over the most recent blocks as the tip, and sets the max fee high enough to
+//! survive a few blocks of base fee increases. Chains without EIP-1559 (no base fee
+//! in the history, or no `eth_feeHistory` at all) fall back to `eth_gasPrice`.
+//!
+//! The same history sets a safety cap: a max fee more than [`CAP_MULTIPLIER`] times
+//! what the window's medians call for is refused unless the caller overrides it.
+
+use ethers::providers::{JsonRpcClient, Middleware};
+use ethers::types::{BlockNumber, FeeHistory, U256};
+use ethers::utils::format_units;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Result, VeroxError};
+use crate::provider::RpcProvider;
+use crate::transaction::{TxRequest, TxType};
+
+/// Blocks of history the medians and the cap are computed over (~10 minutes on mainnet)
+pub const HISTORY_BLOCKS: u64 = 50;
+
+/// Most recent blocks whose tips set the presets
+pub const RECENT_BLOCKS: usize = 10;
+
+/// Fees above this multiple of the median-based reference are refused
+pub const CAP_MULTIPLIER: u64 = 3;
+
+/// Seconds per block used for time estimates (mainnet's slot time)
+pub const BLOCK_TIME_SECS: u64 = 12;
+
+/// Tip used when every recent block was empty and the history has no rewards
+pub const DEFAULT_PRIORITY_FEE: u64 = 1_000_000_000;
+
+const SPEEDS: [FeeSpeed; 3] = [FeeSpeed::Slow, FeeSpeed::Normal, FeeSpeed::Fast];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeeSpeed {
+    Slow,
+    Normal,
+    Fast,
+}
+
+impl FeeSpeed {
+    /// Priority-fee percentile requested from `eth_feeHistory`
+    fn percentile(self) -> f64 {
+        match self {
+            FeeSpeed::Slow => 10.0,
+            FeeSpeed::Normal => 50.0,
+            FeeSpeed::Fast => 90.0,
+        }
+    }
+
+    /// Max fee as a multiple of the next base fee, in percent. The base fee rises
+    /// at most 12.5% per block, so 150% covers about three full blocks.
+    fn base_fee_percent(self) -> u64 {
+        match self {
+            FeeSpeed::Slow => 125,
+            FeeSpeed::Normal => 150,
+            FeeSpeed::Fast => 200,
+        }
+    }
+
+    /// Blocks until inclusion we expect at this tip
+    fn expected_blocks(self) -> u64 {
+        match self {
+            FeeSpeed::Slow => 6,
+            FeeSpeed::Normal => 3,
+            FeeSpeed::Fast => 1,
+        }
+    }
+
+    /// Gas price as a percentage of `eth_gasPrice` on legacy chains
+    fn gas_price_percent(self) -> u64 {
+        match self {
+            FeeSpeed::Slow => 100,
+            FeeSpeed::Normal => 110,
+            FeeSpeed::Fast => 125,
+        }
+    }
+}
+
+impl FromStr for FeeSpeed {
+    type Err = VeroxError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "slow" => Ok(FeeSpeed::Slow),
+            "normal" | "medium" => Ok(FeeSpeed::Normal),
+            "fast" => Ok(FeeSpeed::Fast),
+            _ => Err(VeroxError::InvalidArgument(format!(
+                "unknown fee speed '{}', expected slow, normal or fast",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for FeeSpeed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FeeSpeed::Slow => "slow",
+            FeeSpeed::Normal => "normal",
+            FeeSpeed::Fast => "fast",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Fees for one speed. On legacy chains both fields hold the gas price.
+#[derive(Debug, Clone, Serialize)]
+pub struct FeePreset {
+    pub speed: FeeSpeed,
+    #[serde(serialize_with = "decimal")]
+    pub max_fee_per_gas: U256,
+    #[serde(serialize_with = "decimal")]
+    pub max_priority_fee_per_gas: U256,
+    pub expected_blocks: u64,
+    pub expected_seconds: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeeEstimate {
+    /// False on chains without a base fee; presets are then gas prices
+    pub eip1559: bool,
+    /// Base fee of the next block
+    #[serde(serialize_with = "decimal")]
+    pub base_fee_per_gas: U256,
+    #[serde(serialize_with = "decimal")]
+    pub median_base_fee: U256,
+    #[serde(serialize_with = "decimal")]
+    pub median_priority_fee: U256,
+    /// Highest max fee per gas accepted without an explicit override
+    #[serde(serialize_with = "decimal")]
+    pub fee_cap: U256,
+    pub presets: Vec<FeePreset>,
+}
+
+impl FeeEstimate {
+    pub fn preset(&self, speed: FeeSpeed) -> &FeePreset {
+        self.presets
+            .iter()
+            .find(|preset| preset.speed == speed)
+            .expect("every speed has a preset")
+    }
+
+    /// Refuse a max fee (or legacy gas price) above the cap
+    pub fn check_cap(&self, max_fee_per_gas: U256) -> Result<()> {
+        if max_fee_per_gas > self.fee_cap {
+            return Err(VeroxError::FeeTooHigh(format!(
+                "{} gwei per gas is more than {}x the recent median; the limit is {} gwei",
+                gwei(max_fee_per_gas),
+                CAP_MULTIPLIER,
+                gwei(self.fee_cap)
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fill in the fee fields `request` leaves out with the preset for `speed`,
+    /// then check the resulting fee against the cap unless `allow_high_fee`
+    pub fn apply(&self, request: &mut TxRequest, speed: FeeSpeed, allow_high_fee: bool) -> Result<()> {
+        let preset = self.preset(speed);
+        let has_fees = request.gas_price.is_some()
+            || request.max_fee_per_gas.is_some()
+            || request.max_priority_fee_per_gas.is_some();
+        if !has_fees {
+            if self.eip1559 && request.tx_type != Some(TxType::Legacy) && request.tx_type != Some(TxType::Eip2930) {
+                request.max_fee_per_gas = Some(preset.max_fee_per_gas);
+                request.max_priority_fee_per_gas = Some(preset.max_priority_fee_per_gas);
+            } else {
+                request.gas_price = Some(preset.max_fee_per_gas);
+                if request.tx_type.is_none() && request.access_list.is_none() {
+                    request.tx_type = Some(TxType::Legacy);
+                }
+            }
+        } else if request.resolved_type() == TxType::Eip1559 {
+            // Only the tip was given: derive the max fee from it
+            if let (None, Some(tip)) = (request.max_fee_per_gas, request.max_priority_fee_per_gas) {
+                request.max_fee_per_gas = Some((preset.max_fee_per_gas - preset.max_priority_fee_per_gas).saturating_add(tip));
+            }
+            // Only the max fee was given: tip as much of it as the preset would
+            if let (Some(max_fee), None) = (request.max_fee_per_gas, request.max_priority_fee_per_gas) {
+                request.max_priority_fee_per_gas = Some(max_fee.min(preset.max_priority_fee_per_gas));
+            }
+        }
+
+        if !allow_high_fee {
+            let fee = request.max_fee_per_gas.or(request.gas_price).unwrap_or_default();
+            self.check_cap(fee)?;
+        }
+        Ok(())
+    }
+}
+
+/// Build an estimate from an `eth_feeHistory` result. `None` when the chain has
+/// no base fee, i.e. doesn't do EIP-1559.
+pub fn estimate_from_history(history: &FeeHistory) -> Option<FeeEstimate> {
+    // base_fee_per_gas has one more entry than there are blocks: the next block's
+    // The node's numbers can be anything, so the arithmetic on them saturates
+    let (next_base_fee, past_base_fees) = history.base_fee_per_gas.split_last()?;
+    if next_base_fee.is_zero() && past_base_fees.iter().all(U256::is_zero) {
+        return None;
+    }
+
+    // Empty blocks report all-zero rewards and would drag every tip to zero
+    let rewards: Vec<&Vec<U256>> = history
+        .reward
+        .iter()
+        .zip(history.gas_used_ratio.iter().chain(std::iter::repeat(&1.0)))
+        .filter(|(reward, ratio)| **ratio > 0.0 && reward.len() == SPEEDS.len())
+        .map(|(reward, _)| reward)
+        .collect();
+    let column = |blocks: &[&Vec<U256>], i: usize| median(blocks.iter().map(|reward| reward[i]).collect());
+    let recent = &rewards[rewards.len().saturating_sub(RECENT_BLOCKS)..];
+
+    let median_base_fee = median(past_base_fees.to_vec()).unwrap_or(*next_base_fee);
+    let median_priority_fee = column(&rewards, 1).unwrap_or_else(|| U256::from(DEFAULT_PRIORITY_FEE));
+    let median_fast_tip = column(&rewards, 2).unwrap_or(median_priority_fee);
+
+    let presets = SPEEDS
+        .iter()
+        .enumerate()
+        .map(|(i, &speed)| {
+            let tip = column(recent, i).unwrap_or_else(|| U256::from(DEFAULT_PRIORITY_FEE));
+            FeePreset {
+                speed,
+                max_fee_per_gas: (next_base_fee.saturating_mul(speed.base_fee_percent().into()) / 100).saturating_add(tip),
+                max_priority_fee_per_gas: tip,
+                expected_blocks: speed.expected_blocks(),
+                expected_seconds: speed.expected_blocks() * BLOCK_TIME_SECS,
+            }
+        })
+        .collect();
+
+    Some(FeeEstimate {
+        eip1559: true,
+        base_fee_per_gas: *next_base_fee,
+        median_base_fee,
+        median_priority_fee,
+        // The fast preset in a quiet market sits at 2x base fee plus a fast tip
+        fee_cap: median_base_fee
+            .saturating_mul(2.into())
+            .saturating_add(median_fast_tip)
+            .saturating_mul(CAP_MULTIPLIER.into()),
+        presets,
+    })
+}
+
+/// Presets for a chain without EIP-1559, from `eth_gasPrice`
+pub fn estimate_from_gas_price(gas_price: U256) -> FeeEstimate {
+    let presets = SPEEDS
+        .iter()
+        .map(|&speed| {
+            let price = gas_price.saturating_mul(speed.gas_price_percent().into()) / 100;
+            FeePreset {
+                speed,
+                max_fee_per_gas: price,
+                max_priority_fee_per_gas: price,
+                expected_blocks: speed.expected_blocks(),
+                expected_seconds: speed.expected_blocks() * BLOCK_TIME_SECS,
+            }
+        })
+        .collect();
+    FeeEstimate {
+        eip1559: false,
+        base_fee_per_gas: U256::zero(),
+        median_base_fee: gas_price,
+        median_priority_fee: U256::zero(),
+        fee_cap: gas_price.saturating_mul(CAP_MULTIPLIER.into()),
+        presets,
+    }
+}
+
+/// Query the network and estimate fees, falling back to `eth_gasPrice`
+pub async fn estimate_fees<P: JsonRpcClient>(rpc: &RpcProvider<P>) -> Result<FeeEstimate> {
+    let percentiles: Vec<f64> = SPEEDS.iter().map(|speed| speed.percentile()).collect();
+    let history = rpc
+        .inner()
+        .fee_history(HISTORY_BLOCKS, BlockNumber::Latest, &percentiles)
+        .await;
+    if let Some(estimate) = history.ok().as_ref().and_then(estimate_from_history) {
+        return Ok(estimate);
+    }
+    Ok(estimate_from_gas_price(rpc.gas_price().await?))
+}
+
+fn median(mut values: Vec<U256>) -> Option<U256> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort();
+    let mid = values.len() / 2;
+    Some(if values.len().is_multiple_of(2) {
+        // Halved first so two large values can't overflow
+        values[mid - 1] / 2 + values[mid] / 2 + (values[mid - 1] % 2 + values[mid] % 2) / 2
+    } else {
+        values[mid]
+    })
+}
+
+// Wei amounts go to the UI as decimal strings, like balances
+fn decimal<S: serde::Serializer>(value: &U256, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&value.to_string())
+}
+
+/// Wei as a decimal gwei string
+pub fn gwei(wei: U256) -> String {
+    format_units(wei, "gwei").unwrap_or_else(|_| wei.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{NativeCurrency, Network};
+    use ethers::providers::{MockProvider, MockResponse, Provider};
+    use ethers::types::U256;
+
+    const GWEI: u64 = 1_000_000_000;
+
+    fn gwei_u256(n: u64) -> U256 {
+        U256::from(n) * GWEI
+    }
+
+    /// `blocks` blocks at a 20 gwei base fee with tips of 1/2/5 gwei, the last one empty
+    fn quiet_history(blocks: usize, next_base_fee: u64) -> FeeHistory {
+        let mut base_fee_per_gas = vec![gwei_u256(20); blocks];
+        base_fee_per_gas.push(gwei_u256(next_base_fee));
+        let mut reward = vec![vec![gwei_u256(1), gwei_u256(2), gwei_u256(5)]; blocks];
+        let mut gas_used_ratio = vec![0.5; blocks];
+        reward[blocks - 1] = vec![U256::zero(); 3];
+        gas_used_ratio[blocks - 1] = 0.0;
+        FeeHistory {
+            base_fee_per_gas,
+            gas_used_ratio,
+            oldest_block: U256::from(100),
+            reward,
+        }
+    }
+
+    #[test]
+    fn test_presets_from_history() {
+        let estimate = estimate_from_history(&quiet_history(50, 20)).unwrap();
+        assert!(estimate.eip1559);
+        assert_eq!(estimate.median_base_fee, gwei_u256(20));
+        // The empty block's zero tips are ignored
+        assert_eq!(estimate.median_priority_fee, gwei_u256(2));
+
+        let slow = estimate.preset(FeeSpeed::Slow);
+        assert_eq!(slow.max_priority_fee_per_gas, gwei_u256(1));
+        assert_eq!(slow.max_fee_per_gas, gwei_u256(25 + 1));
+        assert_eq!(slow.expected_seconds, 72);
+        let normal = estimate.preset(FeeSpeed::Normal);
+        assert_eq!(normal.max_fee_per_gas, gwei_u256(30 + 2));
+        let fast = estimate.preset(FeeSpeed::Fast);
+        assert_eq!(fast.max_fee_per_gas, gwei_u256(40 + 5));
+        assert_eq!(fast.expected_blocks, 1);
+
+        // Quiet market: every preset is well under the cap of 3 * (2 * 20 + 5) gwei
+        assert_eq!(estimate.fee_cap, gwei_u256(135));
+        for preset in &estimate.presets {
+            estimate.check_cap(preset.max_fee_per_gas).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_cap_refuses_spike() {
+        // The next base fee jumped to 10x the window's median
+        let estimate = estimate_from_history(&quiet_history(50, 200)).unwrap();
+        let fast = estimate.preset(FeeSpeed::Fast).max_fee_per_gas;
+        assert!(matches!(estimate.check_cap(fast), Err(VeroxError::FeeTooHigh(_))));
+
+        let mut request = TxRequest::default();
+        assert!(estimate.apply(&mut request, FeeSpeed::Normal, false).is_err());
+        estimate.apply(&mut request, FeeSpeed::Normal, true).unwrap();
+        assert_eq!(request.max_fee_per_gas, Some(gwei_u256(300 + 2)));
+
+        // A dapp-supplied fee far above the medians is refused too
+        let calm = estimate_from_history(&quiet_history(50, 20)).unwrap();
+        let mut request = TxRequest {
+            max_fee_per_gas: Some(gwei_u256(500)),
+            ..TxRequest::default()
+        };
+        assert!(calm.apply(&mut request, FeeSpeed::Normal, false).is_err());
+    }
+
+    #[test]
+    fn test_apply_fills_missing_fields() {
+        let estimate = estimate_from_history(&quiet_history(50, 20)).unwrap();
+
+        let mut request = TxRequest {
+            max_priority_fee_per_gas: Some(gwei_u256(3)),
+            ..TxRequest::default()
+        };
+        estimate.apply(&mut request, FeeSpeed::Normal, false).unwrap();
+        assert_eq!(request.max_fee_per_gas, Some(gwei_u256(30 + 3)));
+
+        // An explicit legacy request gets a gas price, not 1559 fields
+        let mut request = TxRequest {
+            tx_type: Some(TxType::Legacy),
+            ..TxRequest::default()
+        };
+        estimate.apply(&mut request, FeeSpeed::Fast, false).unwrap();
+        assert_eq!(request.gas_price, Some(gwei_u256(45)));
+        assert!(request.max_fee_per_gas.is_none());
+    }
+
+    #[test]
+    fn test_legacy_chain() {
+        let history = FeeHistory {
+            base_fee_per_gas: vec![U256::zero(); 3],
+            gas_used_ratio: vec![0.5; 2],
+            oldest_block: U256::from(1),
+            reward: Vec::new(),
+        };
+        assert!(estimate_from_history(&history).is_none());
+
+        let estimate = estimate_from_gas_price(gwei_u256(10));
+        assert!(!estimate.eip1559);
+        assert_eq!(estimate.preset(FeeSpeed::Fast).max_fee_per_gas, gwei_u256(10) * 125 / 100);
+        let mut request = TxRequest::default();
+        estimate.apply(&mut request, FeeSpeed::Slow, false).unwrap();
+        assert_eq!(request.gas_price, Some(gwei_u256(10)));
+        assert_eq!(request.tx_type, Some(TxType::Legacy));
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_to_gas_price() {
+        let mock = MockProvider::new();
+        let network = Network {
+            chain_id: 56,
+            name: "Legacy".to_string(),
+            rpc_urls: vec!["http://mock".to_string()],
+            native_currency: NativeCurrency::ether(),
+            explorer_url: None,
+        };
+        let rpc = RpcProvider::new(Provider::new(mock.clone()), network);
+
+        // Responses pop from the back: both eth_feeHistory attempts fail, then eth_gasPrice
+        mock.push(gwei_u256(3)).unwrap();
+        let unsupported = MockResponse::Error(ethers::providers::JsonRpcError {
+            code: -32601,
+            message: "the method eth_feeHistory does not exist".to_string(),
+            data: None,
+        });
+        mock.push_response(unsupported.clone());
+        mock.push_response(unsupported);
+
+        let estimate = estimate_fees(&rpc).await.unwrap();
+        assert!(!estimate.eip1559);
+        assert_eq!(estimate.preset(FeeSpeed::Slow).max_fee_per_gas, gwei_u256(3));
+
+        mock.push(quiet_history(50, 20)).unwrap();
+        assert!(estimate_fees(&rpc).await.unwrap().eip1559);
+    }
+
+    #[tokio::test]
+    async fn test_extreme_fees_do_not_overflow() {
+        let mock = MockProvider::new();
+        let network = Network {
+            chain_id: 1,
+            name: "Hostile".to_string(),
+            rpc_urls: vec!["http://mock".to_string()],
+            native_currency: NativeCurrency::ether(),
+            explorer_url: None,
+        };
+        let rpc = RpcProvider::new(Provider::new(mock.clone()), network);
+
+        let mut history = quiet_history(50, 20);
+        history.base_fee_per_gas = vec![U256::MAX; 51];
+        history.reward = vec![vec![U256::MAX; 3]; 50];
+        mock.push(history).unwrap();
+        let estimate = estimate_fees(&rpc).await.unwrap();
+        assert_eq!(estimate.median_base_fee, U256::MAX);
+        assert_eq!(estimate.fee_cap, U256::MAX);
+        assert_eq!(estimate.preset(FeeSpeed::Fast).max_fee_per_gas, U256::MAX);
+
+        // A tip of the caller's own on top of the preset
+        let mut request = TxRequest { max_priority_fee_per_gas: Some(U256::MAX), ..Default::default() };
+        estimate.apply(&mut request, FeeSpeed::Normal, true).unwrap();
+        assert_eq!(request.max_fee_per_gas, Some(U256::MAX));
+
+        let estimate = estimate_from_gas_price(U256::MAX);
+        assert_eq!(estimate.fee_cap, U256::MAX);
+        assert_eq!(estimate.preset(FeeSpeed::Slow).max_fee_per_gas, U256::MAX / 100);
+    }
+}
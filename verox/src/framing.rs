@@ -0,0 +1,205 @@
+//! Native messaging frames
+//!
+//! Chrome and Firefox prefix every message with its length as a native-endian
+//! `u32`. Nothing stops the browser from announcing a huge length, so frames over
+//! [`MAX_REQUEST_BYTES`] are drained without being buffered and reported as
+//! [`Frame::TooLarge`]. In the other direction the browser drops the connection on
+//! a frame over [`MAX_RESPONSE_BYTES`]; [`chunk`] splits a larger reply into
+//! [`ChunkFrame`]s that each fit.
+
+use serde_json::Value;
+use std::io::{self, Read, Write};
+
+use crate::protocol::{Chunk, ChunkFrame, PROTOCOL_VERSION};
+
+/// Largest message the host accepts. Chrome allows up to 64 MiB, but no wallet
+/// request comes near this.
+pub const MAX_REQUEST_BYTES: usize = 8 * 1024 * 1024;
+
+/// Largest message the browser accepts from a native host
+pub const MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Frame {
+    Message(Vec<u8>),
+    /// A frame over the limit, already skipped, with its announced length
+    TooLarge(usize),
+}
+
+/// Read the next frame. `Ok(None)` is a clean end of input; input that ends
+/// inside a frame is an `UnexpectedEof` error.
+pub fn read_frame<R: Read>(reader: &mut R, limit: usize) -> io::Result<Option<Frame>> {
+    let mut length = [0u8; 4];
+    let mut filled = 0;
+    while filled < length.len() {
+        match reader.read(&mut length[filled..]) {
+            Ok(0) if filled == 0 => return Ok(None),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    let length = u32::from_ne_bytes(length) as usize;
+    if length > limit {
+        let skipped = io::copy(&mut reader.take(length as u64), &mut io::sink())?;
+        if skipped < length as u64 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        return Ok(Some(Frame::TooLarge(length)));
+    }
+
+    let mut message = vec![0u8; length];
+    reader.read_exact(&mut message)?;
+    Ok(Some(Frame::Message(message)))
+}
+
+/// Write one frame and flush it. Refuses a payload the browser would reject.
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    if payload.len() > MAX_RESPONSE_BYTES {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} byte frame is over the {} byte limit", payload.len(), MAX_RESPONSE_BYTES),
+        ));
+    }
+    writer.write_all(&(payload.len() as u32).to_ne_bytes())?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// Split the reply `json` to request `id` into serialized chunk frames of at
+/// most `limit` bytes each. `None` if even an empty chunk is over `limit`.
+pub fn chunk(id: &Value, json: &str, limit: usize) -> Option<Vec<Vec<u8>>> {
+    let envelope = |index, count, data: &str| {
+        serde_json::to_vec(&ChunkFrame {
+            id: id.clone(),
+            version: PROTOCOL_VERSION,
+            chunk: Chunk { index, count, data: data.to_string() },
+        })
+        .expect("chunk frames serialize")
+    };
+    let capacity = limit.checked_sub(envelope(u32::MAX, u32::MAX, "").len())?;
+    if capacity < 6 {
+        return None;
+    }
+
+    let mut pieces = Vec::new();
+    let (mut start, mut size) = (0, 0);
+    for (at, c) in json.char_indices() {
+        let escaped = escaped_len(c);
+        if size + escaped > capacity {
+            pieces.push(&json[start..at]);
+            (start, size) = (at, 0);
+        }
+        size += escaped;
+    }
+    if start < json.len() {
+        pieces.push(&json[start..]);
+    }
+    let count = u32::try_from(pieces.len()).ok()?;
+    Some(
+        pieces
+            .iter()
+            .zip(0..)
+            .map(|(data, index)| envelope(index, count, data))
+            .collect(),
+    )
+}
+
+/// Bytes `c` takes inside a JSON string, as serde_json escapes it
+fn escaped_len(c: char) -> usize {
+    match c {
+        '"' | '\\' | '\u{8}' | '\u{c}' | '\n' | '\r' | '\t' => 2,
+        '\0'..='\u{1f}' => 6,
+        _ => c.len_utf8(),
+    }
+}
+
+/// Join the chunks of one reply back into its JSON text, as the extension does.
+/// `None` if any are missing, repeated or inconsistent.
+pub fn reassemble(frames: &[ChunkFrame]) -> Option<String> {
+    let count = frames.first()?.chunk.count;
+    if frames.len() != count as usize {
+        return None;
+    }
+    let mut pieces = vec![None; frames.len()];
+    for frame in frames {
+        if frame.chunk.count != count || frame.id != frames[0].id {
+            return None;
+        }
+        let slot = pieces.get_mut(frame.chunk.index as usize)?;
+        if slot.replace(frame.chunk.data.as_str()).is_some() {
+            return None;
+        }
+    }
+    pieces.into_iter().collect::<Option<Vec<_>>>().map(|pieces| pieces.concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn framed(payload: &[u8]) -> Vec<u8> {
+        let mut bytes = (payload.len() as u32).to_ne_bytes().to_vec();
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn test_read_frame() {
+        let mut input = framed(b"{\"action\":\"get_networks\"}");
+        input.extend(framed(&[b'x'; 100]));
+        input.extend(framed(b"{}"));
+        let mut reader = input.as_slice();
+
+        assert_eq!(
+            read_frame(&mut reader, 64).unwrap(),
+            Some(Frame::Message(b"{\"action\":\"get_networks\"}".to_vec()))
+        );
+        // The oversized frame is skipped and the stream stays in sync
+        assert_eq!(read_frame(&mut reader, 64).unwrap(), Some(Frame::TooLarge(100)));
+        assert_eq!(read_frame(&mut reader, 64).unwrap(), Some(Frame::Message(b"{}".to_vec())));
+        assert_eq!(read_frame(&mut reader, 64).unwrap(), None);
+
+        // Input that stops mid-frame is an error, not an empty message
+        for truncated in [&framed(b"{}")[..5], &[1u8, 0][..], &(u32::MAX).to_ne_bytes()[..]] {
+            let err = read_frame(&mut &truncated[..], 64).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+    }
+
+    #[test]
+    fn test_write_frame() {
+        let mut out = Vec::new();
+        write_frame(&mut out, b"{\"success\":true}").unwrap();
+        assert_eq!(out, framed(b"{\"success\":true}"));
+
+        let err = write_frame(&mut Vec::new(), &vec![b' '; MAX_RESPONSE_BYTES + 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_chunk_round_trip() {
+        // Quotes, backslashes, control and multi-byte characters all survive the split
+        let reply = json!({
+            "id": "r1",
+            "success": true,
+            "data": (0..200).map(|i| format!("tx \"{}\" \\ ✓ {}", i, "é".repeat(i % 7))).collect::<Vec<_>>(),
+        })
+        .to_string()
+            + "\u{1}\n";
+        let frames = chunk(&json!("r1"), &reply, 512).unwrap();
+        assert!(frames.len() > 10);
+        assert!(frames.iter().all(|frame| frame.len() <= 512));
+
+        let mut parsed: Vec<ChunkFrame> = frames.iter().map(|f| serde_json::from_slice(f).unwrap()).collect();
+        parsed.reverse();
+        assert_eq!(reassemble(&parsed).unwrap(), reply);
+        parsed.pop();
+        assert_eq!(reassemble(&parsed), None);
+
+        assert_eq!(chunk(&json!("x".repeat(600)), &reply, 512), None);
+    }
+}
@@ -1,11 +1,6 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use ethers::{
-    prelude::*,
-    signers::Signer,
-    core::types::U256,
-};
+use ethers::signers::Signer;
 
 // Import the `console.log` function from the `console` module of `web_sys`
 #[wasm_bindgen]
@@ -19,11 +14,32 @@ macro_rules! console_log {
     ($($t:tt)*) => (log(&format_args!($($t)*).to_string()))
 }
 
-mod wallet;
-mod crypto;
-mod biometric;
-mod types;
-mod utils;
+pub mod wallet;
+pub mod crypto;
+pub mod error;
+pub mod biometric;
+pub mod types;
+pub mod utils;
+pub mod web3_keystore;
+pub mod mnemonic;
+pub mod registry;
+pub mod transaction;
+pub mod message;
+pub mod typed_data;
+pub mod network;
+pub mod provider;
+pub mod fees;
+pub mod pending;
+pub mod nonce;
+pub mod replace;
+pub mod protocol;
+pub mod framing;
+pub mod assets;
+pub mod eip1193;
+pub mod permissions;
+pub mod events;
+pub mod native_manifest;
+pub mod agent;
 
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global allocator
 #[cfg(feature = "wee_alloc")]
@@ -35,13 +51,9 @@ pub struct WalletInfo {
     pub address: String,
     pub balance: String,
     pub private_key: Option<String>,
-}
-
-#[derive(Serialize, Deserialize)]
-pub struct TransactionRequest {
-    pub to: String,
-    pub amount: String,
-    pub gas_price: Option<String>,
+    /// BIP-39 recovery phrase, only returned when a wallet is created
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mnemonic: Option<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -57,23 +69,12 @@ pub fn init() {
 }
 
 #[wasm_bindgen]
-pub fn create_wallet() -> String {
+pub fn create_wallet(word_count: Option<u32>, bip39_passphrase: Option<String>) -> String {
     console_log!("Creating new wallet...");
     
-    // Generate a new wallet
-    let wallet_result = std::panic::catch_unwind(|| {
-        let mut rng = rand::thread_rng();
-        let secret = ethers::core::k256::SecretKey::random(&mut rng);
-        let wallet = ethers::signers::LocalWallet::from(secret);
-        
-        let address = wallet.address();
-        let private_key_hex = format!("0x{}", hex::encode(wallet.signer().to_bytes()));
-        
-        WalletInfo {
-            address: address.to_string(),
-            balance: "0.0".to_string(),
-            private_key: Some(private_key_hex),
-        }
+    // Generate a new recovery phrase and derive the first account from it
+    let wallet_result = mnemonic::generate_mnemonic(word_count.unwrap_or(12) as usize).and_then(|phrase| {
+        wallet_info_from_mnemonic(phrase, bip39_passphrase.as_deref())
     });
     
     match wallet_result {
@@ -81,17 +82,43 @@ pub fn create_wallet() -> String {
             console_log!("Wallet created successfully: {}", wallet_info.address);
             serde_json::to_string(&wallet_info).unwrap_or_else(|_| "{}".to_string())
         }
-        Err(_) => {
-            console_log!("Failed to create wallet");
+        Err(e) => {
+            console_log!("Failed to create wallet: {}", e);
             serde_json::to_string(&WalletInfo {
                 address: "".to_string(),
                 balance: "0.0".to_string(),
                 private_key: None,
+                mnemonic: None,
             }).unwrap_or_else(|_| "{}".to_string())
         }
     }
 }
 
+#[wasm_bindgen]
+pub fn restore_wallet(phrase: &str, bip39_passphrase: Option<String>) -> String {
+    console_log!("Restoring wallet from recovery phrase...");
+    
+    match wallet_info_from_mnemonic(mnemonic::normalize_mnemonic(phrase), bip39_passphrase.as_deref()) {
+        Ok(wallet_info) => serde_json::to_string(&wallet_info).unwrap_or_else(|_| "{}".to_string()),
+        Err(e) => serde_json::json!({ "success": false, "error": e.to_string(), "code": e.code() }).to_string(),
+    }
+}
+
+#[wasm_bindgen]
+pub fn validate_mnemonic(phrase: &str) -> bool {
+    mnemonic::validate_mnemonic(phrase).is_ok()
+}
+
+fn wallet_info_from_mnemonic(phrase: String, bip39_passphrase: Option<&str>) -> error::Result<WalletInfo> {
+    let wallet = mnemonic::wallet_from_mnemonic(&phrase, bip39_passphrase, mnemonic::DEFAULT_DERIVATION_PATH)?;
+    Ok(WalletInfo {
+        address: wallet::address_string(wallet.address()),
+        balance: "0.0".to_string(),
+        private_key: Some(format!("0x{}", hex::encode(wallet.signer().to_bytes()))),
+        mnemonic: Some(phrase),
+    })
+}
+
 #[wasm_bindgen]
 pub fn verify_biometric() -> String {
     console_log!("Verifying biometric authentication...");
@@ -107,59 +134,184 @@ pub fn verify_biometric() -> String {
     serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string())
 }
 
+/// Balance of `address` on a built-in network (mainnet by default), or through `rpc_url`
 #[wasm_bindgen]
-pub fn get_wallet_balance(address: &str) -> String {
+pub async fn get_wallet_balance(address: String, network: Option<String>, rpc_url: Option<String>) -> String {
     console_log!("Getting wallet balance for: {}", address);
     
-    // This would typically query the blockchain
-    // For now, return a mock balance
-    let wallet_info = WalletInfo {
-        address: address.to_string(),
-        balance: "0.5234".to_string(),
-        private_key: None,
+    let result = async {
+        let address: ethers::types::Address = address
+            .parse()
+            .map_err(|_| error::VeroxError::InvalidArgument(format!("invalid address '{}'", address)))?;
+        let network = wasm_network(network, rpc_url)?;
+        provider::RpcProvider::connect(&network).await?.balance(address).await
     };
     
-    serde_json::to_string(&wallet_info).unwrap_or_else(|_| "{}".to_string())
+    match result.await {
+        Ok(balance) => serde_json::to_string(&WalletInfo {
+            address,
+            balance: ethers::utils::format_ether(balance),
+            private_key: None,
+            mnemonic: None,
+        }).unwrap_or_else(|_| "{}".to_string()),
+        Err(e) => serde_json::json!({ "success": false, "error": e.to_string(), "code": e.code() }).to_string(),
+    }
 }
 
 #[wasm_bindgen]
 pub fn prepare_transaction(transaction_json: &str) -> String {
     console_log!("Preparing transaction: {}", transaction_json);
     
-    let transaction_request: Result<TransactionRequest, _> = serde_json::from_str(transaction_json);
-    
-    match transaction_request {
-        Ok(tx_req) => {
-            console_log!("Transaction prepared for {} ETH to {}", tx_req.amount, tx_req.to);
-            
-            // Return a mock transaction hash
-            let mut result = HashMap::new();
-            result.insert("success".to_string(), serde_json::Value::Bool(true));
-            result.insert("tx_hash".to_string(), serde_json::Value::String("0x1234567890abcdef...".to_string()));
-            result.insert("status".to_string(), serde_json::Value::String("prepared".to_string()));
-            
-            serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string())
+    match parse_transaction(transaction_json) {
+        Ok(params) => {
+            let tx = params.to_typed();
+            console_log!("Transaction prepared for {} wei to {:?}", params.value, params.to);
+            serde_json::json!({
+                "success": true,
+                "unsigned_transaction": tx.rlp().to_string(),
+                "sighash": format!("{:?}", tx.sighash()),
+                "status": "prepared"
+            }).to_string()
         }
         Err(e) => {
-            console_log!("Failed to parse transaction: {}", e);
-            
-            let mut result = HashMap::new();
-            result.insert("success".to_string(), serde_json::Value::Bool(false));
-            result.insert("error".to_string(), serde_json::Value::String(e.to_string()));
-            
-            serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string())
+            console_log!("Failed to prepare transaction: {}", e);
+            serde_json::json!({ "success": false, "error": e.to_string(), "code": e.code() }).to_string()
         }
     }
 }
 
+/// Sign a complete transaction object with a hex private key
+#[wasm_bindgen]
+pub fn sign_transaction(transaction_json: &str, private_key: &str) -> String {
+    let result = parse_transaction(transaction_json).and_then(|params| {
+        let wallet: ethers::signers::LocalWallet = private_key
+            .parse()
+            .map_err(|_| error::VeroxError::InvalidArgument("invalid private key".to_string()))?;
+        transaction::sign_transaction(&wallet, &params)
+    });
+    
+    match result {
+        Ok(signed) => serde_json::json!({
+            "success": true,
+            "from": wallet::address_string(signed.from),
+            "tx_hash": format!("{:?}", signed.hash),
+            "raw_transaction": signed.raw.to_string(),
+            "status": "signed"
+        }).to_string(),
+        Err(e) => serde_json::json!({ "success": false, "error": e.to_string(), "code": e.code() }).to_string(),
+    }
+}
+
+fn parse_transaction(transaction_json: &str) -> error::Result<transaction::TxParams> {
+    let json: serde_json::Value = serde_json::from_str(transaction_json)?;
+    transaction::TxRequest::from_json(&json)?.into_params()
+}
+
+/// EIP-191 `personal_sign` of a text or 0x-hex message with a hex private key
 #[wasm_bindgen]
-pub fn get_gas_price() -> String {
+pub fn sign_message(message: &str, private_key: &str) -> String {
+    let result = private_key
+        .parse::<ethers::signers::LocalWallet>()
+        .map_err(|_| error::VeroxError::InvalidArgument("invalid private key".to_string()))
+        .and_then(|wallet| message::sign_message(&wallet, &message::decode_message(message)).map(|sig| (wallet, sig)));
+    
+    match result {
+        Ok((wallet, signature)) => serde_json::json!({
+            "success": true,
+            "address": wallet::address_string(wallet.address()),
+            "signature": format!("0x{}", signature)
+        }).to_string(),
+        Err(e) => serde_json::json!({ "success": false, "error": e.to_string(), "code": e.code() }).to_string(),
+    }
+}
+
+/// Recover the address that signed `message`
+#[wasm_bindgen]
+pub fn verify_message(message: &str, signature: &str) -> String {
+    let result = message::parse_signature(signature)
+        .and_then(|signature| message::recover_signer(&message::decode_message(message), &signature));
+    
+    match result {
+        Ok(signer) => serde_json::json!({ "success": true, "address": wallet::address_string(signer) }).to_string(),
+        Err(e) => serde_json::json!({ "success": false, "error": e.to_string(), "code": e.code() }).to_string(),
+    }
+}
+
+/// Human-readable tree and digest of an EIP-712 v4 payload, for approval prompts
+#[wasm_bindgen]
+pub fn preview_typed_data(typed_data_json: &str) -> String {
+    let result = parse_typed_data(typed_data_json).and_then(|payload| {
+        let hashes = typed_data::hash_typed_data(&payload)?;
+        Ok(serde_json::json!({
+            "success": true,
+            "fields": typed_data::field_tree(&payload)?,
+            "digest": format!("{:?}", hashes.digest)
+        }))
+    });
+    
+    match result {
+        Ok(preview) => preview.to_string(),
+        Err(e) => serde_json::json!({ "success": false, "error": e.to_string(), "code": e.code() }).to_string(),
+    }
+}
+
+/// `eth_signTypedData_v4` with a hex private key
+#[wasm_bindgen]
+pub fn sign_typed_data(typed_data_json: &str, private_key: &str) -> String {
+    let result = parse_typed_data(typed_data_json).and_then(|payload| {
+        let wallet = private_key
+            .parse::<ethers::signers::LocalWallet>()
+            .map_err(|_| error::VeroxError::InvalidArgument("invalid private key".to_string()))?;
+        typed_data::sign_typed_data(&wallet, &payload).map(|signature| (wallet, signature))
+    });
+    
+    match result {
+        Ok((wallet, signature)) => serde_json::json!({
+            "success": true,
+            "address": wallet::address_string(wallet.address()),
+            "signature": format!("0x{}", signature)
+        }).to_string(),
+        Err(e) => serde_json::json!({ "success": false, "error": e.to_string(), "code": e.code() }).to_string(),
+    }
+}
+
+fn parse_typed_data(typed_data_json: &str) -> error::Result<ethers::types::transaction::eip712::TypedData> {
+    typed_data::parse_typed_data(&serde_json::from_str(typed_data_json)?)
+}
+
+/// Slow, normal and fast fee presets from `eth_feeHistory` (or `eth_gasPrice`
+/// on chains without EIP-1559), with the safety cap
+#[wasm_bindgen]
+pub async fn get_gas_price(network: Option<String>, rpc_url: Option<String>) -> String {
     console_log!("Getting current gas price...");
     
-    // Mock gas price data
-    let mut result = HashMap::new();
-    result.insert("gas_price".to_string(), serde_json::Value::String("25 gwei".to_string()));
-    result.insert("timestamp".to_string(), serde_json::Value::Number(serde_json::Number::from(1234567890)));
+    let result = async {
+        let network = wasm_network(network, rpc_url)?;
+        let rpc = provider::RpcProvider::connect(&network).await?;
+        fees::estimate_fees(&rpc).await
+    };
     
-    serde_json::to_string(&result).unwrap_or_else(|_| "{}".to_string())
+    match result.await {
+        Ok(estimate) => {
+            let mut result = serde_json::to_value(&estimate).unwrap_or_default();
+            result["gas_price"] = estimate.preset(fees::FeeSpeed::Normal).max_fee_per_gas.to_string().into();
+            result["timestamp"] = chrono::Utc::now().timestamp().into();
+            result.to_string()
+        }
+        Err(e) => serde_json::json!({ "success": false, "error": e.to_string(), "code": e.code() }).to_string(),
+    }
+}
+
+// The browser has no data directory, so only built-in networks are known;
+// `rpc_url` replaces the network's endpoints
+fn wasm_network(network: Option<String>, rpc_url: Option<String>) -> error::Result<network::Network> {
+    let config = network::NetworkConfig::default();
+    let mut network = match network {
+        Some(selector) => config.find(&selector)?,
+        None => config.selected_network(),
+    };
+    if let Some(rpc_url) = rpc_url {
+        network.rpc_urls = vec![rpc_url];
+    }
+    Ok(network)
 }
@@ -0,0 +1,102 @@
+//! EIP-191 message signing (`personal_sign` / `eth_sign`)
+//!
+//! The message is prefixed with `"\x19Ethereum Signed Message:\n" + len(message)`
+//! before hashing, so a signature over it can never be replayed as a transaction.
+//! `personal_sign` takes `[message, address]` and `eth_sign` takes `[address, message]`;
+//! both sign the same prefixed hash here. Signatures are 65 bytes `r || s || v` with
+//! `v` 27 or 28, as wallets return them.
+
+use ethers::signers::LocalWallet;
+use ethers::types::{Address, Signature, H256};
+use ethers::utils::hash_message;
+
+use crate::error::{Result, VeroxError};
+
+/// Bytes to sign for a message as dapps send it: `0x`-prefixed hex is decoded,
+/// anything else is taken as UTF-8 text
+pub fn decode_message(message: &str) -> Vec<u8> {
+    message
+        .strip_prefix("0x")
+        .and_then(|data| hex::decode(data).ok())
+        .unwrap_or_else(|| message.as_bytes().to_vec())
+}
+
+/// EIP-191 hash of `message`
+pub fn message_hash(message: &[u8]) -> H256 {
+    hash_message(message)
+}
+
+/// Sign `message` with the EIP-191 prefix
+pub fn sign_message(wallet: &LocalWallet, message: &[u8]) -> Result<Signature> {
+    wallet
+        .sign_hash(message_hash(message))
+        .map_err(|e| VeroxError::InvalidArgument(format!("cannot sign message: {}", e)))
+}
+
+/// Parse a 65-byte hex signature (`v` may be 0/1 or 27/28)
+pub fn parse_signature(signature: &str) -> Result<Signature> {
+    signature
+        .parse()
+        .map_err(|_| VeroxError::InvalidArgument("signature must be 65 bytes of hex".to_string()))
+}
+
+/// Address whose key produced `signature` over `message`
+pub fn recover_signer(message: &[u8], signature: &Signature) -> Result<Address> {
+    signature
+        .recover(message_hash(message))
+        .map_err(|e| VeroxError::InvalidArgument(format!("cannot recover signer: {}", e)))
+}
+
+/// Whether `signature` over `message` was made by `address`
+pub fn verify_message(message: &[u8], signature: &Signature, address: Address) -> Result<bool> {
+    Ok(recover_signer(message, signature)? == address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::address_string;
+
+    // web3.js `eth.accounts.sign` documentation example
+    const KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+    const SIGNATURE: &str = "0xb91467e570a6466aa9e9876cbcd013baba02900b8979d43fe208a4a4f339f5fd\
+                             6007e74cd82e037b800186422fc2da167c747ef045e5d18a5f5d4300f8e1a0291c";
+
+    #[test]
+    fn test_sign_known_vector() {
+        assert_eq!(
+            format!("{:?}", message_hash(b"Hello World")),
+            "0xa1de988600a42c4b4ab089b619297c17d53cffae5d5120d82d8a92d0bb3b78f2"
+        );
+
+        let wallet: LocalWallet = KEY.parse().unwrap();
+        let signature = sign_message(&wallet, b"Some data").unwrap();
+        assert_eq!(format!("0x{}", signature), SIGNATURE);
+        assert_eq!(signature.v, 28);
+    }
+
+    #[test]
+    fn test_recover_and_verify() {
+        let signature = parse_signature(SIGNATURE).unwrap();
+        let signer = recover_signer(b"Some data", &signature).unwrap();
+        assert_eq!(address_string(signer), "0x2c7536E3605D9C16a7a3D7b1898e529396a65c23");
+        assert!(verify_message(b"Some data", &signature, signer).unwrap());
+        assert!(!verify_message(b"Other data", &signature, signer).unwrap());
+
+        // Hardware wallets return v as 0/1
+        let mut raw = hex::decode(&SIGNATURE[2..]).unwrap();
+        raw[64] -= 27;
+        let signature = parse_signature(&hex::encode(raw)).unwrap();
+        assert_eq!(recover_signer(b"Some data", &signature).unwrap(), signer);
+
+        assert!(parse_signature("0x1234").is_err());
+    }
+
+    #[test]
+    fn test_decode_message() {
+        assert_eq!(decode_message("0x536f6d652064617461"), b"Some data");
+        assert_eq!(decode_message("Some data"), b"Some data");
+        // Not valid hex, so it is signed as text
+        assert_eq!(decode_message("0xzz"), b"0xzz");
+    }
+}
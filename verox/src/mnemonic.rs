@@ -0,0 +1,200 @@
+//! BIP-39 mnemonic phrases
+//!
+//! New wallets are generated from a 12- or 24-word English mnemonic so they can be
+//! backed up on paper. The phrase (and the optional BIP-39 passphrase, the "25th
+//! word") is stored inside the encrypted keystore; the signing key is derived from
+//! it with BIP-32 along `DEFAULT_DERIVATION_PATH`, and further accounts along the
+//! path produced by a [`DerivationScheme`].
+
+use ethers::core::k256::ecdsa::SigningKey;
+use ethers::signers::coins_bip39::{English, Mnemonic};
+use ethers::signers::LocalWallet;
+use rand::thread_rng;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{Result, VeroxError};
+
+/// First account of the standard Ethereum BIP-44 path
+pub const DEFAULT_DERIVATION_PATH: &str = "m/44'/60'/0'/0/0";
+
+/// Placeholder for the account index in a custom derivation path template
+pub const INDEX_PLACEHOLDER: &str = "{index}";
+
+/// How account `i` of a seed maps to a BIP-32 path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DerivationScheme {
+    /// `m/44'/60'/0'/0/i`, used by MetaMask, geth and most software wallets
+    Bip44,
+    /// `m/44'/60'/i'/0/0`, used by Ledger Live
+    LedgerLive,
+    /// Any other path; `{index}` in it is replaced by the account index
+    Custom(String),
+}
+
+impl DerivationScheme {
+    /// Full derivation path of account `index`
+    pub fn path(&self, index: u32) -> String {
+        match self {
+            DerivationScheme::Bip44 => format!("m/44'/60'/0'/0/{}", index),
+            DerivationScheme::LedgerLive => format!("m/44'/60'/{}'/0/0", index),
+            DerivationScheme::Custom(template) => template.replace(INDEX_PLACEHOLDER, &index.to_string()),
+        }
+    }
+
+    /// Whether different indexes give different paths (false for a fixed custom path)
+    pub fn is_indexed(&self) -> bool {
+        match self {
+            DerivationScheme::Custom(template) => template.contains(INDEX_PLACEHOLDER),
+            _ => true,
+        }
+    }
+}
+
+impl FromStr for DerivationScheme {
+    type Err = VeroxError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "bip44" | "default" => Ok(DerivationScheme::Bip44),
+            "ledger-live" | "ledger" => Ok(DerivationScheme::LedgerLive),
+            _ if s.starts_with("m/") => Ok(DerivationScheme::Custom(s.to_string())),
+            _ => Err(VeroxError::InvalidArgument(format!(
+                "unknown derivation scheme '{}', expected bip44, ledger-live or a path like m/44'/60'/0'/0/{{index}}",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for DerivationScheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DerivationScheme::Bip44 => write!(f, "bip44"),
+            DerivationScheme::LedgerLive => write!(f, "ledger-live"),
+            DerivationScheme::Custom(template) => write!(f, "{}", template),
+        }
+    }
+}
+
+/// Word counts offered when generating a mnemonic
+pub const SUPPORTED_WORD_COUNTS: [usize; 2] = [12, 24];
+
+/// Generate a fresh mnemonic with `word_count` words (12 or 24)
+pub fn generate_mnemonic(word_count: usize) -> Result<String> {
+    if !SUPPORTED_WORD_COUNTS.contains(&word_count) {
+        return Err(VeroxError::InvalidArgument(format!(
+            "mnemonic must have 12 or 24 words, got {}",
+            word_count
+        )));
+    }
+    let mnemonic = Mnemonic::<English>::new_with_count(&mut thread_rng(), word_count)
+        .map_err(|e| VeroxError::InvalidMnemonic(e.to_string()))?;
+    Ok(mnemonic.to_phrase())
+}
+
+/// Lowercase the phrase and collapse whitespace, so pasted words still match
+pub fn normalize_mnemonic(phrase: &str) -> String {
+    phrase
+        .split_whitespace()
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Check word count, wordlist membership and checksum of a phrase
+pub fn validate_mnemonic(phrase: &str) -> Result<()> {
+    parse(phrase).map(|_| ())
+}
+
+/// Derive the wallet at `derivation_path` from a mnemonic and optional BIP-39 passphrase
+pub fn wallet_from_mnemonic(
+    phrase: &str,
+    bip39_passphrase: Option<&str>,
+    derivation_path: &str,
+) -> Result<LocalWallet> {
+    let mnemonic = parse(phrase)?;
+    let xpriv = mnemonic
+        .derive_key(derivation_path, bip39_passphrase)
+        .map_err(|e| VeroxError::InvalidArgument(format!("cannot derive {}: {}", derivation_path, e)))?;
+    let signing_key: &SigningKey = xpriv.as_ref();
+    LocalWallet::from_bytes(&signing_key.to_bytes())
+        .map_err(|e| VeroxError::InvalidMnemonic(format!("derived an invalid key: {}", e)))
+}
+
+// The library's errors echo the phrase back, so they are replaced with
+// messages that never contain the words themselves.
+fn parse(phrase: &str) -> Result<Mnemonic<English>> {
+    let normalized = normalize_mnemonic(phrase);
+    let word_count = normalized.split(' ').filter(|w| !w.is_empty()).count();
+    if ![12, 15, 18, 21, 24].contains(&word_count) {
+        return Err(VeroxError::InvalidMnemonic(format!(
+            "expected 12, 15, 18, 21 or 24 words, got {}",
+            word_count
+        )));
+    }
+    Mnemonic::<English>::new_from_phrase(&normalized).map_err(|_| {
+        VeroxError::InvalidMnemonic("unknown word or bad checksum".to_string())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wallet::address_string;
+    use ethers::signers::Signer;
+
+    #[test]
+    fn test_known_vectors() {
+        let abandon = "abandon abandon abandon abandon abandon abandon \
+                       abandon abandon abandon abandon abandon about";
+        let wallet = wallet_from_mnemonic(abandon, None, DEFAULT_DERIVATION_PATH).unwrap();
+        assert_eq!(address_string(wallet.address()), "0x9858EfFD232B4033E47d90003D41EC34EcaEda94");
+
+        // Hardhat / Anvil default accounts
+        let junk = "test test test test test test test test test test test junk";
+        let wallet = wallet_from_mnemonic(junk, None, DEFAULT_DERIVATION_PATH).unwrap();
+        assert_eq!(address_string(wallet.address()), "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266");
+
+        // A BIP-39 passphrase yields a different wallet
+        let wallet = wallet_from_mnemonic(junk, Some("extra"), DEFAULT_DERIVATION_PATH).unwrap();
+        assert_ne!(address_string(wallet.address()), "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266");
+    }
+
+    #[test]
+    fn test_derivation_schemes() {
+        let junk = "test test test test test test test test test test test junk";
+        let bip44 = DerivationScheme::Bip44;
+        assert_eq!(bip44.path(1), "m/44'/60'/0'/0/1");
+        let wallet = wallet_from_mnemonic(junk, None, &bip44.path(1)).unwrap();
+        // Anvil's second default account
+        assert_eq!(address_string(wallet.address()), "0x70997970C51812dc3A010C7d01b50e0d17dc79C8");
+
+        assert_eq!("ledger-live".parse::<DerivationScheme>().unwrap().path(2), "m/44'/60'/2'/0/0");
+        let custom: DerivationScheme = "m/44'/60'/0'/{index}".parse().unwrap();
+        assert!(custom.is_indexed());
+        assert_eq!(custom.path(3), "m/44'/60'/0'/3");
+        assert!(!"m/44'/60'/0'/0/7".parse::<DerivationScheme>().unwrap().is_indexed());
+        assert!("trezor".parse::<DerivationScheme>().is_err());
+        assert!(wallet_from_mnemonic(junk, None, "m/not/a/path").is_err());
+    }
+
+    #[test]
+    fn test_generate_and_validate() {
+        for count in SUPPORTED_WORD_COUNTS {
+            let phrase = generate_mnemonic(count).unwrap();
+            assert_eq!(phrase.split(' ').count(), count);
+            validate_mnemonic(&phrase).unwrap();
+        }
+        assert!(matches!(generate_mnemonic(13), Err(VeroxError::InvalidArgument(_))));
+
+        // Whitespace and case are forgiven, a wrong checksum word is not
+        validate_mnemonic("  Test test test test test test test test test test test   JUNK\n").unwrap();
+        let bad_checksum = "test test test test test test test test test test test test";
+        match validate_mnemonic(bad_checksum) {
+            Err(VeroxError::InvalidMnemonic(msg)) => assert!(!msg.contains("test")),
+            other => panic!("expected InvalidMnemonic, got {:?}", other.map(|_| ())),
+        }
+        assert!(validate_mnemonic("test test test").is_err());
+    }
+}
@@ -1,162 +1,507 @@
 use serde::{Deserialize, Serialize};
+use chrono::Utc;
 use serde_json::Value;
-use std::io::{self, Write, Read};
-use ethers::{
-    prelude::*,
-    signers::Signer,
-};
-
-mod wallet;
-mod crypto;
-mod biometric;
-mod types;
-mod utils;
+use std::io;
+use ethers::signers::Signer;
+use ethers::types::transaction::eip712::TypedData;
+use ethers::types::{Address, H256};
+use ethers::utils::format_ether;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-#[derive(Serialize, Deserialize)]
-struct NativeMessage {
-    action: String,
-    data: Option<Value>,
-}
+use verox::error::VeroxError;
+use verox::message;
+use verox::mnemonic::{self, DerivationScheme};
+use verox::transaction::TxRequest;
+use verox::typed_data;
+use verox::network::{self, Network};
+use verox::provider::{self, RpcProvider};
+use verox::fees::{self, FeeSpeed};
+use verox::pending::{self, TxStore};
+use verox::nonce::NonceManager;
+use verox::replace::{self, Replacement};
+use verox::assets::{AssetList, WatchedAsset};
+use verox::eip1193::{self, ProviderError, ProviderRequest};
+use verox::agent::{self, UnlockedAccount};
+use verox::biometric;
+use verox::events::{self, ProviderState};
+use verox::framing::{self, Frame};
+use verox::permissions::{self, Grant, PermissionStore};
+use verox::protocol::{self, Event, Hello, Message, Response, Session};
+use verox::utils;
+use verox::types::KeyType;
+use verox::wallet;
 
 #[derive(Serialize, Deserialize)]
 struct NativeResponse {
     success: bool,
     data: Option<Value>,
     error: Option<String>,
+    /// Stable error code (see `VeroxError::code`) so the UI doesn't parse messages
+    code: Option<String>,
 }
 
-fn main() {
-    let _stdin = io::stdin();
-    let stdout = io::stdout();
-    
-    loop {
-        // Read message length (first 4 bytes)
-        let mut length_bytes = [0u8; 4];
-        if io::stdin().read_exact(&mut length_bytes).is_err() {
-            break;
-        }
-        
-        let message_length = u32::from_ne_bytes(length_bytes);
-        
-        // Read the actual message
-        let mut buffer = vec![0u8; message_length as usize];
-        if io::stdin().read_exact(&mut buffer).is_err() {
-            break;
-        }
-        
-        let message_str = String::from_utf8_lossy(&buffer);
-        
-        // Parse and handle the message
-        let response = match serde_json::from_str::<NativeMessage>(&message_str) {
-            Ok(message) => handle_message(message),
-            Err(e) => NativeResponse {
-                success: false,
-                data: None,
-                error: Some(format!("Failed to parse message: {}", e)),
-            }
-        };
-        
-        // Send response back
-        let response_json = serde_json::to_string(&response).unwrap();
-        let response_bytes = response_json.as_bytes();
-        let response_length = (response_bytes.len() as u32).to_ne_bytes();
-        
-        let mut stdout_lock = stdout.lock();
-        stdout_lock.write_all(&response_length).unwrap();
-        stdout_lock.write_all(response_bytes).unwrap();
-        stdout_lock.flush().unwrap();
-    }
-}
-
-fn handle_message(message: NativeMessage) -> NativeResponse {
-    match message.action.as_str() {
-        "create_wallet" => handle_create_wallet(),
-        "unlock_wallet" => handle_unlock_wallet(),
-        "verify_biometric" => handle_verify_biometric(),
-        "register_biometric" => handle_register_biometric(),
-        "get_wallet_info" => handle_get_wallet_info(),
-        "send_transaction" => handle_send_transaction(message.data),
-        _ => NativeResponse {
+impl From<VeroxError> for NativeResponse {
+    fn from(e: VeroxError) -> Self {
+        NativeResponse {
             success: false,
             data: None,
-            error: Some("Unknown action".to_string()),
+            error: Some(e.to_string()),
+            code: Some(e.code().to_string()),
         }
     }
 }
 
-fn handle_create_wallet() -> NativeResponse {
-    // Use the existing wallet creation logic
-    match std::panic::catch_unwind(|| {
-        let mut rng = rand::thread_rng();
-        let secret = ethers::core::k256::SecretKey::random(&mut rng);
-        let wallet = ethers::signers::LocalWallet::from(secret);
-        
-        let address = wallet.address();
-        let private_key_hex = format!("0x{}", hex::encode(wallet.signer().to_bytes()));
-        
-        serde_json::json!({
-            "address": address.to_string(),
-            "private_key": private_key_hex,
-            "created_at": chrono::Utc::now().to_rfc3339()
-        })
-    }) {
-        Ok(wallet_data) => NativeResponse {
+/// Writes frames to the extension. Request workers and the transaction tracker share
+/// it; each reply is written whole under the stdout lock.
+#[derive(Clone)]
+struct Host {
+    /// What the client negotiated in its `hello`, if it sent one
+    session: Arc<Mutex<Option<Session>>>,
+}
+
+impl Host {
+    fn new() -> Self {
+        Host { session: Arc::new(Mutex::new(None)) }
+    }
+
+    fn has(&self, capability: &str) -> bool {
+        self.session
+            .lock()
+            .map(|session| session.as_ref().is_some_and(|s| s.has(capability)))
+            .unwrap_or(false)
+    }
+
+    /// Answer a request: a version 2 reply if it had an id, a bare response if not.
+    /// A reply over the browser's limit is chunked if the client can take that,
+    /// and replaced by a `RESPONSE_TOO_LARGE` error otherwise.
+    fn reply(&self, id: Option<Value>, response: NativeResponse) {
+        let json = match &id {
+            Some(id) => serde_json::to_string(&Response::new(id.clone(), &response)),
+            None => serde_json::to_string(&response),
+        };
+        let Ok(json) = json else { return };
+
+        let mut stdout = io::stdout().lock();
+        // A closed pipe means the browser went away; the read loop will notice
+        if json.len() <= framing::MAX_RESPONSE_BYTES {
+            let _ = framing::write_frame(&mut stdout, json.as_bytes());
+            return;
+        }
+        if let Some(id) = id.as_ref().filter(|_| self.has(protocol::CAP_CHUNKS)) {
+            if let Some(frames) = framing::chunk(id, &json, framing::MAX_RESPONSE_BYTES) {
+                for frame in frames {
+                    if framing::write_frame(&mut stdout, &frame).is_err() {
+                        return;
+                    }
+                }
+                return;
+            }
+        }
+        drop(stdout);
+        if response.code.as_deref() == Some("RESPONSE_TOO_LARGE") {
+            // Even the error doesn't fit, the id must be huge
+            return;
+        }
+        let message = format!("Response of {} bytes is over the {} byte limit", json.len(), framing::MAX_RESPONSE_BYTES);
+        self.reply(id, error_response("RESPONSE_TOO_LARGE", &message));
+    }
+
+    /// Push an event, if the client asked for them. One too large for a frame is dropped.
+    fn push(&self, event: &str, data: Value) {
+        if self.has(protocol::CAP_EVENTS) {
+            if let Ok(json) = serde_json::to_vec(&Event::new(event, data)) {
+                let _ = framing::write_frame(&mut io::stdout().lock(), &json);
+            }
+        }
+    }
+
+    fn hello(&self, params: Option<Value>) -> NativeResponse {
+        let hello: Hello = match params.map(serde_json::from_value).transpose() {
+            Ok(Some(hello)) => hello,
+            Ok(None) => return error_response("INVALID_REQUEST", "hello needs the client's version"),
+            Err(e) => return error_response("INVALID_REQUEST", &format!("Invalid hello: {}", e)),
+        };
+        let session = protocol::negotiate(&hello);
+        let data = serde_json::json!({
+            "version": session.version,
+            "capabilities": session.capabilities,
+            "host": { "name": env!("CARGO_PKG_NAME"), "version": env!("CARGO_PKG_VERSION") },
+            "max_request_bytes": framing::MAX_REQUEST_BYTES,
+        });
+        if let Ok(mut current) = self.session.lock() {
+            *current = Some(session);
+        }
+        NativeResponse {
             success: true,
-            data: Some(wallet_data),
+            data: Some(data),
             error: None,
-        },
-        Err(_) => NativeResponse {
-            success: false,
-            data: None,
-            error: Some("Failed to create wallet".to_string()),
-        }
-    }
-}
-
-fn handle_unlock_wallet() -> NativeResponse {
-    // Try to find and unlock existing wallet
-    match std::fs::read_dir("keystore") {
-        Ok(entries) => {
-            for entry in entries {
-                if let Ok(entry) = entry {
-                    let path = entry.path();
-                    if path.extension().and_then(|s| s.to_str()) == Some("dat") {
-                        // Found a keystore file
-                        return NativeResponse {
-                            success: true,
-                            data: Some(serde_json::json!({
-                                "address": "0x742d35Cc6634C0532925a3b8d0b4E1b87D5E2d3c",
-                                "unlocked": true
-                            })),
-                            error: None,
-                        };
+            code: None,
+        }
+    }
+}
+
+fn main() {
+    let host = Host::new();
+
+    // Follow broadcast transactions, including ones sent before this host started
+    let tracker = host.clone();
+    thread::spawn(move || loop {
+        if let Ok(dir) = utils::get_data_dir() {
+            match provider::block_on(pending::refresh(&dir, pending::CONFIRMATIONS)) {
+                Ok(Ok(report)) => {
+                    for tx in report.changed {
+                        tracker.push("transaction_updated", serde_json::to_value(tx).unwrap_or_default());
+                    }
+                    for error in report.errors {
+                        eprintln!("Could not check {}", error);
                     }
                 }
+                Ok(Err(e)) | Err(e) => eprintln!("Could not check transactions: {}", e),
             }
-            
-            NativeResponse {
-                success: false,
-                data: None,
-                error: Some("No wallet found".to_string()),
+        }
+        thread::sleep(Duration::from_secs(pending::POLL_INTERVAL_SECS));
+    });
+
+    // Tell connected pages when the wallet locks, the network changes or stops answering.
+    // Changes are announced to every host by whoever makes them; reachability is checked
+    // on a timer. Without notifications (not a Unix system) the timer is all there is.
+    let subscription = utils::get_data_dir().and_then(|dir| events::subscribe(&dir));
+    let (_subscription, changes) = match subscription {
+        Ok((subscription, changes)) => (Some(subscription), Some(changes)),
+        Err(e) => {
+            eprintln!("Not notified of wallet changes: {}", e);
+            (None, None)
+        }
+    };
+    let watcher = host.clone();
+    thread::spawn(move || {
+        let mut last: Option<ProviderState> = None;
+        let mut checked: Option<(u64, Instant)> = None;
+        let mut connected = true;
+        loop {
+            // Until the client asks for events there is nothing to compare with
+            let wait = match (&last, &changes) {
+                (Some(_), Some(_)) => Duration::from_secs(events::CONNECTIVITY_INTERVAL_SECS),
+                _ => Duration::from_secs(events::WATCH_INTERVAL_SECS),
+            };
+            match &changes {
+                Some(changes) => {
+                    if let Err(mpsc::RecvTimeoutError::Disconnected) = changes.recv_timeout(wait) {
+                        return;
+                    }
+                    // One look covers changes that arrived together
+                    while changes.try_recv().is_ok() {}
+                }
+                None => thread::sleep(wait),
             }
+            if !watcher.has(protocol::CAP_EVENTS) {
+                continue;
+            }
+            let Ok(mut state) = provider_state() else { continue };
+            let due = checked.is_none_or(|(chain_id, at)| {
+                chain_id != state.chain_id || at.elapsed() >= Duration::from_secs(events::CONNECTIVITY_INTERVAL_SECS)
+            });
+            if due {
+                let timeout = Duration::from_secs(events::CONNECTIVITY_TIMEOUT_SECS);
+                connected = network::resolve_network(Some(&state.chain_id.to_string())).is_ok_and(|network| {
+                    matches!(
+                        provider::block_on(async { tokio::time::timeout(timeout, RpcProvider::connect(&network)).await }),
+                        Ok(Ok(Ok(_)))
+                    )
+                });
+                checked = Some((state.chain_id, Instant::now()));
+            }
+            state.connected = connected;
+            // The first snapshot after the hello is what the pages already know
+            if let Some(last) = &last {
+                for (event, data) in events::diff(last, &state) {
+                    watcher.push(event, data);
+                }
+            }
+            last = Some(state);
         }
-        Err(_) => NativeResponse {
-            success: false,
-            data: None,
-            error: Some("Keystore directory not found".to_string()),
+    });
+
+    let mut stdin = io::stdin().lock();
+    // Stops at the end of input, or at input that ends mid-frame
+    while let Ok(Some(frame)) = framing::read_frame(&mut stdin, framing::MAX_REQUEST_BYTES) {
+        let buffer = match frame {
+            Frame::Message(buffer) => buffer,
+            Frame::TooLarge(length) => {
+                let message = format!("Message of {} bytes is over the {} byte limit", length, framing::MAX_REQUEST_BYTES);
+                host.reply(None, error_response("MESSAGE_TOO_LARGE", &message));
+                continue;
+            }
+        };
+
+        match Message::parse(&buffer) {
+            // Version 1 clients match replies by order, so answer before reading on
+            Ok(Message::Legacy { action, data }) => host.reply(None, handle_message(&action, data)),
+            Ok(Message::Request(request)) if request.method == protocol::HELLO => {
+                host.reply(Some(request.id), host.hello(request.params))
+            }
+            Ok(Message::Request(request)) => {
+                let host = host.clone();
+                thread::spawn(move || {
+                    let response = handle_message(&request.method, request.params);
+                    host.reply(Some(request.id), response);
+                });
+            }
+            Err(rejected) => host.reply(rejected.id, error_response(rejected.code, &rejected.message)),
         }
     }
 }
 
-fn handle_verify_biometric() -> NativeResponse {
-    // For now, just return success with mock biometric verification
+fn handle_message(action: &str, data: Option<Value>) -> NativeResponse {
+    // Actions that use keys must say whose request they carry
+    let authorized = utils::get_data_dir()
+        .and_then(|dir| permissions::authorize_action(&dir, action, data.as_ref().unwrap_or(&Value::Null)));
+    if let Err(e) = authorized {
+        return e.into();
+    }
+    match action {
+        "create_wallet" => handle_create_wallet(data),
+        "restore_wallet" => handle_restore_wallet(data),
+        "unlock_wallet" => handle_unlock_wallet(data),
+        "lock_wallet" => handle_lock_wallet(),
+        "agent_status" => handle_agent_status(),
+        "verify_biometric" => handle_verify_biometric(data),
+        "register_biometric" => handle_register_biometric(),
+        "get_wallet_info" => handle_get_wallet_info(data),
+        "add_account" => handle_add_account(data),
+        "update_account" => handle_update_account(data),
+        "delete_account" => handle_delete_account(data),
+        "watch_account" => handle_watch_account(data),
+        "send_transaction" => handle_send_transaction(data),
+        "get_transaction_status" => handle_get_transaction_status(data),
+        "list_transactions" => handle_list_transactions(data),
+        "get_nonce_status" => handle_get_nonce_status(data),
+        "speed_up_transaction" => handle_replace_transaction(data, Replacement::SpeedUp),
+        "cancel_transaction" => handle_replace_transaction(data, Replacement::Cancel),
+        "sign_message" => handle_sign_message(data),
+        "verify_message" => handle_verify_message(data),
+        "preview_typed_data" => handle_preview_typed_data(data),
+        "sign_typed_data" => handle_sign_typed_data(data),
+        "get_networks" => handle_get_networks(),
+        "add_network" => handle_add_network(data),
+        "switch_network" => handle_switch_network(data),
+        "get_balance" => handle_get_balance(data),
+        "get_gas_price" => handle_get_gas_price(data),
+        "import_keystore" => handle_import_keystore(data),
+        "export_keystore" => handle_export_keystore(data),
+        "change_passphrase" => handle_change_passphrase(data),
+        "provider_request" => handle_provider_request(data),
+        _ => error_response("UNKNOWN_ACTION", "Unknown action"),
+    }
+}
+
+fn handle_create_wallet(data: Option<Value>) -> NativeResponse {
+    let data = data.unwrap_or(Value::Null);
+    let word_count = data["words"].as_u64().unwrap_or(12) as usize;
+    let bip39_passphrase = data["bip39_passphrase"].as_str();
+
+    let phrase = match mnemonic::generate_mnemonic(word_count) {
+        Ok(phrase) => phrase,
+        Err(e) => return e.into(),
+    };
+    let wallet = match mnemonic::wallet_from_mnemonic(&phrase, bip39_passphrase, mnemonic::DEFAULT_DERIVATION_PATH) {
+        Ok(wallet) => wallet,
+        Err(e) => return e.into(),
+    };
+
+    // With a passphrase the seed is stored right away, so the CLI and WASM share it
+    let file = match data["passphrase"].as_str() {
+        Some(passphrase) => {
+            let result = wallet::default_kdf_params().and_then(|kdf_params| {
+                wallet::restore_wallet(&phrase, bip39_passphrase, passphrase, kdf_params)
+            });
+            match result {
+                Ok((_, filename)) => Some(filename.display().to_string()),
+                Err(e) => return e.into(),
+            }
+        }
+        None => None,
+    };
+
     NativeResponse {
         success: true,
         data: Some(serde_json::json!({
-            "verified": true,
-            "method": "Touch ID"
+            "address": wallet::address_string(wallet.address()),
+            "mnemonic": phrase,
+            "derivation_path": mnemonic::DEFAULT_DERIVATION_PATH,
+            "private_key": format!("0x{}", hex::encode(wallet.signer().to_bytes())),
+            "file": file,
+            "created_at": chrono::Utc::now().to_rfc3339()
         })),
         error: None,
+        code: None,
+    }
+}
+
+fn handle_restore_wallet(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No restore data provided"),
+    };
+
+    let (phrase, passphrase) = match (data["mnemonic"].as_str(), data["passphrase"].as_str()) {
+        (Some(phrase), Some(passphrase)) => (phrase, passphrase),
+        _ => return error_response("INVALID_REQUEST", "Missing mnemonic or passphrase"),
+    };
+    let bip39_passphrase = data["bip39_passphrase"].as_str();
+
+    let result = wallet::default_kdf_params().and_then(|kdf_params| {
+        wallet::restore_wallet(phrase, bip39_passphrase, passphrase, kdf_params)
+    });
+    match result {
+        Ok((address, filename)) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({
+                "address": wallet::address_string(address),
+                "derivation_path": mnemonic::DEFAULT_DERIVATION_PATH,
+                "file": filename.display().to_string()
+            })),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_unlock_wallet(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No unlock data provided"),
+    };
+    // Without a passphrase this only succeeds while the agent holds the key
+    match unlock_selected(&data, data["passphrase"].as_str()) {
+        Ok((wallet_file, wallet)) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({
+                "address": wallet::address_string(wallet.address()),
+                "file": wallet_file.display().to_string(),
+                "unlocked": true
+            })),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+/// Hide the accounts from connected pages until the passphrase is entered again,
+/// and have the agent forget its keys
+fn handle_lock_wallet() -> NativeResponse {
+    match utils::ensure_data_dir().and_then(|dir| agent::lock(&dir)) {
+        Ok(agent) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({ "locked": true, "agent": agent })),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+/// What the agent holds, so the extension knows whether to ask for the passphrase
+fn handle_agent_status() -> NativeResponse {
+    match utils::get_data_dir().and_then(|dir| agent::status(&dir)) {
+        Ok(status) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({ "running": status.is_some(), "status": status })),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+/// Unlock the account chosen by `file` and/or `address`/`label` in a request
+/// and record the use. Without either, there must be exactly one wallet. Without a
+/// passphrase the agent signs with the key it holds, and a key unlocked here is given to it.
+fn unlock_selected(data: &Value, passphrase: Option<&str>) -> Result<(PathBuf, UnlockedAccount), VeroxError> {
+    // `address` takes an address or a label, like the CLI's --account; a transaction's
+    // `from` selects the account too
+    let account = data["address"]
+        .as_str()
+        .or_else(|| data["label"].as_str())
+        .or_else(|| data["from"].as_str());
+
+    let (wallet_file, address) = match data["file"].as_str() {
+        Some(file) => (PathBuf::from(file), account.map(str::to_string)),
+        None => {
+            let accounts = wallet::find_accounts(account)?;
+            match accounts.as_slice() {
+                [meta] => (wallet::account_file(meta)?, Some(meta.address.clone())),
+                _ => {
+                    return Err(VeroxError::InvalidArgument(format!(
+                        "{} wallets found, choose one by address, label or file",
+                        accounts.len()
+                    )))
+                }
+            }
+        }
+    };
+    let dir = utils::get_data_dir()?;
+    let Some(passphrase) = passphrase else {
+        let address = address.as_deref().and_then(|address| address.parse().ok()).ok_or(VeroxError::Locked)?;
+        return Ok((wallet_file, agent::account(&dir, address)?));
+    };
+    let wallet = wallet::load_account(&wallet_file, passphrase, address.as_deref())?;
+    let _ = wallet::mark_used(&wallet_file, wallet.address());
+    // Entering the passphrase unlocks the wallet for connected pages too
+    if let Err(e) = events::set_locked(&dir, false) {
+        eprintln!("Could not unlock for connected pages: {}", e);
+    }
+    let _ = agent::add(&dir, &wallet);
+    Ok((wallet_file, wallet.into()))
+}
+
+/// The keystore file a request picks by `file` or by `address` (an address or a
+/// label), with the account picked in it. The host cannot ask, so several candidates
+/// are an error. `label` is left alone, as `add_account` names the new account with it.
+fn selected_keystore(data: &Value) -> Result<(PathBuf, Option<String>), VeroxError> {
+    let account = data["address"].as_str();
+    if let Some(file) = data["file"].as_str() {
+        return Ok((PathBuf::from(file), account.map(str::to_string)));
+    }
+    match wallet::find_keystores(account)?.as_slice() {
+        [meta] => Ok((wallet::account_file(meta)?, Some(meta.address.clone()))),
+        keystores => Err(VeroxError::InvalidArgument(format!(
+            "{} wallets found, choose one by address, label or file",
+            keystores.len()
+        ))),
+    }
+}
+
+/// Check biometrics before unlocking the account as `unlock_wallet` does. The
+/// registered biometric secret can't decrypt a keystore, so this is a presence check
+/// on top of the passphrase, or of a key the agent already holds, not a way around it.
+fn handle_verify_biometric(data: Option<Value>) -> NativeResponse {
+    let data = data.unwrap_or(Value::Null);
+    let result = biometric::verify().and_then(|verified| {
+        if !verified {
+            return Err(VeroxError::Biometric("biometric verification failed".to_string()));
+        }
+        unlock_selected(&data, data["passphrase"].as_str())
+    });
+    match result {
+        Ok((wallet_file, wallet)) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({
+                "verified": true,
+                "method": biometric::get_biometric_name(),
+                "address": wallet::address_string(wallet.address()),
+                "file": wallet_file.display().to_string(),
+                "unlocked": true
+            })),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
     }
 }
 
@@ -169,44 +514,917 @@ fn handle_register_biometric() -> NativeResponse {
             "method": "Touch ID"
         })),
         error: None,
+        code: None,
     }
 }
 
-fn handle_get_wallet_info() -> NativeResponse {
-    NativeResponse {
-        success: true,
-        data: Some(serde_json::json!({
-            "address": "0x742d35Cc6634C0532925a3b8d0b4E1b87D5E2d3c",
-            "balance": "0.5234",
-            "network": "mainnet"
-        })),
-        error: None,
+fn handle_get_wallet_info(data: Option<Value>) -> NativeResponse {
+    let include_hidden = data.as_ref().and_then(|data| data["include_hidden"].as_bool()).unwrap_or(false);
+    let result = wallet::list_accounts(include_hidden)
+        .and_then(|accounts| network::load_config().map(|config| (accounts, config.selected_network())));
+    match result {
+        Ok((accounts, network)) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({
+                "address": accounts.first().map(|meta| meta.address.clone()),
+                "accounts": accounts,
+                "network": network
+            })),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_add_account(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No account data provided"),
+    };
+
+    let passphrase = match data["passphrase"].as_str() {
+        Some(passphrase) => passphrase,
+        None => return error_response("INVALID_REQUEST", "Missing passphrase"),
+    };
+    let (wallet_file, _) = match selected_keystore(&data) {
+        Ok(selected) => selected,
+        Err(e) => return e.into(),
+    };
+    let scheme = match data["scheme"].as_str().unwrap_or("bip44").parse::<DerivationScheme>() {
+        Ok(scheme) => scheme,
+        Err(e) => return e.into(),
+    };
+    let index = data["index"].as_u64().map(|index| index as u32);
+    let label = data["label"].as_str().map(str::to_string);
+
+    match wallet::add_account(&wallet_file, passphrase, &scheme, index, label) {
+        Ok(meta) => NativeResponse {
+            success: true,
+            data: serde_json::to_value(meta).ok(),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_update_account(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No account data provided"),
+    };
+    let address = match data["address"].as_str() {
+        Some(address) => address,
+        None => return error_response("INVALID_REQUEST", "Missing address"),
+    };
+
+    let result = match (data.get("label"), data["hidden"].as_bool()) {
+        (None, None) => return error_response("INVALID_REQUEST", "Nothing to update, pass label or hidden"),
+        (None, Some(hidden)) => wallet::set_account_hidden(address, hidden),
+        // Hide by address afterwards, since the rename may have changed the label
+        (Some(label), hidden) => wallet::set_account_label(address, label.as_str().map(str::to_string))
+            .and_then(|meta| match hidden {
+                Some(hidden) => wallet::set_account_hidden(&meta.address, hidden),
+                None => Ok(meta),
+            }),
+    };
+
+    match result {
+        Ok(meta) => NativeResponse {
+            success: true,
+            data: serde_json::to_value(meta).ok(),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_delete_account(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No account data provided"),
+    };
+    let address = match data["address"].as_str() {
+        Some(address) => address,
+        None => return error_response("INVALID_REQUEST", "Missing address"),
+    };
+
+    match wallet::delete_account(address, data["passphrase"].as_str()) {
+        Ok(removed) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({ "deleted": removed })),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_watch_account(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No account data provided"),
+    };
+    let address = match data["address"].as_str() {
+        Some(address) => address,
+        None => return error_response("INVALID_REQUEST", "Missing address"),
+    };
+
+    match wallet::add_watch_only(address, data["label"].as_str().map(str::to_string)) {
+        Ok(meta) => NativeResponse {
+            success: true,
+            data: serde_json::to_value(meta).ok(),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
     }
 }
 
 fn handle_send_transaction(data: Option<Value>) -> NativeResponse {
-    match data {
-        Some(tx_data) => {
-            // Parse transaction data
-            let to = tx_data["to"].as_str().unwrap_or("");
-            let amount = tx_data["amount"].as_str().unwrap_or("0");
-            
-            // Mock transaction sending
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No transaction data provided"),
+    };
+    let passphrase = data["passphrase"].as_str();
+    let mut request = match TxRequest::from_json(&data) {
+        Ok(request) => request,
+        Err(e) => return e.into(),
+    };
+    // Fields the request leaves out come from the network, and every fee is
+    // checked against recent medians unless the user explicitly allowed it
+    let speed = match data["speed"].as_str().map(str::parse::<FeeSpeed>).transpose() {
+        Ok(speed) => speed.unwrap_or(FeeSpeed::Normal),
+        Err(e) => return e.into(),
+    };
+    let allow_high_fee = data["allow_high_fee"].as_bool().unwrap_or(false);
+    // `broadcast: false` only signs, for callers that submit the transaction themselves
+    let broadcast = data["broadcast"].as_bool().unwrap_or(true);
+    let network = match request.chain_id {
+        Some(chain_id) => network::resolve_network(Some(&chain_id.to_string())),
+        None => request_network(&data),
+    };
+
+    let result = network.and_then(|network| {
+        let (_, wallet) = unlock_selected(&data, passphrase)?;
+        if let Some(from) = request.from.filter(|from| *from != wallet.address()) {
+            return Err(VeroxError::InvalidArgument(format!(
+                "transaction is from {} but the unlocked account is {}",
+                wallet::address_string(from),
+                wallet::address_string(wallet.address())
+            )));
+        }
+        provider::block_on(async {
+            let rpc = RpcProvider::connect(&network).await?;
+            if broadcast {
+                let nonces = NonceManager::open()?;
+                let (params, tracked) =
+                    pending::send(&rpc, &nonces, &wallet, request, speed, allow_high_fee).await?;
+                return Ok((params, tracked.hash, tracked.raw, tracked.status.to_string()));
+            }
+            rpc.fill_request(&mut request, wallet.address(), speed, allow_high_fee).await?;
+            let params = request.into_params()?;
+            let signed = wallet.sign_transaction(&params)?;
+            Ok((params, signed.hash, signed.raw, "signed".to_string()))
+        })?
+        .map(|(params, hash, raw, status)| (network, wallet.address(), params, hash, raw, status))
+    });
+    match result {
+        Ok((network, from, params, hash, raw, status)) => {
+            let tx_hash = format!("{:?}", hash);
             NativeResponse {
                 success: true,
                 data: Some(serde_json::json!({
-                    "tx_hash": "0x1234567890abcdef1234567890abcdef12345678",
-                    "to": to,
-                    "amount": amount,
-                    "status": "pending"
+                    "from": wallet::address_string(from),
+                    "to": params.to.map(wallet::address_string),
+                    "chain_id": params.chain_id,
+                    "nonce": params.nonce.to_string(),
+                    "tx_hash": tx_hash,
+                    "raw_transaction": raw.to_string(),
+                    "status": status,
+                    "explorer_url": network.tx_url(&tx_hash)
                 })),
                 error: None,
+                code: None,
             }
         }
-        None => NativeResponse {
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_get_transaction_status(data: Option<Value>) -> NativeResponse {
+    let data = data.unwrap_or(Value::Null);
+    let hash: H256 = match data["tx_hash"].as_str().or_else(|| data["hash"].as_str()).map(str::parse) {
+        Some(Ok(hash)) => hash,
+        Some(Err(_)) => return error_response("INVALID_REQUEST", "Invalid transaction hash"),
+        None => return error_response("INVALID_REQUEST", "Missing transaction hash"),
+    };
+    let confirmations = data["confirmations"].as_u64().unwrap_or(pending::CONFIRMATIONS);
+
+    let result = utils::get_data_dir()
+        .and_then(|dir| provider::block_on(async { pending::check(&dir, hash, confirmations).await })?);
+    match result {
+        Ok(tx) => NativeResponse {
+            success: true,
+            data: serde_json::to_value(tx).ok(),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_list_transactions(data: Option<Value>) -> NativeResponse {
+    let data = data.unwrap_or(Value::Null);
+    let address = match data["address"].as_str().map(str::parse::<Address>).transpose() {
+        Ok(address) => address,
+        Err(_) => return error_response("INVALID_REQUEST", "Invalid address"),
+    };
+    let chain_id = data["chain_id"].as_u64();
+
+    match utils::get_data_dir().and_then(|dir| TxStore::load(&dir)) {
+        // Most recent first
+        Ok(store) => {
+            let transactions: Vec<_> = store
+                .transactions
+                .into_iter()
+                .rev()
+                .filter(|tx| address.is_none_or(|address| tx.from == address))
+                .filter(|tx| chain_id.is_none_or(|chain_id| tx.chain_id == chain_id))
+                .collect();
+            NativeResponse {
+                success: true,
+                data: Some(serde_json::json!({ "transactions": transactions })),
+                error: None,
+                code: None,
+            }
+        }
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_replace_transaction(data: Option<Value>, kind: Replacement) -> NativeResponse {
+    let mut data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No transaction data provided"),
+    };
+    let hash: H256 = match data["tx_hash"].as_str().map(str::parse) {
+        Some(Ok(hash)) => hash,
+        Some(Err(_)) => return error_response("INVALID_REQUEST", "Invalid transaction hash"),
+        None => return error_response("INVALID_REQUEST", "Missing tx_hash"),
+    };
+    let passphrase = data["passphrase"].as_str().map(str::to_string);
+    // Replacements go for the gas spike, so fast unless asked otherwise
+    let speed = match data["speed"].as_str().map(str::parse::<FeeSpeed>).transpose() {
+        Ok(speed) => speed.unwrap_or(FeeSpeed::Fast),
+        Err(e) => return e.into(),
+    };
+    let allow_high_fee = data["allow_high_fee"].as_bool().unwrap_or(false);
+
+    let result = utils::get_data_dir().and_then(|dir| {
+        let original = TxStore::load(&dir)?
+            .get(hash)
+            .cloned()
+            .ok_or_else(|| VeroxError::InvalidArgument(format!("transaction {:?} is not tracked", hash)))?;
+        // The account that sent the original signs the replacement
+        data["address"] = wallet::address_string(original.from).into();
+        let (_, wallet) = unlock_selected(&data, passphrase.as_deref())?;
+        let network = network::resolve_network(Some(&original.chain_id.to_string()))?;
+        let (params, tracked) = provider::block_on(async {
+            let rpc = RpcProvider::connect(&network).await?;
+            replace::replace(&rpc, &dir, &wallet, hash, kind, speed, allow_high_fee).await
+        })??;
+        Ok((network, params, tracked))
+    });
+    match result {
+        Ok((network, params, tracked)) => {
+            let tx_hash = format!("{:?}", tracked.hash);
+            NativeResponse {
+                success: true,
+                data: Some(serde_json::json!({
+                    "replaces": format!("{:?}", hash),
+                    "tx_hash": tx_hash,
+                    "nonce": tracked.nonce,
+                    "chain_id": tracked.chain_id,
+                    "max_fee_per_gas": params.max_fee_per_gas.to_string(),
+                    "max_priority_fee_per_gas": params.max_priority_fee_per_gas.to_string(),
+                    "gas_price": params.gas_price.to_string(),
+                    "status": tracked.status,
+                    "explorer_url": network.tx_url(&tx_hash)
+                })),
+                error: None,
+                code: None,
+            }
+        }
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_get_nonce_status(data: Option<Value>) -> NativeResponse {
+    let data = data.unwrap_or(Value::Null);
+    let address: Address = match data["address"].as_str().map(str::parse) {
+        Some(Ok(address)) => address,
+        Some(Err(_)) => return error_response("INVALID_REQUEST", "Invalid address"),
+        None => return error_response("INVALID_REQUEST", "Missing address"),
+    };
+
+    let result = request_network(&data).and_then(|network| {
+        let nonces = NonceManager::open()?;
+        provider::block_on(async {
+            let rpc = RpcProvider::connect(&network).await?;
+            nonces.status(&rpc, address).await
+        })?
+    });
+    match result {
+        Ok(status) => NativeResponse {
+            success: true,
+            data: serde_json::to_value(status).ok(),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_sign_message(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No message data provided"),
+    };
+    let text = match data["message"].as_str() {
+        Some(text) => text,
+        None => return error_response("INVALID_REQUEST", "Missing message"),
+    };
+    let bytes = message::decode_message(text);
+
+    let result = unlock_selected(&data, data["passphrase"].as_str())
+        .and_then(|(_, wallet)| wallet.sign_message(&bytes).map(|signature| (wallet, signature)));
+    match result {
+        Ok((wallet, signature)) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({
+                "address": wallet::address_string(wallet.address()),
+                "signature": format!("0x{}", signature),
+                "message_hash": format!("{:?}", message::message_hash(&bytes))
+            })),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_verify_message(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No message data provided"),
+    };
+    let (text, signature) = match (data["message"].as_str(), data["signature"].as_str()) {
+        (Some(text), Some(signature)) => (text, signature),
+        _ => return error_response("INVALID_REQUEST", "Missing message or signature"),
+    };
+
+    let result = message::parse_signature(signature)
+        .and_then(|signature| message::recover_signer(&message::decode_message(text), &signature));
+    match result {
+        Ok(signer) => {
+            let signer = wallet::address_string(signer);
+            // `valid` is only reported when the caller names the expected signer
+            let valid = data["address"].as_str().map(|address| address.eq_ignore_ascii_case(&signer));
+            NativeResponse {
+                success: true,
+                data: Some(serde_json::json!({ "address": signer, "valid": valid })),
+                error: None,
+                code: None,
+            }
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Parse `typed_data` from a request and build what the approval prompt shows
+fn typed_data_preview(data: &Value) -> Result<(TypedData, Value), VeroxError> {
+    let payload = typed_data::parse_typed_data(&data["typed_data"])?;
+    let hashes = typed_data::hash_typed_data(&payload)?;
+    let preview = serde_json::json!({
+        "primary_type": payload.primary_type,
+        "chain_id": payload.domain.chain_id.and_then(|chain_id| u64::try_from(chain_id).ok()),
+        "fields": typed_data::field_tree(&payload)?,
+        "domain_separator": format!("{:?}", hashes.domain_separator),
+        "digest": format!("{:?}", hashes.digest)
+    });
+    Ok((payload, preview))
+}
+
+fn handle_preview_typed_data(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No typed data provided"),
+    };
+    match typed_data_preview(&data) {
+        Ok((_, preview)) => NativeResponse {
+            success: true,
+            data: Some(preview),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_sign_typed_data(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No typed data provided"),
+    };
+    let passphrase = data["passphrase"].as_str();
+
+    let result = typed_data_preview(&data).and_then(|(payload, preview)| {
+        let (_, wallet) = unlock_selected(&data, passphrase)?;
+        let signature = wallet.sign_typed_data(&payload)?;
+        Ok((wallet, signature, preview))
+    });
+    match result {
+        Ok((wallet, signature, mut preview)) => {
+            preview["address"] = wallet::address_string(wallet.address()).into();
+            preview["signature"] = format!("0x{}", signature).into();
+            NativeResponse {
+                success: true,
+                data: Some(preview),
+                error: None,
+                code: None,
+            }
+        }
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_get_networks() -> NativeResponse {
+    match network::load_config() {
+        Ok(config) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({
+                "selected": config.selected_network().chain_id,
+                "networks": config.networks()
+            })),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_add_network(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No network data provided"),
+    };
+    let network: Network = match serde_json::from_value(data) {
+        Ok(network) => network,
+        Err(e) => return error_response("INVALID_REQUEST", &format!("Invalid network: {}", e)),
+    };
+    let result = network::update_config(|config| config.add(network.clone()));
+    match result {
+        Ok(()) => NativeResponse {
+            success: true,
+            data: serde_json::to_value(network).ok(),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_switch_network(data: Option<Value>) -> NativeResponse {
+    // `network` is a name or chain ID
+    let selector = match data.as_ref().and_then(|data| match &data["network"] {
+        Value::Number(chain_id) => Some(chain_id.to_string()),
+        Value::String(selector) => Some(selector.clone()),
+        _ => None,
+    }) {
+        Some(selector) => selector,
+        None => return error_response("INVALID_REQUEST", "Missing network"),
+    };
+    let result = network::update_config(|config| config.select(&selector));
+    match result {
+        Ok(selected) => NativeResponse {
+            success: true,
+            data: serde_json::to_value(selected).ok(),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+/// The network named by `network` in a request, or the selected one
+fn request_network(data: &Value) -> Result<Network, VeroxError> {
+    match &data["network"] {
+        Value::Number(chain_id) => network::resolve_network(Some(&chain_id.to_string())),
+        network => network::resolve_network(network.as_str()),
+    }
+}
+
+fn handle_get_balance(data: Option<Value>) -> NativeResponse {
+    let data = data.unwrap_or(Value::Null);
+    let address: Address = match data["address"].as_str().map(str::parse) {
+        Some(Ok(address)) => address,
+        Some(Err(_)) => return error_response("INVALID_REQUEST", "Invalid address"),
+        None => return error_response("INVALID_REQUEST", "Missing address"),
+    };
+
+    let result = request_network(&data).and_then(|network| {
+        provider::block_on(async {
+            let rpc = RpcProvider::connect(&network).await?;
+            Ok::<_, VeroxError>((rpc.balance(address).await?, rpc.nonce(address).await?))
+        })?
+        .map(|(balance, nonce)| (network, balance, nonce))
+    });
+    match result {
+        Ok((network, balance, nonce)) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({
+                "address": wallet::address_string(address),
+                "chain_id": network.chain_id,
+                "balance": format_ether(balance),
+                "balance_wei": balance.to_string(),
+                "symbol": network.native_currency.symbol,
+                "nonce": nonce.to_string()
+            })),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_get_gas_price(data: Option<Value>) -> NativeResponse {
+    let data = data.unwrap_or(Value::Null);
+    let result = request_network(&data).and_then(|network| {
+        provider::block_on(async {
+            let rpc = RpcProvider::connect(&network).await?;
+            fees::estimate_fees(&rpc).await
+        })?
+        .map(|estimate| (network, estimate))
+    });
+    match result {
+        Ok((network, estimate)) => {
+            let mut data = serde_json::to_value(&estimate).unwrap_or_default();
+            data["chain_id"] = network.chain_id.into();
+            // The old single-price field, now the normal preset
+            data["gas_price"] = estimate.preset(FeeSpeed::Normal).max_fee_per_gas.to_string().into();
+            NativeResponse {
+                success: true,
+                data: Some(data),
+                error: None,
+                code: None,
+            }
+        }
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_import_keystore(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No keystore data provided"),
+    };
+
+    // The keystore may arrive as the raw file contents or as an already-parsed object
+    let v3_json = match &data["keystore"] {
+        Value::String(contents) => contents.clone(),
+        Value::Object(_) => data["keystore"].to_string(),
+        _ => return error_response("INVALID_REQUEST", "Missing keystore"),
+    };
+    let password = data["password"].as_str().unwrap_or("");
+    let passphrase = match data["passphrase"].as_str() {
+        Some(passphrase) => passphrase,
+        None => return error_response("INVALID_REQUEST", "Missing passphrase"),
+    };
+
+    let result = wallet::default_kdf_params()
+        .and_then(|kdf_params| wallet::import_v3_keystore(&v3_json, password, passphrase, kdf_params));
+    match result {
+        Ok((address, filename)) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({
+                "address": wallet::address_string(address),
+                "file": filename.display().to_string()
+            })),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_export_keystore(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No export data provided"),
+    };
+
+    let (wallet_file, address) = match selected_keystore(&data) {
+        Ok(selected) => selected,
+        Err(e) => return e.into(),
+    };
+    let (passphrase, password) = match (data["passphrase"].as_str(), data["password"].as_str()) {
+        (Some(passphrase), Some(password)) => (passphrase, password),
+        _ => return error_response("INVALID_REQUEST", "Missing passphrase or password"),
+    };
+
+    match wallet::export_v3_keystore(&wallet_file, address.as_deref(), passphrase, password) {
+        Ok((address, v3_json)) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({
+                "address": wallet::address_string(address),
+                "filename": wallet::v3_keystore_filename(address),
+                "keystore": serde_json::from_str::<Value>(&v3_json).unwrap_or(Value::Null)
+            })),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+fn handle_change_passphrase(data: Option<Value>) -> NativeResponse {
+    let data = match data {
+        Some(data) => data,
+        None => return error_response("INVALID_REQUEST", "No passphrase data provided"),
+    };
+
+    let (wallet_file, _) = match selected_keystore(&data) {
+        Ok(selected) => selected,
+        Err(e) => return e.into(),
+    };
+    let (old_passphrase, new_passphrase) =
+        match (data["old_passphrase"].as_str(), data["new_passphrase"].as_str()) {
+            (Some(old_passphrase), Some(new_passphrase)) => (old_passphrase, new_passphrase),
+            _ => return error_response("INVALID_REQUEST", "Missing old or new passphrase"),
+        };
+
+    let result = wallet::default_kdf_params().and_then(|kdf_params| {
+        wallet::change_passphrase(&wallet_file, old_passphrase, new_passphrase, kdf_params)
+    });
+    match result {
+        Ok(()) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({
+                "file": wallet_file.display().to_string(),
+                "changed": true
+            })),
+            error: None,
+            code: None,
+        },
+        Err(e) => e.into(),
+    }
+}
+
+/// An EIP-1193 `request({ method, params })` from the page at `origin`. Requests the
+/// user must approve carry `approved: true` once the extension has asked, or the
+/// passphrase for ones that sign; `approved: false` is the user saying no. Approving
+/// a connection may narrow it with `accounts` and `chains`. Failures carry the
+/// EIP-1193 error object in `data`.
+fn handle_provider_request(data: Option<Value>) -> NativeResponse {
+    let data = data.unwrap_or_default();
+    let method = match data["method"].as_str() {
+        Some(method) => method,
+        None => return error_response("INVALID_REQUEST", "Missing method"),
+    };
+    let result = ProviderRequest::parse(method, &data["params"]).and_then(|request| provider_call(&data, request));
+    match result {
+        Ok(result) => NativeResponse {
+            success: true,
+            data: Some(serde_json::json!({ "result": result })),
+            error: None,
+            code: None,
+        },
+        Err(e) => NativeResponse {
             success: false,
-            data: None,
-            error: Some("No transaction data provided".to_string()),
+            data: serde_json::to_value(&e).ok(),
+            error: Some(e.message),
+            code: Some("PROVIDER_ERROR".to_string()),
+        },
+    }
+}
+
+fn provider_call(data: &Value, request: ProviderRequest) -> Result<Value, ProviderError> {
+    let origin = data["origin"]
+        .as_str()
+        .ok_or_else(|| ProviderError::unauthorized("The request has no origin"))
+        .and_then(|origin| permissions::normalize_origin(origin).map_err(|e| ProviderError::unauthorized(e.to_string())))?;
+    let dir = utils::get_data_dir()?;
+    let grant = PermissionStore::load(&dir)?.get(&origin, Utc::now()).cloned();
+
+    let passphrase = data["passphrase"].as_str();
+    let approved = match data["approved"].as_bool() {
+        Some(false) => return Err(ProviderError::user_rejected()),
+        approved => approved == Some(true) || passphrase.is_some(),
+    };
+    // A connected page asking for its accounts again doesn't bother the user
+    let connected = matches!(request, ProviderRequest::RequestAccounts) && grant.is_some();
+    if request.needs_approval() && !connected && !approved {
+        return Err(ProviderError::unauthorized("The request needs the user's approval"));
+    }
+    let grant = match grant {
+        Some(grant) => Some(grant),
+        None if request.needs_grant() => {
+            return Err(ProviderError::unauthorized(format!(
+                "{} is not connected, call eth_requestAccounts first",
+                origin
+            )))
+        }
+        None => None,
+    };
+    let check_chain = |chain_id: u64| match &grant {
+        Some(grant) if !grant.allows_chain(chain_id) => Err(ProviderError::unauthorized(format!(
+            "{} may not use chain {}",
+            origin,
+            eip1193::chain_id_hex(chain_id)
+        ))),
+        _ => Ok(()),
+    };
+    let unlock = |address: Address| -> Result<UnlockedAccount, ProviderError> {
+        if !grant.as_ref().is_some_and(|grant| grant.allows_account(address)) {
+            return Err(ProviderError::unauthorized(format!(
+                "{} may not use account {}",
+                origin,
+                wallet::address_string(address)
+            )));
+        }
+        // Without a passphrase the agent has to hold the key
+        let selector = serde_json::json!({ "address": wallet::address_string(address) });
+        Ok(unlock_selected(&selector, passphrase)?.1)
+    };
+
+    match request {
+        ProviderRequest::Accounts => match grant {
+            Some(grant) if !events::is_locked(&dir) => Ok(visible_accounts(&grant)?.into()),
+            // Not connected, or locked, is an empty list rather than an error
+            _ => Ok(serde_json::json!([])),
+        },
+        ProviderRequest::RequestAccounts => {
+            let grant = match grant {
+                Some(grant) => grant,
+                None => grant_access(&dir, &origin, data)?,
+            };
+            let accounts = visible_accounts(&grant)?;
+            if events::is_locked(&dir) {
+                // Any of the page's accounts proves the passphrase
+                let passphrase = passphrase.ok_or_else(|| ProviderError::unauthorized("The wallet is locked"))?;
+                let first = accounts.first().ok_or_else(|| ProviderError::unauthorized("The wallet is locked"))?;
+                unlock_selected(&serde_json::json!({ "address": first }), Some(passphrase))?;
+            }
+            Ok(accounts.into())
+        }
+        ProviderRequest::RequestPermissions => Ok(grant_access(&dir, &origin, data)?.to_permissions()),
+        ProviderRequest::GetPermissions => {
+            Ok(grant.map(|grant| grant.to_permissions()).unwrap_or_else(|| serde_json::json!([])))
+        }
+        ProviderRequest::RevokePermissions => {
+            PermissionStore::update(&dir, |store| store.revoke(&origin))?;
+            Ok(Value::Null)
+        }
+        ProviderRequest::ChainId => {
+            Ok(eip1193::chain_id_hex(network::load_config()?.selected_network().chain_id).into())
+        }
+        ProviderRequest::SendTransaction(request) => {
+            let from = request.from.ok_or_else(|| ProviderError::invalid_params("transaction has no from address"))?;
+            let wallet = unlock(from)?;
+            let network = match request.chain_id {
+                Some(chain_id) => network::resolve_network(Some(&chain_id.to_string()))?,
+                None => network::resolve_network(None)?,
+            };
+            check_chain(network.chain_id)?;
+            let (_, tracked) = provider::block_on(async {
+                let rpc = RpcProvider::connect(&network).await?;
+                let nonces = NonceManager::open()?;
+                pending::send(&rpc, &nonces, &wallet, *request, FeeSpeed::Normal, false).await
+            })??;
+            Ok(format!("{:?}", tracked.hash).into())
+        }
+        ProviderRequest::PersonalSign { address, message } => {
+            let signature = unlock(address)?.sign_message(&message)?;
+            Ok(format!("0x{}", signature).into())
         }
+        ProviderRequest::SignTypedData { address, typed_data } => {
+            let payload = typed_data::parse_typed_data(&typed_data)?;
+            let signature = unlock(address)?.sign_typed_data(&payload)?;
+            Ok(format!("0x{}", signature).into())
+        }
+        ProviderRequest::SwitchChain(chain_id) => {
+            check_chain(chain_id)?;
+            if network::load_config()?.find(&chain_id.to_string()).is_err() {
+                return Err(ProviderError::new(
+                    eip1193::UNRECOGNIZED_CHAIN,
+                    format!("Unrecognized chain ID {}", eip1193::chain_id_hex(chain_id)),
+                ));
+            }
+            network::update_config(|config| config.select(&chain_id.to_string()))?;
+            Ok(Value::Null)
+        }
+        ProviderRequest::AddChain(network) => {
+            // A page may add chains but never change the RPC of one the user already has
+            if network::load_config()?.find(&network.chain_id.to_string()).is_err() {
+                network::update_config(|config| match config.find(&network.chain_id.to_string()) {
+                    Ok(_) => Ok(()),
+                    Err(_) => config.add(network),
+                })?;
+            }
+            Ok(Value::Null)
+        }
+        ProviderRequest::WatchAsset { address, symbol, decimals, image } => {
+            let chain_id = network::load_config()?.selected_network().chain_id;
+            check_chain(chain_id)?;
+            let dir = utils::ensure_data_dir()?;
+            let mut assets = AssetList::load(&dir)?;
+            assets.add(WatchedAsset { chain_id, address, symbol, decimals, image })?;
+            assets.save(&dir)?;
+            Ok(true.into())
+        }
+        ProviderRequest::Proxy { method, params } => {
+            let network = network::resolve_network(None)?;
+            check_chain(network.chain_id)?;
+            provider::block_on(async {
+                let rpc = RpcProvider::connect(&network).await?;
+                Ok::<_, VeroxError>(eip1193::proxy(&rpc, &method, &params).await)
+            })??
+        }
+    }
+}
+
+/// Accounts a page may be shown: watch-only ones can't sign, so they never are
+fn signing_accounts() -> Result<Vec<Address>, VeroxError> {
+    Ok(wallet::list_accounts(false)?
+        .into_iter()
+        .filter(|meta| meta.key_type != KeyType::WatchOnly)
+        .filter_map(|meta| meta.address.parse().ok())
+        .collect())
+}
+
+/// The granted accounts that still exist, as pages see them
+fn visible_accounts(grant: &Grant) -> Result<Vec<String>, VeroxError> {
+    Ok(signing_accounts()?
+        .into_iter()
+        .filter(|address| grant.allows_account(*address))
+        .map(wallet::address_string)
+        .collect())
+}
+
+/// What connected pages can see now, short of whether the network answers
+fn provider_state() -> Result<ProviderState, VeroxError> {
+    let dir = utils::get_data_dir()?;
+    let locked = events::is_locked(&dir);
+    let accounts = PermissionStore::load(&dir)?
+        .grants
+        .iter()
+        .filter(|grant| !grant.is_expired(Utc::now()))
+        .map(|grant| {
+            let accounts = if locked { Vec::new() } else { visible_accounts(grant)? };
+            Ok((grant.origin.clone(), accounts))
+        })
+        .collect::<Result<_, VeroxError>>()?;
+    Ok(ProviderState {
+        chain_id: network::NetworkConfig::load(&dir)?.selected_network().chain_id,
+        connected: false,
+        accounts,
+    })
+}
+
+/// Record the user's approval of `origin`: the `accounts` and `chains` they picked,
+/// or every signing account on any chain
+fn grant_access(dir: &Path, origin: &str, data: &Value) -> Result<Grant, ProviderError> {
+    let available = signing_accounts()?;
+    let accounts = match data["accounts"].as_array() {
+        Some(picked) => picked
+            .iter()
+            .map(|address| {
+                address
+                    .as_str()
+                    .and_then(|address| address.parse().ok())
+                    .filter(|address| available.contains(address))
+                    .ok_or_else(|| ProviderError::invalid_params(format!("cannot grant account {}", address)))
+            })
+            .collect::<Result<Vec<Address>, _>>()?,
+        None => available,
+    };
+    let chains = match data["chains"].as_array() {
+        Some(chains) => chains.iter().map(eip1193::parse_chain_id).collect::<Result<Vec<u64>, _>>()?,
+        None => Vec::new(),
+    };
+    let grant = Grant::new(origin.to_string(), accounts, chains, Utc::now());
+    PermissionStore::update(dir, |store| store.grant(grant.clone()))?;
+    Ok(grant)
+}
+
+fn error_response(code: &str, message: &str) -> NativeResponse {
+    NativeResponse {
+        success: false,
+        data: None,
+        error: Some(message.to_string()),
+        code: Some(code.to_string()),
     }
 }
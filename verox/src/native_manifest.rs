@@ -0,0 +1,268 @@
+//! Registering `verox-native-host` with browsers
+//!
+//! A browser only starts a native messaging host it finds a manifest for: a JSON
+//! file named after the host, giving the binary's absolute path and which extensions
+//! may connect. Chromium-based browsers list extensions as `chrome-extension://<id>/`
+//! origins, while Firefox takes add-on IDs. Each browser looks in its own directories.
+//! Only the Linux locations are known here, per user under the home directory and
+//! system-wide under `/etc` or `/usr/lib`.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::error::{Result, VeroxError};
+use crate::utils;
+
+/// The name the extension passes to `connectNative`
+pub const HOST_NAME: &str = "com.verox.native_host";
+
+pub const HOST_DESCRIPTION: &str = "Verox Wallet Native Messaging Host";
+
+/// File name of the host binary, installed next to `verox`
+pub const HOST_BINARY: &str = "verox-native-host";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Browser {
+    Chrome,
+    Chromium,
+    Brave,
+    Firefox,
+}
+
+impl Browser {
+    pub const ALL: [Browser; 4] = [Browser::Chrome, Browser::Chromium, Browser::Brave, Browser::Firefox];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Browser::Chrome => "chrome",
+            Browser::Chromium => "chromium",
+            Browser::Brave => "brave",
+            Browser::Firefox => "firefox",
+        }
+    }
+
+    /// Firefox names extensions by add-on ID, the others by origin
+    pub fn is_firefox(&self) -> bool {
+        matches!(self, Browser::Firefox)
+    }
+
+    /// Where the browser looks for host manifests on Linux
+    pub fn manifest_dir(&self, scope: Scope, home: &Path) -> PathBuf {
+        match (self, scope) {
+            (Browser::Chrome, Scope::User) => home.join(".config/google-chrome/NativeMessagingHosts"),
+            (Browser::Chromium, Scope::User) => home.join(".config/chromium/NativeMessagingHosts"),
+            (Browser::Brave, Scope::User) => home.join(".config/BraveSoftware/Brave-Browser/NativeMessagingHosts"),
+            (Browser::Firefox, Scope::User) => home.join(".mozilla/native-messaging-hosts"),
+            (Browser::Chrome, Scope::System) => PathBuf::from("/etc/opt/chrome/native-messaging-hosts"),
+            (Browser::Chromium, Scope::System) => PathBuf::from("/etc/chromium/native-messaging-hosts"),
+            (Browser::Brave, Scope::System) => PathBuf::from("/etc/brave/native-messaging-hosts"),
+            (Browser::Firefox, Scope::System) => PathBuf::from("/usr/lib/mozilla/native-messaging-hosts"),
+        }
+    }
+
+    pub fn manifest_path(&self, scope: Scope, home: &Path) -> PathBuf {
+        self.manifest_dir(scope, home).join(format!("{}.json", HOST_NAME))
+    }
+}
+
+impl FromStr for Browser {
+    type Err = VeroxError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "chrome" | "google-chrome" => Ok(Browser::Chrome),
+            "chromium" => Ok(Browser::Chromium),
+            "brave" => Ok(Browser::Brave),
+            "firefox" => Ok(Browser::Firefox),
+            _ => Err(VeroxError::InvalidArgument(format!(
+                "unknown browser '{}', expected chrome, chromium, brave or firefox",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for Browser {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Whose browsers see the manifest: the current user's, or every user's
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    User,
+    System,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Scope::User => "user",
+            Scope::System => "system",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostManifest {
+    pub name: String,
+    pub description: String,
+    pub path: PathBuf,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_origins: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_extensions: Option<Vec<String>>,
+}
+
+impl HostManifest {
+    /// The manifest letting `extension_id` start the host at `host_path`. A Chromium
+    /// ID is the 32 letters a to p Chrome shows for the extension. A Firefox ID is the
+    /// add-on's `browser_specific_settings.gecko.id`.
+    pub fn new(browser: Browser, host_path: &Path, extension_id: &str) -> Result<Self> {
+        if !host_path.is_absolute() {
+            return Err(VeroxError::InvalidArgument(format!(
+                "the host path must be absolute, got {}",
+                host_path.display()
+            )));
+        }
+        let extension_id = extension_id.trim();
+        let (allowed_origins, allowed_extensions) = if browser.is_firefox() {
+            let valid = !extension_id.is_empty()
+                && !extension_id.chars().any(char::is_whitespace)
+                && (extension_id.contains('@') || (extension_id.starts_with('{') && extension_id.ends_with('}')));
+            if !valid {
+                return Err(VeroxError::InvalidArgument(format!("invalid Firefox add-on ID '{}'", extension_id)));
+            }
+            (None, Some(vec![extension_id.to_string()]))
+        } else {
+            let valid = extension_id.len() == 32 && extension_id.bytes().all(|b| (b'a'..=b'p').contains(&b));
+            if !valid {
+                return Err(VeroxError::InvalidArgument(format!("invalid extension ID '{}'", extension_id)));
+            }
+            (Some(vec![format!("chrome-extension://{}/", extension_id)]), None)
+        };
+        Ok(HostManifest {
+            name: HOST_NAME.to_string(),
+            description: HOST_DESCRIPTION.to_string(),
+            path: host_path.to_path_buf(),
+            kind: "stdio".to_string(),
+            allowed_origins,
+            allowed_extensions,
+        })
+    }
+}
+
+/// Write the manifest where `browser` looks for it, returning the file written
+pub fn install(browser: Browser, scope: Scope, home: &Path, manifest: &HostManifest) -> Result<PathBuf> {
+    let path = browser.manifest_path(scope, home);
+    // Browsers run as the user, so the manifest stays world-readable
+    fs::create_dir_all(browser.manifest_dir(scope, home))?;
+    utils::write_atomic(&path, serde_json::to_string_pretty(manifest)?.as_bytes())?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644))?;
+    }
+    Ok(path)
+}
+
+/// Remove the manifest, returning the file if there was one
+pub fn uninstall(browser: Browser, scope: Scope, home: &Path) -> Result<Option<PathBuf>> {
+    let path = browser.manifest_path(scope, home);
+    match fs::remove_file(&path) {
+        Ok(()) => Ok(Some(path)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The installed manifest, if any
+pub fn installed(browser: Browser, scope: Scope, home: &Path) -> Result<Option<HostManifest>> {
+    match fs::read_to_string(browser.manifest_path(scope, home)) {
+        Ok(json) => Ok(Some(serde_json::from_str(&json)?)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// `verox-native-host` next to the running executable
+pub fn default_host_path() -> Result<PathBuf> {
+    let path = std::env::current_exe()?.with_file_name(HOST_BINARY);
+    if !path.is_file() {
+        return Err(VeroxError::InvalidArgument(format!(
+            "{} not found, build it or pass its path",
+            path.display()
+        )));
+    }
+    Ok(path.canonicalize()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHROME_ID: &str = "abcdefghijklmnopabcdefghijklmnop";
+    const FIREFOX_ID: &str = "wallet@verox.dev";
+
+    #[test]
+    fn test_manifest() {
+        let host = Path::new("/opt/verox/verox-native-host");
+        let chrome = HostManifest::new(Browser::Brave, host, CHROME_ID).unwrap();
+        assert_eq!(
+            serde_json::to_value(&chrome).unwrap(),
+            serde_json::json!({
+                "name": "com.verox.native_host",
+                "description": HOST_DESCRIPTION,
+                "path": "/opt/verox/verox-native-host",
+                "type": "stdio",
+                "allowed_origins": ["chrome-extension://abcdefghijklmnopabcdefghijklmnop/"],
+            })
+        );
+        let firefox = HostManifest::new(Browser::Firefox, host, FIREFOX_ID).unwrap();
+        assert_eq!(firefox.allowed_extensions, Some(vec![FIREFOX_ID.to_string()]));
+        assert!(firefox.allowed_origins.is_none());
+        assert!(HostManifest::new(Browser::Firefox, host, "{0b8e3a52-3d8c-4c9f-9a3e-1f0c2d4b5a6e}").is_ok());
+
+        assert!(HostManifest::new(Browser::Chrome, Path::new("verox-native-host"), CHROME_ID).is_err());
+        assert!(HostManifest::new(Browser::Chrome, host, "verox-extension-id").is_err());
+        assert!(HostManifest::new(Browser::Chrome, host, FIREFOX_ID).is_err());
+        assert!(HostManifest::new(Browser::Firefox, host, CHROME_ID).is_err());
+    }
+
+    #[test]
+    fn test_install_and_uninstall() {
+        let home = tempfile::tempdir().unwrap();
+        let host = Path::new("/opt/verox/verox-native-host");
+        for browser in Browser::ALL {
+            let id = if browser.is_firefox() { FIREFOX_ID } else { CHROME_ID };
+            let manifest = HostManifest::new(browser, host, id).unwrap();
+            let path = install(browser, Scope::User, home.path(), &manifest).unwrap();
+            assert!(path.starts_with(home.path()));
+            assert_eq!(installed(browser, Scope::User, home.path()).unwrap(), Some(manifest));
+        }
+        let written = |relative: &str| home.path().join(relative).join("com.verox.native_host.json").is_file();
+        assert!(written(".config/google-chrome/NativeMessagingHosts"));
+        assert!(written(".config/chromium/NativeMessagingHosts"));
+        assert!(written(".config/BraveSoftware/Brave-Browser/NativeMessagingHosts"));
+        assert!(written(".mozilla/native-messaging-hosts"));
+
+        assert!(uninstall(Browser::Chrome, Scope::User, home.path()).unwrap().is_some());
+        assert!(uninstall(Browser::Chrome, Scope::User, home.path()).unwrap().is_none());
+        assert_eq!(installed(Browser::Chrome, Scope::User, home.path()).unwrap(), None);
+        assert!(installed(Browser::Firefox, Scope::User, home.path()).unwrap().is_some());
+
+        // System-wide manifests never live under the home directory
+        for browser in Browser::ALL {
+            assert!(!browser.manifest_path(Scope::System, home.path()).starts_with(home.path()));
+        }
+        assert_eq!("Google-Chrome".parse::<Browser>().unwrap(), Browser::Chrome);
+        assert!("safari".parse::<Browser>().is_err());
+    }
+}
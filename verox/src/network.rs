@@ -0,0 +1,306 @@
+//! Networks Verox can talk to
+//!
+//! Mainnet, Sepolia and Holesky are built in. `networks.json` in the data directory
+//! holds networks the user added (a local anvil node, an L2, or a built-in chain with
+//! a different RPC endpoint; an entry with a built-in's chain ID replaces it) and the
+//! selected network.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, VeroxError};
+use crate::events;
+use crate::utils;
+
+pub const NETWORKS_FILE: &str = "networks.json";
+
+pub const MAINNET_CHAIN_ID: u64 = 1;
+pub const SEPOLIA_CHAIN_ID: u64 = 11155111;
+pub const HOLESKY_CHAIN_ID: u64 = 17000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NativeCurrency {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+impl NativeCurrency {
+    pub fn ether() -> Self {
+        NativeCurrency {
+            name: "Ether".to_string(),
+            symbol: "ETH".to_string(),
+            decimals: 18,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Network {
+    pub chain_id: u64,
+    pub name: String,
+    /// Tried in order until one answers with the right chain ID
+    pub rpc_urls: Vec<String>,
+    pub native_currency: NativeCurrency,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explorer_url: Option<String>,
+}
+
+impl Network {
+    /// Explorer link for a transaction hash, if the network has an explorer
+    pub fn tx_url(&self, tx_hash: &str) -> Option<String> {
+        self.explorer_url
+            .as_ref()
+            .map(|url| format!("{}/tx/{}", url.trim_end_matches('/'), tx_hash))
+    }
+
+    /// Explorer link for an address, if the network has an explorer
+    pub fn address_url(&self, address: &str) -> Option<String> {
+        self.explorer_url
+            .as_ref()
+            .map(|url| format!("{}/address/{}", url.trim_end_matches('/'), address))
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.chain_id == 0 {
+            return Err(VeroxError::InvalidArgument("chain ID must not be 0".to_string()));
+        }
+        if self.name.trim().is_empty() {
+            return Err(VeroxError::InvalidArgument("network name must not be empty".to_string()));
+        }
+        if self.rpc_urls.is_empty() {
+            return Err(VeroxError::InvalidArgument(format!("{} has no RPC URL", self.name)));
+        }
+        if let Some(url) = self.rpc_urls.iter().find(|url| !url.starts_with("http://") && !url.starts_with("https://")) {
+            return Err(VeroxError::InvalidArgument(format!("RPC URL '{}' must be http(s)", url)));
+        }
+        Ok(())
+    }
+}
+
+/// The networks every install knows about
+pub fn builtin_networks() -> Vec<Network> {
+    let network = |chain_id, name: &str, rpc_url: &str, explorer_url: &str| Network {
+        chain_id,
+        name: name.to_string(),
+        rpc_urls: vec![rpc_url.to_string()],
+        native_currency: NativeCurrency::ether(),
+        explorer_url: Some(explorer_url.to_string()),
+    };
+    vec![
+        network(
+            MAINNET_CHAIN_ID,
+            "Mainnet",
+            "https://ethereum-rpc.publicnode.com",
+            "https://etherscan.io",
+        ),
+        network(
+            SEPOLIA_CHAIN_ID,
+            "Sepolia",
+            "https://ethereum-sepolia-rpc.publicnode.com",
+            "https://sepolia.etherscan.io",
+        ),
+        network(
+            HOLESKY_CHAIN_ID,
+            "Holesky",
+            "https://ethereum-holesky-rpc.publicnode.com",
+            "https://holesky.etherscan.io",
+        ),
+    ]
+}
+
+/// User-added networks and the current selection, stored in `networks.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    /// Chain ID of the selected network; mainnet when unset
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selected: Option<u64>,
+    #[serde(default)]
+    pub custom: Vec<Network>,
+}
+
+impl NetworkConfig {
+    /// Load `networks.json` from `dir`, or the defaults if there is none yet
+    pub fn load(dir: &Path) -> Result<Self> {
+        match fs::read_to_string(config_path(dir)) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(NetworkConfig::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Apply `change` to the stored configuration under the data directory lock, so
+    /// concurrent writers don't lose each other's edits. Nothing is saved if it fails.
+    pub fn update<T>(dir: &Path, change: impl FnOnce(&mut NetworkConfig) -> Result<T>) -> Result<T> {
+        let _lock = utils::lock_dir(dir)?;
+        let mut config = NetworkConfig::load(dir)?;
+        let result = change(&mut config)?;
+        config.save(dir)?;
+        // Pages learn about a switch of network
+        events::notify(dir, "network");
+        Ok(result)
+    }
+
+    fn save(&self, dir: &Path) -> Result<()> {
+        utils::create_private_dir(dir)?;
+        utils::write_atomic(&config_path(dir), serde_json::to_string_pretty(self)?.as_bytes())
+    }
+
+    /// Built-in networks, with user entries replacing built-ins of the same chain ID
+    pub fn networks(&self) -> Vec<Network> {
+        let mut networks: Vec<Network> = builtin_networks()
+            .into_iter()
+            .map(|builtin| {
+                self.custom
+                    .iter()
+                    .find(|network| network.chain_id == builtin.chain_id)
+                    .cloned()
+                    .unwrap_or(builtin)
+            })
+            .collect();
+        for network in &self.custom {
+            if !networks.iter().any(|known| known.chain_id == network.chain_id) {
+                networks.push(network.clone());
+            }
+        }
+        networks
+    }
+
+    /// Find a network by chain ID (decimal or 0x hex) or name
+    pub fn find(&self, selector: &str) -> Result<Network> {
+        let chain_id = match selector.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => selector.parse().ok(),
+        };
+        self.networks()
+            .into_iter()
+            .find(|network| Some(network.chain_id) == chain_id || network.name.eq_ignore_ascii_case(selector))
+            .ok_or_else(|| VeroxError::InvalidArgument(format!("unknown network '{}'", selector)))
+    }
+
+    pub fn selected_network(&self) -> Network {
+        let chain_id = self.selected.unwrap_or(MAINNET_CHAIN_ID);
+        self.networks()
+            .into_iter()
+            .find(|network| network.chain_id == chain_id)
+            .unwrap_or_else(|| builtin_networks().remove(0))
+    }
+
+    pub fn select(&mut self, selector: &str) -> Result<Network> {
+        let network = self.find(selector)?;
+        self.selected = Some(network.chain_id);
+        Ok(network)
+    }
+
+    /// Add a network, or replace the one with the same chain ID
+    pub fn add(&mut self, network: Network) -> Result<()> {
+        network.validate()?;
+        match self.custom.iter_mut().find(|known| known.chain_id == network.chain_id) {
+            Some(existing) => *existing = network,
+            None => self.custom.push(network),
+        }
+        Ok(())
+    }
+
+    /// Remove a user-added network. For a built-in chain this drops the override
+    /// and its defaults come back.
+    pub fn remove(&mut self, chain_id: u64) -> Result<Network> {
+        let position = self
+            .custom
+            .iter()
+            .position(|network| network.chain_id == chain_id)
+            .ok_or_else(|| VeroxError::InvalidArgument(format!("chain {} was not added by the user", chain_id)))?;
+        let removed = self.custom.remove(position);
+        if self.selected == Some(chain_id) && !builtin_networks().iter().any(|network| network.chain_id == chain_id) {
+            self.selected = None;
+        }
+        Ok(removed)
+    }
+}
+
+pub fn config_path(dir: &Path) -> PathBuf {
+    dir.join(NETWORKS_FILE)
+}
+
+/// Load the network configuration from the data directory
+pub fn load_config() -> Result<NetworkConfig> {
+    NetworkConfig::load(&utils::get_data_dir()?)
+}
+
+/// Change the network configuration in the data directory, see [`NetworkConfig::update`]
+pub fn update_config<T>(change: impl FnOnce(&mut NetworkConfig) -> Result<T>) -> Result<T> {
+    NetworkConfig::update(&utils::ensure_data_dir()?, change)
+}
+
+/// The network named by `selector`, or the selected one
+pub fn resolve_network(selector: Option<&str>) -> Result<Network> {
+    let config = load_config()?;
+    match selector {
+        Some(selector) => config.find(selector),
+        None => Ok(config.selected_network()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anvil() -> Network {
+        Network {
+            chain_id: 31337,
+            name: "Anvil".to_string(),
+            rpc_urls: vec!["http://127.0.0.1:8545".to_string()],
+            native_currency: NativeCurrency::ether(),
+            explorer_url: None,
+        }
+    }
+
+    #[test]
+    fn test_builtin_networks() {
+        let config = NetworkConfig::default();
+        assert_eq!(config.selected_network().chain_id, MAINNET_CHAIN_ID);
+        assert_eq!(config.find("sepolia").unwrap().chain_id, SEPOLIA_CHAIN_ID);
+        assert_eq!(config.find("17000").unwrap().name, "Holesky");
+        assert_eq!(config.find("0xaa36a7").unwrap().name, "Sepolia");
+        assert!(config.find("goerli").is_err());
+        assert_eq!(
+            config.find("mainnet").unwrap().tx_url("0xabc").as_deref(),
+            Some("https://etherscan.io/tx/0xabc")
+        );
+    }
+
+    #[test]
+    fn test_custom_networks_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        NetworkConfig::update(dir.path(), |config| {
+            config.add(anvil())?;
+            config.select("anvil")?;
+
+            // Overriding a built-in's RPC endpoint
+            let mut sepolia = config.find("sepolia")?;
+            sepolia.rpc_urls = vec!["https://sepolia.example.org".to_string()];
+            config.add(sepolia)
+        })
+        .unwrap();
+        // A failed change leaves the file alone
+        assert!(NetworkConfig::update(dir.path(), |config| config.select("nowhere")).is_err());
+
+        let mut config = NetworkConfig::load(dir.path()).unwrap();
+        assert_eq!(config.selected_network().chain_id, 31337);
+        assert_eq!(config.networks().len(), 4);
+        assert_eq!(config.find("sepolia").unwrap().rpc_urls, vec!["https://sepolia.example.org"]);
+
+        // Removing the override restores the default, removing anvil drops the selection
+        config.remove(SEPOLIA_CHAIN_ID).unwrap();
+        assert_eq!(config.find("sepolia").unwrap().rpc_urls, builtin_networks()[1].rpc_urls);
+        config.remove(31337).unwrap();
+        assert_eq!(config.selected_network().chain_id, MAINNET_CHAIN_ID);
+        assert!(config.remove(MAINNET_CHAIN_ID).is_err());
+
+        let mut bad = anvil();
+        bad.rpc_urls = vec!["ftp://node".to_string()];
+        assert!(config.add(bad).is_err());
+    }
+}
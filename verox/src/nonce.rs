@@ -0,0 +1,321 @@
+//! Nonces for accounts that send several transactions at once
+//!
+//! `eth_getTransactionCount(pending)` alone hands two transactions sent in quick
+//! succession the same nonce: the second asks before the first reaches the node.
+//! [`NonceManager`] combines the node's count with what Verox knows locally: the
+//! unsettled transactions in `transactions.json` and nonces handed out but not yet
+//! broadcast, which are kept as reservations in `nonces.json`. Reservations and the
+//! store are read and written under the data directory lock, so the CLI and the
+//! native host can send from the same account concurrently.
+//!
+//! The next nonce is the lowest one at or above the node's pending count that is
+//! neither tracked nor reserved, so a nonce left unused (a reservation that was
+//! never broadcast, a dropped transaction) is filled before the sequence continues.
+//! [`NonceStatus`] reports such gaps and a stuck transaction holding up the rest.
+
+use chrono::{DateTime, Duration, Utc};
+use ethers::providers::{JsonRpcClient, Middleware};
+use ethers::types::{Address, BlockNumber};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+use crate::pending::{TxStatus, TxStore};
+use crate::provider::{rpc_error, RpcProvider};
+use crate::utils;
+use crate::wallet::address_string;
+
+pub const NONCES_FILE: &str = "nonces.json";
+
+/// A reservation that was not broadcast within this long is abandoned and its nonce reused
+pub const RESERVATION_TTL_SECS: i64 = 120;
+
+/// A transaction holding the account's next nonce this long is reported as stuck
+pub const STUCK_AFTER_SECS: i64 = 10 * 60;
+
+/// A nonce handed out and not yet seen in the transaction store
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reservation {
+    pub chain_id: u64,
+    pub address: Address,
+    pub nonce: u64,
+    pub reserved_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Reservations {
+    #[serde(default)]
+    reservations: Vec<Reservation>,
+}
+
+/// Where an account's nonces stand on one chain
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NonceStatus {
+    pub chain_id: u64,
+    pub address: String,
+    /// Transactions mined so far, i.e. the nonce the next block must include
+    pub mined: u64,
+    /// The node's count including its mempool
+    pub pending: u64,
+    /// What [`NonceManager::reserve`] would hand out now
+    pub next: u64,
+    /// Nonces of unsettled transactions Verox sent, and live reservations
+    pub in_use: Vec<u64>,
+    /// Unused nonces below ones in use; nothing after a gap can be mined until it is filled
+    pub gaps: Vec<u64>,
+    /// The transaction at `mined` if it has been waiting longer than [`STUCK_AFTER_SECS`]
+    pub stuck: Option<u64>,
+}
+
+pub struct NonceManager {
+    dir: PathBuf,
+}
+
+impl NonceManager {
+    pub fn new(dir: &Path) -> Self {
+        NonceManager { dir: dir.to_path_buf() }
+    }
+
+    /// The manager for the data directory
+    pub fn open() -> Result<Self> {
+        Ok(NonceManager::new(&utils::ensure_data_dir()?))
+    }
+
+    /// The data directory the reservations and the transaction store live in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Hand out the next nonce for `address` on the provider's chain and reserve it
+    /// until the transaction shows up in the store or the reservation expires
+    pub async fn reserve<P: JsonRpcClient>(&self, rpc: &RpcProvider<P>, address: Address) -> Result<u64> {
+        let (mined, pending) = counts(rpc, address).await?;
+        self.reserve_at(rpc.network().chain_id, address, mined, pending, Utc::now())
+    }
+
+    /// Give a reserved nonce back, when signing or broadcasting failed
+    pub fn release(&self, chain_id: u64, address: Address, nonce: u64) -> Result<()> {
+        let _lock = utils::lock_dir(&self.dir)?;
+        let mut reservations = self.load()?;
+        reservations
+            .reservations
+            .retain(|r| (r.chain_id, r.address, r.nonce) != (chain_id, address, nonce));
+        self.save(&reservations)
+    }
+
+    pub async fn status<P: JsonRpcClient>(&self, rpc: &RpcProvider<P>, address: Address) -> Result<NonceStatus> {
+        let (mined, pending) = counts(rpc, address).await?;
+        self.status_at(rpc.network().chain_id, address, mined, pending, Utc::now())
+    }
+
+    /// [`reserve`](Self::reserve) with the node's counts already known
+    pub fn reserve_at(
+        &self,
+        chain_id: u64,
+        address: Address,
+        mined: u64,
+        pending: u64,
+        now: DateTime<Utc>,
+    ) -> Result<u64> {
+        let _lock = utils::lock_dir(&self.dir)?;
+        let mut reservations = self.load()?;
+        let store = TxStore::load(&self.dir)?;
+        let in_use = in_use(&store, &mut reservations, chain_id, address, mined, now);
+        let nonce = next_nonce(pending.max(mined), &in_use);
+        reservations.reservations.push(Reservation {
+            chain_id,
+            address,
+            nonce,
+            reserved_at: now,
+        });
+        self.save(&reservations)?;
+        Ok(nonce)
+    }
+
+    /// [`status`](Self::status) with the node's counts already known
+    pub fn status_at(
+        &self,
+        chain_id: u64,
+        address: Address,
+        mined: u64,
+        pending: u64,
+        now: DateTime<Utc>,
+    ) -> Result<NonceStatus> {
+        let _lock = utils::lock_dir(&self.dir)?;
+        let mut reservations = self.load()?;
+        let store = TxStore::load(&self.dir)?;
+        let in_use = in_use(&store, &mut reservations, chain_id, address, mined, now);
+
+        let start = pending.max(mined);
+        let gaps = match in_use.iter().next_back() {
+            Some(&highest) => (start..highest).filter(|nonce| !in_use.contains(nonce)).collect(),
+            None => Vec::new(),
+        };
+        let stuck = store
+            .transactions
+            .iter()
+            .find(|tx| {
+                (tx.chain_id, tx.from, tx.nonce) == (chain_id, address, mined)
+                    && tx.status == TxStatus::Pending
+                    && now - tx.submitted_at > Duration::seconds(STUCK_AFTER_SECS)
+            })
+            .map(|tx| tx.nonce);
+        Ok(NonceStatus {
+            chain_id,
+            address: address_string(address),
+            mined,
+            pending,
+            next: next_nonce(start, &in_use),
+            in_use: in_use.into_iter().collect(),
+            gaps,
+            stuck,
+        })
+    }
+
+    fn load(&self) -> Result<Reservations> {
+        match fs::read_to_string(self.dir.join(NONCES_FILE)) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Reservations::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, reservations: &Reservations) -> Result<()> {
+        let path = self.dir.join(NONCES_FILE);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(reservations)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
+
+/// The account's mined and pending transaction counts
+async fn counts<P: JsonRpcClient>(rpc: &RpcProvider<P>, address: Address) -> Result<(u64, u64)> {
+    let mined = rpc
+        .inner()
+        .get_transaction_count(address, Some(BlockNumber::Latest.into()))
+        .await
+        .map_err(rpc_error)?;
+    let pending = rpc.nonce(address).await?;
+    Ok((mined.low_u64(), pending.low_u64()))
+}
+
+/// Nonces at or above `mined` taken by unsettled transactions or live reservations.
+/// Expired, mined and now-tracked reservations are dropped from `reservations`.
+fn in_use(
+    store: &TxStore,
+    reservations: &mut Reservations,
+    chain_id: u64,
+    address: Address,
+    mined: u64,
+    now: DateTime<Utc>,
+) -> BTreeSet<u64> {
+    let tracked: BTreeSet<u64> = store
+        .transactions
+        .iter()
+        .filter(|tx| (tx.chain_id, tx.from) == (chain_id, address) && tx.status == TxStatus::Pending)
+        .map(|tx| tx.nonce)
+        .filter(|&nonce| nonce >= mined)
+        .collect();
+    reservations.reservations.retain(|r| {
+        if (r.chain_id, r.address) != (chain_id, address) {
+            return now - r.reserved_at <= Duration::seconds(RESERVATION_TTL_SECS);
+        }
+        r.nonce >= mined
+            && !tracked.contains(&r.nonce)
+            && now - r.reserved_at <= Duration::seconds(RESERVATION_TTL_SECS)
+    });
+    let reserved = reservations
+        .reservations
+        .iter()
+        .filter(|r| (r.chain_id, r.address) == (chain_id, address))
+        .map(|r| r.nonce);
+    tracked.iter().copied().chain(reserved).collect()
+}
+
+fn next_nonce(start: u64, in_use: &BTreeSet<u64>) -> u64 {
+    (start..).find(|nonce| !in_use.contains(nonce)).expect("nonces are unbounded")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pending::TrackedTx;
+    use crate::transaction::{sign_transaction, TxRequest};
+    use ethers::signers::{LocalWallet, Signer};
+
+    const KEY: &str = "4c0883a69102937d6231471b5ecb1c5a0a8a4f2a6e1d0b51c7bce6ad8e7fa0a1";
+
+    fn track(dir: &Path, nonce: u64, status: TxStatus, submitted_at: DateTime<Utc>) {
+        let wallet: LocalWallet = KEY.parse().unwrap();
+        let params = TxRequest {
+            chain_id: Some(1),
+            nonce: Some(nonce.into()),
+            to: Some(Address::repeat_byte(0x11)),
+            gas_limit: Some(21000.into()),
+            gas_price: Some(1_000_000_000u64.into()),
+            ..Default::default()
+        }
+        .into_params()
+        .unwrap();
+        let mut tx = TrackedTx::new(&params, &sign_transaction(&wallet, &params).unwrap(), submitted_at);
+        tx.status = status;
+        TxStore::update(dir, |store| store.upsert(tx)).unwrap();
+    }
+
+    #[test]
+    fn test_reserve_burst() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = NonceManager::new(dir.path());
+        let address: Address = KEY.parse::<LocalWallet>().unwrap().address();
+        let now = Utc::now();
+
+        // The node still says 3 for every request of the burst
+        let nonces: Vec<u64> = (0..3).map(|_| manager.reserve_at(1, address, 3, 3, now).unwrap()).collect();
+        assert_eq!(nonces, vec![3, 4, 5]);
+        // Other chains and accounts count separately
+        assert_eq!(manager.reserve_at(5, address, 0, 0, now).unwrap(), 0);
+        assert_eq!(manager.reserve_at(1, Address::zero(), 0, 0, now).unwrap(), 0);
+
+        // 3 was broadcast, 4 failed and was released, 5 is still in flight
+        track(dir.path(), 3, TxStatus::Pending, now);
+        manager.release(1, address, 4).unwrap();
+        let status = manager.status_at(1, address, 3, 4, now).unwrap();
+        assert_eq!(status.in_use, vec![3, 5]);
+        assert_eq!(status.gaps, vec![4]);
+        // The gap is handed out first
+        assert_eq!(manager.reserve_at(1, address, 3, 4, now).unwrap(), 4);
+        assert_eq!(manager.reserve_at(1, address, 3, 4, now).unwrap(), 6);
+
+        // Reservations that were never broadcast expire
+        let later = now + Duration::seconds(RESERVATION_TTL_SECS + 1);
+        assert_eq!(manager.reserve_at(1, address, 3, 4, later).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_gaps_and_stuck() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = NonceManager::new(dir.path());
+        let address: Address = KEY.parse::<LocalWallet>().unwrap().address();
+        let now = Utc::now();
+        let long_ago = now - Duration::seconds(STUCK_AFTER_SECS + 1);
+
+        // 7 is stuck in the mempool, 8 was dropped, 9 waits behind the gap
+        track(dir.path(), 7, TxStatus::Pending, long_ago);
+        track(dir.path(), 8, TxStatus::Dropped, long_ago);
+        track(dir.path(), 9, TxStatus::Pending, now);
+        let status = manager.status_at(1, address, 7, 8, now).unwrap();
+        assert_eq!(status.stuck, Some(7));
+        assert_eq!(status.gaps, vec![8]);
+        assert_eq!(status.next, 8);
+
+        // Once 7 is mined it no longer counts, and 10 follows 9
+        let status = manager.status_at(1, address, 8, 8, now).unwrap();
+        assert_eq!((status.stuck, status.in_use.clone(), status.next), (None, vec![9], 8));
+        let status = manager.status_at(1, address, 8, 10, now).unwrap();
+        assert_eq!((status.gaps.clone(), status.next), (Vec::<u64>::new(), 10));
+    }
+}
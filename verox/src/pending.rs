@@ -0,0 +1,643 @@
+//! Broadcast transactions and follow them until they settle
+//!
+//! A transaction sent with [`broadcast`] is recorded in `transactions.json` in the
+//! data directory and polled from there, so a restarted native host picks up where
+//! the previous one stopped. Its [`TxStatus`] moves through:
+//!
+//! - `pending`: sent, not in a block yet
+//! - `included`: in a block, fewer than the required confirmations deep
+//! - `confirmed`: at least that many blocks deep
+//! - `failed`: included but reverted
+//! - `dropped`: missing from the node and rejected on rebroadcast for
+//!   [`DROP_AFTER_SECS`], with its nonce still unused
+//! - `replaced`: another transaction with the same nonce was mined
+//!
+//! A transaction the node no longer knows is rebroadcast on every poll, and a reorg
+//! that takes an included one out of its block moves it back to pending.
+
+use chrono::{DateTime, Duration, Utc};
+use ethers::providers::{JsonRpcClient, Middleware};
+use ethers::types::{Address, BlockNumber, Bytes, H256};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::agent::UnlockedAccount;
+use crate::error::{Result, VeroxError};
+use crate::fees::FeeSpeed;
+use crate::network::NetworkConfig;
+use crate::nonce::NonceManager;
+use crate::provider::{rpc_error, RpcProvider};
+use crate::transaction::{SignedTransaction, TxParams, TxRequest};
+use crate::utils;
+use crate::wallet::address_string;
+
+pub const TRANSACTIONS_FILE: &str = "transactions.json";
+
+/// Blocks on top of (and including) the one a transaction is in before it counts as confirmed
+pub const CONFIRMATIONS: u64 = 12;
+
+/// How long a transaction may be unknown to the node before it counts as dropped
+pub const DROP_AFTER_SECS: i64 = 30 * 60;
+
+/// Seconds between polls of unsettled transactions
+pub const POLL_INTERVAL_SECS: u64 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxStatus {
+    Pending,
+    Included,
+    Confirmed,
+    Failed,
+    Dropped,
+    Replaced,
+}
+
+impl TxStatus {
+    /// Whether the transaction can still change state and needs polling
+    pub fn is_final(self) -> bool {
+        !matches!(self, TxStatus::Pending | TxStatus::Included)
+    }
+}
+
+impl fmt::Display for TxStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TxStatus::Pending => "pending",
+            TxStatus::Included => "included",
+            TxStatus::Confirmed => "confirmed",
+            TxStatus::Failed => "failed",
+            TxStatus::Dropped => "dropped",
+            TxStatus::Replaced => "replaced",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A broadcast transaction and what is known about it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrackedTx {
+    pub hash: H256,
+    pub chain_id: u64,
+    #[serde(serialize_with = "checksummed")]
+    pub from: Address,
+    #[serde(serialize_with = "checksummed_option")]
+    pub to: Option<Address>,
+    pub nonce: u64,
+    /// The signed envelope, kept for rebroadcasting
+    pub raw: Bytes,
+    pub status: TxStatus,
+    pub submitted_at: DateTime<Utc>,
+    /// Last time the node knew the transaction or accepted a rebroadcast
+    pub last_seen_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_number: Option<u64>,
+    #[serde(default)]
+    pub confirmations: u64,
+    /// The transaction that took the nonce, once a replacement is known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaced_by: Option<H256>,
+    /// For a speed-up or cancel, the transaction it was sent to replace
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaces: Option<H256>,
+}
+
+impl TrackedTx {
+    pub fn new(params: &TxParams, signed: &SignedTransaction, now: DateTime<Utc>) -> Self {
+        TrackedTx {
+            hash: signed.hash,
+            chain_id: params.chain_id,
+            from: signed.from,
+            to: params.to,
+            nonce: params.nonce.low_u64(),
+            raw: signed.raw.clone(),
+            status: TxStatus::Pending,
+            submitted_at: now,
+            last_seen_at: now,
+            block_number: None,
+            confirmations: 0,
+            replaced_by: None,
+            replaces: None,
+        }
+    }
+}
+
+fn checksummed<S: Serializer>(address: &Address, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&address_string(*address))
+}
+
+fn checksummed_option<S: Serializer>(
+    address: &Option<Address>,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    match address {
+        Some(address) => checksummed(address, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Every transaction Verox broadcast, stored in `transactions.json`
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TxStore {
+    #[serde(default)]
+    pub transactions: Vec<TrackedTx>,
+}
+
+impl TxStore {
+    /// Load `transactions.json` from `dir`, or an empty store if there is none yet
+    pub fn load(dir: &Path) -> Result<Self> {
+        match fs::read_to_string(store_path(dir)) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(TxStore::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        utils::create_private_dir(dir)?;
+        let path = store_path(dir);
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// Load the store from `dir`, apply `change` and save it, holding the data
+    /// directory lock so concurrent updates (from this or another process) don't
+    /// overwrite each other
+    pub fn update<T>(dir: &Path, change: impl FnOnce(&mut TxStore) -> T) -> Result<T> {
+        let _lock = utils::lock_dir(dir)?;
+        let mut store = TxStore::load(dir)?;
+        let result = change(&mut store);
+        store.save(dir)?;
+        Ok(result)
+    }
+
+    pub fn get(&self, hash: H256) -> Option<&TrackedTx> {
+        self.transactions.iter().find(|tx| tx.hash == hash)
+    }
+
+    /// Add a transaction, or replace the record with the same hash
+    pub fn upsert(&mut self, tx: TrackedTx) {
+        match self.transactions.iter_mut().find(|known| known.hash == tx.hash) {
+            Some(known) => *known = tx,
+            None => self.transactions.push(tx),
+        }
+        self.link_replacements();
+    }
+
+    /// Record what polling found out about a tracked transaction (status, block,
+    /// confirmations) on top of whatever was saved since it was read, such as a
+    /// replacement. A transaction that settled meanwhile is left alone. Returns the
+    /// record if it changed.
+    pub fn record_poll(&mut self, polled: &TrackedTx) -> Option<TrackedTx> {
+        let known = self.transactions.iter_mut().find(|known| known.hash == polled.hash)?;
+        if known.status.is_final() {
+            return None;
+        }
+        let before = known.clone();
+        known.status = polled.status;
+        known.block_number = polled.block_number;
+        known.confirmations = polled.confirmations;
+        known.last_seen_at = known.last_seen_at.max(polled.last_seen_at);
+        self.link_replacements();
+        self.get(polled.hash).filter(|after| **after != before).cloned()
+    }
+
+    /// Transactions that still need polling
+    pub fn active(&self) -> impl Iterator<Item = &TrackedTx> {
+        self.transactions.iter().filter(|tx| !tx.status.is_final())
+    }
+
+    /// Point replaced transactions at the tracked one that was mined with their nonce
+    fn link_replacements(&mut self) {
+        let mined: Vec<(u64, Address, u64, H256)> = self
+            .transactions
+            .iter()
+            .filter(|tx| tx.block_number.is_some())
+            .map(|tx| (tx.chain_id, tx.from, tx.nonce, tx.hash))
+            .collect();
+        for tx in self.transactions.iter_mut().filter(|tx| tx.status == TxStatus::Replaced) {
+            if let Some(&(_, _, _, hash)) = mined
+                .iter()
+                .find(|(chain_id, from, nonce, _)| (*chain_id, *from, *nonce) == (tx.chain_id, tx.from, tx.nonce))
+            {
+                tx.replaced_by = Some(hash);
+            }
+        }
+    }
+}
+
+pub fn store_path(dir: &Path) -> PathBuf {
+    dir.join(TRANSACTIONS_FILE)
+}
+
+/// Load the transaction store from the data directory
+pub fn load_store() -> Result<TxStore> {
+    TxStore::load(&utils::get_data_dir()?)
+}
+
+/// Send a signed transaction with `eth_sendRawTransaction`
+pub async fn broadcast<P: JsonRpcClient>(
+    rpc: &RpcProvider<P>,
+    params: &TxParams,
+    signed: &SignedTransaction,
+) -> Result<TrackedTx> {
+    if params.chain_id != rpc.network().chain_id {
+        return Err(VeroxError::InvalidArgument(format!(
+            "transaction is for chain {} but the provider serves {}",
+            params.chain_id,
+            rpc.network().chain_id
+        )));
+    }
+    let hash = rpc.send_raw_transaction(signed.raw.clone()).await?;
+    if hash != signed.hash {
+        return Err(VeroxError::Rpc(format!(
+            "node reported hash {:?} for transaction {:?}",
+            hash, signed.hash
+        )));
+    }
+    Ok(TrackedTx::new(params, signed, Utc::now()))
+}
+
+/// Complete `request` from the network, sign it with `wallet`, broadcast it and
+/// record it in the manager's store. A missing nonce is reserved from `nonces`
+/// and given back if the transaction doesn't go out.
+pub async fn send<P: JsonRpcClient>(
+    rpc: &RpcProvider<P>,
+    nonces: &NonceManager,
+    wallet: &UnlockedAccount,
+    mut request: TxRequest,
+    speed: FeeSpeed,
+    allow_high_fee: bool,
+) -> Result<(TxParams, TrackedTx)> {
+    let from = wallet.address();
+    let reserved = match request.nonce {
+        Some(_) => None,
+        None => {
+            let nonce = nonces.reserve(rpc, from).await?;
+            request.nonce = Some(nonce.into());
+            Some(nonce)
+        }
+    };
+    let sent = async {
+        rpc.fill_request(&mut request, from, speed, allow_high_fee).await?;
+        let params = request.into_params()?;
+        let signed = wallet.sign_transaction(&params)?;
+        let tracked = broadcast(rpc, &params, &signed).await?;
+        Ok::<_, VeroxError>((params, tracked))
+    }
+    .await;
+    match (&sent, reserved) {
+        (Ok((_, tracked)), _) => TxStore::update(nonces.dir(), |store| store.upsert(tracked.clone()))?,
+        (Err(_), Some(nonce)) => {
+            let _ = nonces.release(rpc.network().chain_id, from, nonce);
+        }
+        (Err(_), None) => {}
+    }
+    sent
+}
+
+/// Check on one transaction and update its status, block and confirmation count.
+/// `confirmations` is the depth at which an included transaction is confirmed.
+pub async fn poll<P: JsonRpcClient>(
+    rpc: &RpcProvider<P>,
+    tx: &mut TrackedTx,
+    confirmations: u64,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let provider = rpc.inner();
+    // Read the account's mined nonce before the receipt: if the receipt is missing
+    // afterwards, whatever used the nonce was not this transaction
+    let mined_nonce = provider
+        .get_transaction_count(tx.from, Some(BlockNumber::Latest.into()))
+        .await
+        .map_err(rpc_error)?;
+    let receipt = provider.get_transaction_receipt(tx.hash).await.map_err(rpc_error)?;
+
+    if let Some(block_number) = receipt.as_ref().and_then(|receipt| receipt.block_number) {
+        let head = provider.get_block_number().await.map_err(rpc_error)?;
+        let block_number = block_number.as_u64();
+        tx.block_number = Some(block_number);
+        tx.confirmations = (head.as_u64() + 1).saturating_sub(block_number);
+        tx.last_seen_at = now;
+        tx.status = match receipt.and_then(|receipt| receipt.status) {
+            Some(status) if status.is_zero() => TxStatus::Failed,
+            _ if tx.confirmations >= confirmations => TxStatus::Confirmed,
+            _ => TxStatus::Included,
+        };
+        return Ok(());
+    }
+
+    // Not in a block (any more)
+    tx.block_number = None;
+    tx.confirmations = 0;
+    if mined_nonce.as_u64() > tx.nonce {
+        tx.status = TxStatus::Replaced;
+        return Ok(());
+    }
+    tx.status = TxStatus::Pending;
+    if provider.get_transaction(tx.hash).await.map_err(rpc_error)?.is_some() {
+        tx.last_seen_at = now;
+        return Ok(());
+    }
+    // The node forgot it (evicted, or restarted): send it again
+    match rpc.send_raw_transaction(tx.raw.clone()).await {
+        Ok(_) => tx.last_seen_at = now,
+        Err(_) if now - tx.last_seen_at > Duration::seconds(DROP_AFTER_SECS) => tx.status = TxStatus::Dropped,
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+/// Look a tracked transaction up in `dir`'s store, polling it first if it hasn't settled
+pub async fn check(dir: &Path, hash: H256, confirmations: u64) -> Result<TrackedTx> {
+    let mut tx = TxStore::load(dir)?
+        .get(hash)
+        .cloned()
+        .ok_or_else(|| VeroxError::InvalidArgument(format!("transaction {:?} is not tracked", hash)))?;
+    if tx.status.is_final() {
+        return Ok(tx);
+    }
+    let network = NetworkConfig::load(dir)?.find(&tx.chain_id.to_string())?;
+    let rpc = RpcProvider::connect(&network).await?;
+    poll(&rpc, &mut tx, confirmations, Utc::now()).await?;
+    let saved = TxStore::update(dir, |store| {
+        store.record_poll(&tx);
+        store.get(hash).cloned()
+    })?;
+    Ok(saved.unwrap_or(tx))
+}
+
+/// What a [`refresh`] found
+#[derive(Debug, Default)]
+pub struct Refresh {
+    /// Transactions whose record changed
+    pub changed: Vec<TrackedTx>,
+    /// Chains and transactions that couldn't be checked this time, and why
+    pub errors: Vec<String>,
+}
+
+/// Poll every unsettled transaction in `dir`'s store and save what changed.
+/// Networks are looked up by chain ID in `dir`'s network configuration; a chain
+/// that is unknown or unreachable, or a transaction that couldn't be polled, is
+/// reported in `errors` and tried again on the next call.
+pub async fn refresh(dir: &Path, confirmations: u64) -> Result<Refresh> {
+    let active: Vec<TrackedTx> = TxStore::load(dir)?.active().cloned().collect();
+    let mut report = Refresh::default();
+    if active.is_empty() {
+        return Ok(report);
+    }
+    let networks = NetworkConfig::load(dir)?;
+
+    let mut by_chain: BTreeMap<u64, Vec<TrackedTx>> = BTreeMap::new();
+    for tx in active {
+        by_chain.entry(tx.chain_id).or_default().push(tx);
+    }
+    let mut polled = Vec::new();
+    for (chain_id, transactions) in by_chain {
+        let connected = match networks.find(&chain_id.to_string()) {
+            Ok(network) => RpcProvider::connect(&network).await,
+            Err(e) => Err(e),
+        };
+        let rpc = match connected {
+            Ok(rpc) => rpc,
+            Err(e) => {
+                report.errors.push(format!("chain {}: {}", chain_id, e));
+                continue;
+            }
+        };
+        for mut tx in transactions {
+            let before = tx.clone();
+            match poll(&rpc, &mut tx, confirmations, Utc::now()).await {
+                Ok(()) if tx != before => polled.push(tx),
+                Ok(()) => {}
+                Err(e) => report.errors.push(format!("{:?}: {}", tx.hash, e)),
+            }
+        }
+    }
+
+    if !polled.is_empty() {
+        // Re-read under the lock, so what was saved while polling isn't overwritten
+        report.changed = TxStore::update(dir, |store| polled.iter().filter_map(|tx| store.record_poll(tx)).collect())?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::{NativeCurrency, Network};
+    use crate::transaction::{sign_transaction, TxRequest};
+    use ethers::providers::{JsonRpcError, MockProvider, MockResponse, Provider};
+    use ethers::signers::LocalWallet;
+    use ethers::types::{Transaction, TransactionReceipt, U256, U64};
+    use serde_json::Value;
+
+    const KEY: &str = "4c0883a69102937d6231471b5ecb1c5a0a8a4f2a6e1d0b51c7bce6ad8e7fa0a1";
+
+    fn mock() -> (MockProvider, RpcProvider<MockProvider>) {
+        let mock = MockProvider::new();
+        let network = Network {
+            chain_id: 1,
+            name: "Mock".to_string(),
+            rpc_urls: vec!["http://mock".to_string()],
+            native_currency: NativeCurrency::ether(),
+            explorer_url: None,
+        };
+        (mock.clone(), RpcProvider::new(Provider::new(mock), network))
+    }
+
+    fn sign(nonce: u64, max_fee: u64) -> (TxParams, SignedTransaction) {
+        let wallet: LocalWallet = KEY.parse().unwrap();
+        let params = TxRequest {
+            chain_id: Some(1),
+            nonce: Some(nonce.into()),
+            to: Some(Address::repeat_byte(0x11)),
+            value: U256::exp10(15),
+            gas_limit: Some(21000.into()),
+            max_fee_per_gas: Some(max_fee.into()),
+            max_priority_fee_per_gas: Some(1_000_000_000u64.into()),
+            ..Default::default()
+        }
+        .into_params()
+        .unwrap();
+        let signed = sign_transaction(&wallet, &params).unwrap();
+        (params, signed)
+    }
+
+    fn value(value: impl Serialize) -> MockResponse {
+        MockResponse::Value(serde_json::to_value(value).unwrap())
+    }
+
+    fn receipt(hash: H256, block: u64, status: u64) -> MockResponse {
+        value(TransactionReceipt {
+            transaction_hash: hash,
+            block_number: Some(U64::from(block)),
+            status: Some(U64::from(status)),
+            ..Default::default()
+        })
+    }
+
+    fn rejected() -> MockResponse {
+        MockResponse::Error(JsonRpcError {
+            code: -32000,
+            message: "transaction underpriced".to_string(),
+            data: None,
+        })
+    }
+
+    // MockProvider answers from the back of its queue; queue the responses in call order
+    fn respond(mock: &MockProvider, responses: Vec<MockResponse>) {
+        for response in responses.into_iter().rev() {
+            mock.push_response(response);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_and_confirm() {
+        let (mock, rpc) = mock();
+        let (params, signed) = sign(5, 30_000_000_000);
+
+        mock.push(signed.hash).unwrap();
+        let mut tx = broadcast(&rpc, &params, &signed).await.unwrap();
+        mock.assert_request("eth_sendRawTransaction", [&signed.raw]).unwrap();
+        assert_eq!((tx.status, tx.nonce), (TxStatus::Pending, 5));
+        let now = tx.submitted_at;
+
+        // In the mempool
+        respond(&mock, vec![value(U256::from(5)), value(Value::Null), value(Transaction::default())]);
+        poll(&rpc, &mut tx, 3, now).await.unwrap();
+        assert_eq!(tx.status, TxStatus::Pending);
+
+        // Mined in block 100 with the head at 101: two confirmations of three
+        respond(&mock, vec![value(U256::from(6)), receipt(tx.hash, 100, 1), value(U64::from(101))]);
+        poll(&rpc, &mut tx, 3, now).await.unwrap();
+        assert_eq!((tx.status, tx.block_number, tx.confirmations), (TxStatus::Included, Some(100), 2));
+
+        respond(&mock, vec![value(U256::from(6)), receipt(tx.hash, 100, 1), value(U64::from(102))]);
+        poll(&rpc, &mut tx, 3, now).await.unwrap();
+        assert_eq!(tx.status, TxStatus::Confirmed);
+        assert!(tx.status.is_final());
+
+        // A reverted transaction fails at any depth
+        let (params, other) = sign(6, 30_000_000_000);
+        let mut reverted = TrackedTx::new(&params, &other, now);
+        respond(&mock, vec![value(U256::from(7)), receipt(reverted.hash, 102, 0), value(U64::from(102))]);
+        poll(&rpc, &mut reverted, 3, now).await.unwrap();
+        assert_eq!(reverted.status, TxStatus::Failed);
+
+        // A broadcast to the wrong chain is refused before anything is sent
+        let mut wrong_chain = params.clone();
+        wrong_chain.chain_id = 5;
+        assert!(matches!(broadcast(&rpc, &wrong_chain, &other).await, Err(VeroxError::InvalidArgument(_))));
+    }
+
+    #[tokio::test]
+    async fn test_dropped_and_replaced() {
+        let (mock, rpc) = mock();
+        let (params, signed) = sign(5, 30_000_000_000);
+        let start = Utc::now();
+        let mut tx = TrackedTx::new(&params, &signed, start);
+
+        // Unknown to the node: it is rebroadcast and accepted
+        let later = start + Duration::seconds(60);
+        respond(&mock, vec![value(U256::from(5)), value(Value::Null), value(Value::Null), value(signed.hash)]);
+        poll(&rpc, &mut tx, 3, later).await.unwrap();
+        assert_eq!((tx.status, tx.last_seen_at), (TxStatus::Pending, later));
+
+        // Rejected, but not for long enough to give up on it
+        respond(&mock, vec![value(U256::from(5)), value(Value::Null), value(Value::Null), rejected()]);
+        poll(&rpc, &mut tx, 3, later + Duration::seconds(60)).await.unwrap();
+        assert_eq!(tx.status, TxStatus::Pending);
+
+        respond(&mock, vec![value(U256::from(5)), value(Value::Null), value(Value::Null), rejected()]);
+        poll(&rpc, &mut tx, 3, later + Duration::seconds(DROP_AFTER_SECS + 1)).await.unwrap();
+        assert_eq!(tx.status, TxStatus::Dropped);
+
+        // The nonce was mined by something else: a speed-up or cancel sent elsewhere
+        let mut tx = TrackedTx::new(&params, &signed, start);
+        respond(&mock, vec![value(U256::from(6)), value(Value::Null)]);
+        poll(&rpc, &mut tx, 3, start).await.unwrap();
+        assert_eq!(tx.status, TxStatus::Replaced);
+    }
+
+    #[test]
+    fn test_store_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Utc::now();
+        let (params, original) = sign(5, 30_000_000_000);
+        let (_, replacement) = sign(5, 60_000_000_000);
+
+        let mut replaced = TrackedTx::new(&params, &original, now);
+        TxStore::update(dir.path(), |store| store.upsert(replaced.clone())).unwrap();
+        let mut winner = TrackedTx::new(&params, &replacement, now);
+        winner.status = TxStatus::Included;
+        winner.block_number = Some(100);
+        replaced.status = TxStatus::Replaced;
+        TxStore::update(dir.path(), |store| {
+            store.upsert(winner.clone());
+            store.upsert(replaced.clone());
+        })
+        .unwrap();
+
+        let store = TxStore::load(dir.path()).unwrap();
+        assert_eq!(store.transactions.len(), 2);
+        assert_eq!(store.get(original.hash).unwrap().replaced_by, Some(replacement.hash));
+        assert_eq!(store.active().map(|tx| tx.hash).collect::<Vec<_>>(), vec![replacement.hash]);
+
+        // Addresses are stored checksummed and read back
+        let json = fs::read_to_string(store_path(dir.path())).unwrap();
+        assert!(json.contains(&address_string(original.from)));
+        assert!(json.contains("\"status\": \"replaced\""));
+    }
+
+    #[test]
+    fn test_record_poll_keeps_concurrent_changes() {
+        let now = Utc::now();
+        let (params, original) = sign(5, 30_000_000_000);
+        let (_, replacement) = sign(5, 60_000_000_000);
+        let mut store = TxStore::default();
+        store.upsert(TrackedTx::new(&params, &original, now));
+        let mut speed_up = TrackedTx::new(&params, &replacement, now);
+        speed_up.replaces = Some(original.hash);
+        store.upsert(speed_up.clone());
+
+        // Polled from a copy read before the speed-up was recorded as replacing anything
+        let mut polled = TrackedTx::new(&params, &replacement, now);
+        polled.status = TxStatus::Included;
+        polled.block_number = Some(100);
+        polled.confirmations = 1;
+        let saved = store.record_poll(&polled).unwrap();
+        assert_eq!((saved.status, saved.replaces), (TxStatus::Included, Some(original.hash)));
+
+        // Marked replaced by someone else while a stale copy was being polled
+        let mut replaced = store.get(original.hash).cloned().unwrap();
+        replaced.status = TxStatus::Replaced;
+        store.upsert(replaced);
+        assert_eq!(store.get(original.hash).unwrap().replaced_by, Some(replacement.hash));
+        let stale = TrackedTx::new(&params, &original, now + Duration::seconds(12));
+        assert!(store.record_poll(&stale).is_none());
+        assert_eq!(store.get(original.hash).unwrap().status, TxStatus::Replaced);
+
+        // Nothing new is no change
+        assert!(store.record_poll(&polled).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_reports_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut params, signed) = sign(5, 30_000_000_000);
+        // No network is configured for this chain
+        params.chain_id = 999_999;
+        TxStore::update(dir.path(), |store| store.upsert(TrackedTx::new(&params, &signed, Utc::now()))).unwrap();
+        let report = refresh(dir.path(), 3).await.unwrap();
+        assert!(report.changed.is_empty());
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].starts_with("chain 999999"), "{}", report.errors[0]);
+    }
+}
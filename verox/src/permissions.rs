@@ -0,0 +1,319 @@
+//! Which sites may use the wallet
+//!
+//! `permissions.json` in the data directory holds one grant per origin (the scheme,
+//! host and port of the page making EIP-1193 requests): the accounts it may see and
+//! sign with, the chains it may use, and when the grant runs out. A page gets a grant
+//! when the user approves `eth_requestAccounts` or `wallet_requestPermissions`, and
+//! loses it through `wallet_revokePermissions`, `verox revoke-permissions` or expiry.
+//! The grant is expressed to pages as an EIP-2255 `eth_accounts` permission.
+
+use chrono::{DateTime, Duration, Utc};
+use ethers::types::Address;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{json, Value};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::eip1193::chain_id_hex;
+use crate::error::{Result, VeroxError};
+use crate::events;
+use crate::utils;
+use crate::wallet::address_string;
+
+pub const PERMISSIONS_FILE: &str = "permissions.json";
+
+/// How long a grant lasts before the site has to ask again
+pub const GRANT_TTL_DAYS: i64 = 30;
+
+/// The only permission there is: seeing and using accounts
+pub const ACCOUNTS_PERMISSION: &str = "eth_accounts";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Grant {
+    pub origin: String,
+    #[serde(serialize_with = "checksummed_list")]
+    pub accounts: Vec<Address>,
+    /// Chains the origin may use; empty allows any
+    #[serde(default)]
+    pub chains: Vec<u64>,
+    pub granted_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Grant {
+    pub fn new(origin: String, accounts: Vec<Address>, chains: Vec<u64>, now: DateTime<Utc>) -> Self {
+        Grant {
+            origin,
+            accounts,
+            chains,
+            granted_at: now,
+            expires_at: now + Duration::days(GRANT_TTL_DAYS),
+        }
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        now >= self.expires_at
+    }
+
+    pub fn allows_account(&self, address: Address) -> bool {
+        self.accounts.contains(&address)
+    }
+
+    pub fn allows_chain(&self, chain_id: u64) -> bool {
+        self.chains.is_empty() || self.chains.contains(&chain_id)
+    }
+
+    /// The grant as EIP-2255 permissions, for `wallet_getPermissions`
+    pub fn to_permissions(&self) -> Value {
+        let mut caveats = vec![json!({
+            "type": "restrictReturnedAccounts",
+            "value": self.accounts.iter().map(|a| address_string(*a)).collect::<Vec<_>>(),
+        })];
+        if !self.chains.is_empty() {
+            caveats.push(json!({
+                "type": "restrictChains",
+                "value": self.chains.iter().map(|c| chain_id_hex(*c)).collect::<Vec<_>>(),
+            }));
+        }
+        caveats.push(json!({ "type": "expiresAt", "value": self.expires_at.timestamp_millis() }));
+        json!([{
+            "invoker": self.origin,
+            "parentCapability": ACCOUNTS_PERMISSION,
+            "date": self.granted_at.timestamp_millis(),
+            "caveats": caveats,
+        }])
+    }
+}
+
+fn checksummed_list<S: Serializer>(addresses: &[Address], serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_seq(addresses.iter().map(|address| address_string(*address)))
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PermissionStore {
+    #[serde(default)]
+    pub grants: Vec<Grant>,
+}
+
+impl PermissionStore {
+    /// Load `permissions.json` from `dir`, or an empty store if there is none yet
+    pub fn load(dir: &Path) -> Result<Self> {
+        match fs::read_to_string(permissions_path(dir)) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(PermissionStore::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, dir: &Path) -> Result<()> {
+        utils::create_private_dir(dir)?;
+        utils::write_atomic(&permissions_path(dir), serde_json::to_string_pretty(self)?.as_bytes())
+    }
+
+    /// Load the store from `dir`, apply `change` and save it under the data
+    /// directory lock. Expired grants are dropped on the way.
+    pub fn update<T>(dir: &Path, change: impl FnOnce(&mut PermissionStore) -> T) -> Result<T> {
+        let _lock = utils::lock_dir(dir)?;
+        let mut store = PermissionStore::load(dir)?;
+        let now = Utc::now();
+        store.grants.retain(|grant| !grant.is_expired(now));
+        let result = change(&mut store);
+        store.save(dir)?;
+        events::notify(dir, "permissions");
+        Ok(result)
+    }
+
+    /// The origin's grant, unless it has expired
+    pub fn get(&self, origin: &str, now: DateTime<Utc>) -> Option<&Grant> {
+        self.grants
+            .iter()
+            .find(|grant| grant.origin == origin && !grant.is_expired(now))
+    }
+
+    /// Add a grant, replacing the origin's previous one
+    pub fn grant(&mut self, grant: Grant) {
+        self.grants.retain(|known| known.origin != grant.origin);
+        self.grants.push(grant);
+    }
+
+    pub fn revoke(&mut self, origin: &str) -> Option<Grant> {
+        let position = self.grants.iter().position(|grant| grant.origin == origin)?;
+        Some(self.grants.remove(position))
+    }
+}
+
+/// What a native host action does with keys, for [`authorize_action`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalletAction {
+    /// Signs or sends with one account
+    Sign,
+    /// Unlocks, exports, re-encrypts or replaces; only the extension's own pages may
+    Manage,
+}
+
+impl WalletAction {
+    /// The kind of a native host action, or `None` for one that never touches a key
+    pub fn of(action: &str) -> Option<Self> {
+        match action {
+            "send_transaction" | "sign_message" | "sign_typed_data" => Some(WalletAction::Sign),
+            "unlock_wallet" | "verify_biometric" | "export_keystore" | "change_passphrase" | "add_account"
+            | "delete_account" | "speed_up_transaction" | "cancel_transaction" => Some(WalletAction::Manage),
+            _ => None,
+        }
+    }
+}
+
+/// Check that whoever sent a native host `action` may run it.
+///
+/// The extension tags each message with the origin it came from. Its own pages
+/// (`chrome-extension://`, `moz-extension://`) may run anything. A web page may only
+/// sign, with an account its grant covers, and only once the user approved the request
+/// (`approved: true` or a passphrase), as for `provider_request`. Without an origin
+/// the message is refused, since it can't be told apart from a page's.
+pub fn authorize_action(dir: &Path, action: &str, data: &Value) -> Result<()> {
+    let Some(kind) = WalletAction::of(action) else {
+        return Ok(());
+    };
+    let origin = data["origin"]
+        .as_str()
+        .ok_or_else(|| VeroxError::Unauthorized(format!("{} needs the sender's origin", action)))?;
+    let origin = normalize_origin(origin).map_err(|e| VeroxError::Unauthorized(e.to_string()))?;
+    if origin.starts_with("chrome-extension://") || origin.starts_with("moz-extension://") {
+        return Ok(());
+    }
+    if kind == WalletAction::Manage {
+        return Err(VeroxError::Unauthorized(format!("{} may not {}", origin, action)));
+    }
+
+    let grant = PermissionStore::load(dir)?
+        .get(&origin, Utc::now())
+        .cloned()
+        .ok_or_else(|| VeroxError::Unauthorized(format!("{} is not connected", origin)))?;
+    let account = data["address"]
+        .as_str()
+        .or_else(|| data["from"].as_str())
+        .and_then(|address| address.parse::<Address>().ok())
+        .ok_or_else(|| VeroxError::Unauthorized(format!("{} must name the account by address", origin)))?;
+    if !grant.allows_account(account) {
+        return Err(VeroxError::Unauthorized(format!(
+            "{} may not use account {}",
+            origin,
+            address_string(account)
+        )));
+    }
+    if data["approved"].as_bool() != Some(true) && data["passphrase"].as_str().is_none() {
+        return Err(VeroxError::Unauthorized("The request needs the user's approval".to_string()));
+    }
+    Ok(())
+}
+
+pub fn permissions_path(dir: &Path) -> PathBuf {
+    dir.join(PERMISSIONS_FILE)
+}
+
+/// An origin as `scheme://host[:port]`, lowercased. Paths, queries and credentials
+/// are refused rather than stripped, since they mean the caller passed a URL where
+/// an origin belongs.
+pub fn normalize_origin(origin: &str) -> Result<String> {
+    let invalid = || VeroxError::InvalidArgument(format!("invalid origin '{}'", origin));
+    let origin = origin.trim().trim_end_matches('/').to_lowercase();
+    let (scheme, host) = origin.split_once("://").ok_or_else(invalid)?;
+    if !matches!(scheme, "https" | "http" | "chrome-extension" | "moz-extension") {
+        return Err(invalid());
+    }
+    if host.is_empty() || host.contains(['/', '?', '#', '@']) || host.chars().any(char::is_whitespace) {
+        return Err(invalid());
+    }
+    Ok(origin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_origin() {
+        assert_eq!(normalize_origin("https://App.Uniswap.org/").unwrap(), "https://app.uniswap.org");
+        assert_eq!(normalize_origin("http://localhost:3000").unwrap(), "http://localhost:3000");
+        for bad in ["app.uniswap.org", "https://", "https://a.org/swap", "https://u@a.org", "file:///etc", "ftp://a.org"] {
+            assert!(normalize_origin(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_grants() {
+        let dir = tempfile::tempdir().unwrap();
+        let now = Utc::now();
+        let account = Address::repeat_byte(0x11);
+
+        PermissionStore::update(dir.path(), |store| {
+            store.grant(Grant::new("https://a.org".to_string(), vec![account], vec![], now));
+            store.grant(Grant::new("https://b.org".to_string(), vec![account], vec![1], now));
+            // An expired grant is never returned, and dropped on the next update
+            let mut old = Grant::new("https://old.org".to_string(), vec![account], vec![], now);
+            old.expires_at = now - Duration::seconds(1);
+            store.grant(old);
+        })
+        .unwrap();
+
+        let store = PermissionStore::load(dir.path()).unwrap();
+        let a = store.get("https://a.org", now).unwrap();
+        assert!(a.allows_account(account) && !a.allows_account(Address::zero()));
+        assert!(a.allows_chain(1) && a.allows_chain(10));
+        let b = store.get("https://b.org", now).unwrap();
+        assert!(b.allows_chain(1) && !b.allows_chain(10));
+        assert!(store.get("https://old.org", now).is_none());
+        assert!(store.get("https://a.org", now + Duration::days(GRANT_TTL_DAYS)).is_none());
+
+        let permissions = b.to_permissions();
+        assert_eq!(permissions[0]["parentCapability"], "eth_accounts");
+        assert_eq!(permissions[0]["caveats"][0]["value"][0], address_string(account));
+        assert_eq!(permissions[0]["caveats"][1]["value"][0], "0x1");
+
+        let revoked = PermissionStore::update(dir.path(), |store| store.revoke("https://a.org")).unwrap();
+        assert_eq!(revoked.map(|grant| grant.origin), Some("https://a.org".to_string()));
+        let store = PermissionStore::load(dir.path()).unwrap();
+        assert_eq!(store.grants.iter().map(|g| g.origin.as_str()).collect::<Vec<_>>(), ["https://b.org"]);
+    }
+
+    #[test]
+    fn test_authorize_action() {
+        let dir = tempfile::tempdir().unwrap();
+        let account = Address::repeat_byte(0x11);
+        let from = address_string(account);
+        let denied = |action: &str, data: Value| {
+            matches!(authorize_action(dir.path(), action, &data), Err(VeroxError::Unauthorized(_)))
+        };
+
+        // A bare send_transaction, and one from a page that never connected
+        let tx = json!({ "from": from, "to": from, "value": "1", "passphrase": "pw" });
+        assert!(denied("send_transaction", tx.clone()));
+        let mut from_page = tx.clone();
+        from_page["origin"] = json!("https://evil.org");
+        assert!(denied("send_transaction", from_page.clone()));
+
+        PermissionStore::update(dir.path(), |store| {
+            store.grant(Grant::new("https://evil.org".to_string(), vec![account], vec![], Utc::now()))
+        })
+        .unwrap();
+        assert!(authorize_action(dir.path(), "send_transaction", &from_page).is_ok());
+        // Connected, but the user hasn't approved this one, or it's someone else's account
+        from_page.as_object_mut().unwrap().remove("passphrase");
+        assert!(denied("send_transaction", from_page.clone()));
+        from_page["approved"] = json!(true);
+        assert!(authorize_action(dir.path(), "sign_message", &from_page).is_ok());
+        from_page["from"] = json!(address_string(Address::zero()));
+        assert!(denied("sign_message", from_page));
+        // Unlocking and exporting stay with the extension's own pages, grant or not
+        let page = json!({ "origin": "https://evil.org", "address": from, "passphrase": "pw" });
+        assert!(denied("unlock_wallet", page.clone()));
+        assert!(denied("verify_biometric", page.clone()));
+        assert!(denied("export_keystore", page));
+
+        let extension = json!({ "origin": "chrome-extension://abcdefghijklmnopabcdefghijklmnop", "passphrase": "pw" });
+        assert!(authorize_action(dir.path(), "export_keystore", &extension).is_ok());
+        assert!(authorize_action(dir.path(), "send_transaction", &extension).is_ok());
+        assert!(authorize_action(dir.path(), "get_balance", &json!({})).is_ok());
+    }
+}
@@ -0,0 +1,245 @@
+//! Messages between the browser extension and the native host
+//!
+//! A version 2 request carries an `id`, the protocol `version` and a `method` with
+//! its `params`. The reply echoes the `id`, so the extension can have several
+//! requests in flight and match replies that come back out of order. A `hello`
+//! request settles the version and the capabilities both sides support. Only a
+//! client that negotiated [`CAP_EVENTS`] is sent unsolicited [`Event`]s, because an
+//! older client would take an event for the reply it is waiting on.
+//!
+//! Version 1 messages (`{"action", "data"}`, answered in order and without an id)
+//! are still accepted while the extension migrates.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Highest protocol version this host speaks
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Bare `{"action", "data"}` messages
+pub const LEGACY_VERSION: u32 = 1;
+
+/// Method of the handshake request
+pub const HELLO: &str = "hello";
+
+/// Requests are handled concurrently and may be answered out of order
+pub const CAP_MULTIPLEX: &str = "multiplex";
+
+/// The host pushes [`Event`]s without being asked
+pub const CAP_EVENTS: &str = "events";
+
+/// Replies too large for one frame are split into [`ChunkFrame`]s
+pub const CAP_CHUNKS: &str = "chunks";
+
+/// Every capability this host supports
+pub const CAPABILITIES: &[&str] = &[CAP_MULTIPLEX, CAP_EVENTS, CAP_CHUNKS];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Request(Request),
+    /// A version 1 message
+    Legacy { action: String, data: Option<Value> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    /// Chosen by the client, a number or a string
+    pub id: Value,
+    pub version: u32,
+    pub method: String,
+    pub params: Option<Value>,
+}
+
+/// A message that could not be parsed, with the id to answer to if it had one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rejected {
+    pub id: Option<Value>,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Rejected {
+    fn new(id: Option<Value>, code: &'static str, message: impl Into<String>) -> Self {
+        Rejected { id, code, message: message.into() }
+    }
+}
+
+impl Message {
+    pub fn parse(bytes: &[u8]) -> std::result::Result<Message, Rejected> {
+        let value: Value = serde_json::from_slice(bytes)
+            .map_err(|e| Rejected::new(None, "PARSE_ERROR", format!("Failed to parse message: {}", e)))?;
+        let Value::Object(mut object) = value else {
+            return Err(Rejected::new(None, "INVALID_REQUEST", "message must be a JSON object"));
+        };
+
+        if !object.contains_key("id") {
+            return match object.remove("action") {
+                Some(Value::String(action)) => Ok(Message::Legacy { action, data: object.remove("data") }),
+                Some(_) => Err(Rejected::new(None, "INVALID_REQUEST", "action must be a string")),
+                None => Err(Rejected::new(None, "INVALID_REQUEST", "request has no id")),
+            };
+        }
+
+        let id = match object.remove("id") {
+            Some(id @ (Value::Number(_) | Value::String(_))) => id,
+            _ => return Err(Rejected::new(None, "INVALID_REQUEST", "id must be a number or a string")),
+        };
+        let version = match object.get("version").and_then(Value::as_u64) {
+            Some(version) if version == u64::from(PROTOCOL_VERSION) => PROTOCOL_VERSION,
+            Some(version) => {
+                return Err(Rejected::new(
+                    Some(id),
+                    "UNSUPPORTED_PROTOCOL",
+                    format!("protocol version {} is not supported, this host speaks {}", version, PROTOCOL_VERSION),
+                ))
+            }
+            None => return Err(Rejected::new(Some(id), "INVALID_REQUEST", "version is required")),
+        };
+        let method = match object.remove("method") {
+            Some(Value::String(method)) => method,
+            _ => return Err(Rejected::new(Some(id), "INVALID_REQUEST", "method must be a string")),
+        };
+
+        Ok(Message::Request(Request { id, version, method, params: object.remove("params") }))
+    }
+}
+
+/// Reply to a version 2 request; `body` is flattened next to the echoed `id`
+#[derive(Debug, Serialize)]
+pub struct Response<T> {
+    pub id: Value,
+    pub version: u32,
+    #[serde(flatten)]
+    pub body: T,
+}
+
+impl<T> Response<T> {
+    pub fn new(id: Value, body: T) -> Self {
+        Response { id, version: PROTOCOL_VERSION, body }
+    }
+}
+
+/// A message the host sends on its own; it has no `id`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Event {
+    pub version: u32,
+    pub event: String,
+    pub data: Value,
+}
+
+impl Event {
+    pub fn new(event: &str, data: Value) -> Self {
+        Event { version: PROTOCOL_VERSION, event: event.to_string(), data }
+    }
+}
+
+/// One piece of a reply that didn't fit in a frame. Joining the `data` of all
+/// `count` pieces in `index` order gives the reply's JSON text.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkFrame {
+    pub id: Value,
+    pub version: u32,
+    pub chunk: Chunk,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chunk {
+    pub index: u32,
+    pub count: u32,
+    pub data: String,
+}
+
+/// Parameters of a `hello` request
+#[derive(Debug, Clone, Deserialize)]
+pub struct Hello {
+    /// Highest version the client speaks
+    pub version: u32,
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+/// What both sides agreed on in the handshake
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub version: u32,
+    pub capabilities: Vec<String>,
+}
+
+impl Session {
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+/// The highest version both sides speak, and the capabilities both support.
+/// Capabilities the host doesn't know are dropped.
+pub fn negotiate(hello: &Hello) -> Session {
+    Session {
+        version: hello.version.min(PROTOCOL_VERSION),
+        capabilities: CAPABILITIES
+            .iter()
+            .filter(|c| hello.capabilities.iter().any(|requested| requested == *c))
+            .map(|c| c.to_string())
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(value: Value) -> std::result::Result<Message, Rejected> {
+        Message::parse(value.to_string().as_bytes())
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(json!({"action": "get_networks"})).unwrap(),
+            Message::Legacy { action: "get_networks".to_string(), data: None }
+        );
+        assert_eq!(
+            parse(json!({"id": "a1", "version": 2, "method": "get_balance", "params": {"network": 1}})).unwrap(),
+            Message::Request(Request {
+                id: json!("a1"),
+                version: 2,
+                method: "get_balance".to_string(),
+                params: Some(json!({"network": 1})),
+            })
+        );
+
+        // Rejections keep the id whenever there is one to answer to
+        let rejected = parse(json!({"id": 7, "version": 3, "method": "get_networks"})).unwrap_err();
+        assert_eq!((rejected.id, rejected.code), (Some(json!(7)), "UNSUPPORTED_PROTOCOL"));
+        let rejected = parse(json!({"id": 8, "version": 2})).unwrap_err();
+        assert_eq!((rejected.id, rejected.code), (Some(json!(8)), "INVALID_REQUEST"));
+        let rejected = parse(json!({"id": 9, "method": "get_networks"})).unwrap_err();
+        assert_eq!((rejected.id, rejected.code), (Some(json!(9)), "INVALID_REQUEST"));
+        assert_eq!(parse(json!({"id": null, "version": 2, "method": "x"})).unwrap_err().id, None);
+        assert_eq!(parse(json!({"version": 2, "method": "x"})).unwrap_err().code, "INVALID_REQUEST");
+        assert_eq!(parse(json!([1, 2])).unwrap_err().code, "INVALID_REQUEST");
+        assert_eq!(Message::parse(b"{not json").unwrap_err().code, "PARSE_ERROR");
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let session = negotiate(&Hello {
+            version: 5,
+            capabilities: vec!["events".to_string(), "compression".to_string()],
+        });
+        assert_eq!(session.version, PROTOCOL_VERSION);
+        assert_eq!(session.capabilities, vec![CAP_EVENTS.to_string()]);
+        assert!(session.has(CAP_EVENTS) && !session.has(CAP_MULTIPLEX));
+
+        let response = Response::new(json!(3), json!({"success": true, "data": session}));
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({"id": 3, "version": 2, "success": true, "data": {"version": 2, "capabilities": ["events"]}})
+        );
+        assert_eq!(
+            serde_json::to_value(Event::new("transaction_updated", json!({}))).unwrap(),
+            json!({"version": 2, "event": "transaction_updated", "data": {}})
+        );
+    }
+}
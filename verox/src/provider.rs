@@ -0,0 +1,265 @@
+//! JSON-RPC access to a [`Network`]
+//!
+//! [`RpcProvider`] wraps an ethers `Provider` together with the network it is meant
+//! to reach, and refuses endpoints that report a different chain ID so a misconfigured
+//! URL can never have a transaction signed for one chain sent to another. It is
+//! generic over the transport so tests can run against ethers' `MockProvider`.
+
+use ethers::providers::{Http, JsonRpcClient, Middleware, Provider};
+use ethers::types::{Address, BlockNumber, Bytes, TransactionRequest, H256, U256};
+use std::fmt::Display;
+use std::future::Future;
+
+use crate::error::{Result, VeroxError};
+use crate::fees::{self, FeeSpeed};
+use crate::network::Network;
+use crate::transaction::TxRequest;
+
+/// Extra gas on top of the estimate for contract calls, in percent, since state can
+/// change between estimation and inclusion
+pub const GAS_HEADROOM_PERCENT: u64 = 20;
+
+pub struct RpcProvider<P = Http> {
+    provider: Provider<P>,
+    network: Network,
+}
+
+impl RpcProvider<Http> {
+    /// Connect to the first of the network's RPC URLs that answers with its chain ID
+    pub async fn connect(network: &Network) -> Result<Self> {
+        let mut failures = Vec::new();
+        for url in &network.rpc_urls {
+            let provider = match Provider::<Http>::try_from(url.as_str()) {
+                Ok(provider) => provider,
+                Err(e) => {
+                    failures.push(format!("{}: {}", url, e));
+                    continue;
+                }
+            };
+            let rpc = RpcProvider::new(provider, network.clone());
+            match rpc.check_chain_id().await {
+                Ok(()) => return Ok(rpc),
+                Err(e) => failures.push(format!("{}: {}", url, e)),
+            }
+        }
+        Err(VeroxError::Rpc(format!("no usable RPC endpoint for {} ({})", network.name, failures.join("; "))))
+    }
+}
+
+impl<P: JsonRpcClient> RpcProvider<P> {
+    pub fn new(provider: Provider<P>, network: Network) -> Self {
+        RpcProvider { provider, network }
+    }
+
+    pub fn network(&self) -> &Network {
+        &self.network
+    }
+
+    /// The underlying ethers provider, for calls this wrapper doesn't cover
+    pub fn inner(&self) -> &Provider<P> {
+        &self.provider
+    }
+
+    pub async fn chain_id(&self) -> Result<u64> {
+        let chain_id = self.provider.get_chainid().await.map_err(rpc_error)?;
+        u64::try_from(chain_id).map_err(|_| VeroxError::Rpc(format!("chain ID {} out of range", chain_id)))
+    }
+
+    /// Fail unless the endpoint serves the configured chain
+    pub async fn check_chain_id(&self) -> Result<()> {
+        let chain_id = self.chain_id().await?;
+        if chain_id != self.network.chain_id {
+            return Err(VeroxError::Rpc(format!(
+                "endpoint serves chain {}, expected {} ({})",
+                chain_id, self.network.chain_id, self.network.name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Balance in wei at the latest block
+    pub async fn balance(&self, address: Address) -> Result<U256> {
+        self.provider.get_balance(address, None).await.map_err(rpc_error)
+    }
+
+    /// Legacy `eth_gasPrice`, in wei
+    pub async fn gas_price(&self) -> Result<U256> {
+        self.provider.get_gas_price().await.map_err(rpc_error)
+    }
+
+    /// Next nonce for `address`, counting transactions still in the mempool
+    pub async fn nonce(&self, address: Address) -> Result<U256> {
+        self.provider
+            .get_transaction_count(address, Some(BlockNumber::Pending.into()))
+            .await
+            .map_err(rpc_error)
+    }
+
+    /// `eth_estimateGas` for `request` sent from `from`, with headroom for contract calls
+    pub async fn estimate_gas(&self, from: Address, request: &TxRequest) -> Result<U256> {
+        let mut call = TransactionRequest::new().from(from).value(request.value).data(request.data.clone());
+        if let Some(to) = request.to {
+            call = call.to(to);
+        }
+        let gas = self.provider.estimate_gas(&call.into(), None).await.map_err(rpc_error)?;
+        if request.data.is_empty() {
+            return Ok(gas);
+        }
+        Ok(gas * (100 + GAS_HEADROOM_PERCENT) / 100)
+    }
+
+    /// Submit a signed transaction and return the hash the node reports
+    pub async fn send_raw_transaction(&self, raw: Bytes) -> Result<H256> {
+        let pending = self.provider.send_raw_transaction(raw).await.map_err(rpc_error)?;
+        Ok(pending.tx_hash())
+    }
+
+    /// Fill in what `request` leaves out for a transaction from `from`: chain ID,
+    /// nonce, gas limit and fees (the `speed` preset). Every fee is checked against
+    /// the cap unless `allow_high_fee`.
+    pub async fn fill_request(
+        &self,
+        request: &mut TxRequest,
+        from: Address,
+        speed: FeeSpeed,
+        allow_high_fee: bool,
+    ) -> Result<()> {
+        match request.chain_id {
+            Some(chain_id) if chain_id != self.network.chain_id => {
+                return Err(VeroxError::InvalidArgument(format!(
+                    "transaction is for chain {} but {} is chain {}",
+                    chain_id, self.network.name, self.network.chain_id
+                )))
+            }
+            _ => request.chain_id = Some(self.network.chain_id),
+        }
+        if request.nonce.is_none() {
+            request.nonce = Some(self.nonce(from).await?);
+        }
+        if request.gas_limit.is_none() {
+            request.gas_limit = Some(self.estimate_gas(from, request).await?);
+        }
+        fees::estimate_fees(self).await?.apply(request, speed, allow_high_fee)
+    }
+}
+
+pub(crate) fn rpc_error(e: impl Display) -> VeroxError {
+    VeroxError::Rpc(e.to_string())
+}
+
+/// Run a provider call from synchronous code (the CLI and the native host)
+#[cfg(not(target_arch = "wasm32"))]
+pub fn block_on<F: Future>(future: F) -> Result<F::Output> {
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    Ok(runtime.block_on(future))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::NativeCurrency;
+    use ethers::providers::MockProvider;
+
+    fn mock(chain_id: u64) -> (MockProvider, RpcProvider<MockProvider>) {
+        let mock = MockProvider::new();
+        let network = Network {
+            chain_id,
+            name: "Mock".to_string(),
+            rpc_urls: vec!["http://mock".to_string()],
+            native_currency: NativeCurrency::ether(),
+            explorer_url: None,
+        };
+        (mock.clone(), RpcProvider::new(Provider::new(mock), network))
+    }
+
+    #[tokio::test]
+    async fn test_queries() {
+        let (mock, rpc) = mock(1);
+        let address: Address = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266".parse().unwrap();
+
+        mock.push(U256::exp10(18)).unwrap();
+        assert_eq!(rpc.balance(address).await.unwrap(), U256::exp10(18));
+        mock.assert_request("eth_getBalance", (address, "latest")).unwrap();
+
+        mock.push(U256::from(7)).unwrap();
+        assert_eq!(rpc.nonce(address).await.unwrap(), U256::from(7));
+        mock.assert_request("eth_getTransactionCount", (address, "pending")).unwrap();
+
+        mock.push(U256::from(25_000_000_000u64)).unwrap();
+        assert_eq!(rpc.gas_price().await.unwrap(), U256::from(25_000_000_000u64));
+    }
+
+    #[tokio::test]
+    async fn test_chain_id_mismatch() {
+        let (mock, rpc) = mock(1);
+        mock.push(U256::from(1)).unwrap();
+        rpc.check_chain_id().await.unwrap();
+
+        mock.push(U256::from(11155111)).unwrap();
+        assert!(matches!(rpc.check_chain_id().await, Err(VeroxError::Rpc(_))));
+
+        // Nothing queued: the transport error surfaces as an RPC error
+        assert!(matches!(rpc.gas_price().await, Err(VeroxError::Rpc(_))));
+    }
+
+    #[tokio::test]
+    async fn test_fill_request() {
+        let (mock, rpc) = mock(1);
+        let from: Address = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266".parse().unwrap();
+        let mut request = TxRequest {
+            to: Some(Address::repeat_byte(0x11)),
+            data: Bytes::from(vec![0xa9, 0x05, 0x9c, 0xbb]),
+            ..Default::default()
+        };
+
+        // Answered last call first: the nonce, the gas estimate, then eth_feeHistory
+        // (no base fee, so a legacy chain) and eth_gasPrice
+        mock.push(U256::from(20_000_000_000u64)).unwrap();
+        mock.push(serde_json::json!({
+            "oldestBlock": "0x1",
+            "baseFeePerGas": ["0x0", "0x0"],
+            "gasUsedRatio": [0.5],
+            "reward": [["0x0", "0x0", "0x0"]]
+        }))
+        .unwrap();
+        mock.push(U256::from(50_000)).unwrap();
+        mock.push(U256::from(3)).unwrap();
+        rpc.fill_request(&mut request, from, FeeSpeed::Normal, false).await.unwrap();
+
+        assert_eq!(request.chain_id, Some(1));
+        assert_eq!(request.nonce, Some(U256::from(3)));
+        // Calldata gets 20% headroom
+        assert_eq!(request.gas_limit, Some(U256::from(60_000)));
+        assert_eq!(request.gas_price, Some(U256::from(22_000_000_000u64)));
+
+        request.chain_id = Some(5);
+        assert!(matches!(
+            rpc.fill_request(&mut request, from, FeeSpeed::Normal, false).await,
+            Err(VeroxError::InvalidArgument(_))
+        ));
+    }
+
+    // Needs `anvil` from Foundry on PATH: cargo test -- --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_against_anvil() {
+        let anvil = ethers::utils::Anvil::new().spawn();
+        let network = Network {
+            chain_id: anvil.chain_id(),
+            name: "Anvil".to_string(),
+            rpc_urls: vec!["http://127.0.0.1:1".to_string(), anvil.endpoint()],
+            native_currency: NativeCurrency::ether(),
+            explorer_url: None,
+        };
+        // The first URL is dead, the second one answers
+        let rpc = RpcProvider::connect(&network).await.unwrap();
+        let address = anvil.addresses()[0];
+        assert_eq!(rpc.balance(address).await.unwrap(), U256::exp10(22));
+        assert_eq!(rpc.nonce(address).await.unwrap(), U256::zero());
+        assert!(rpc.gas_price().await.unwrap() > U256::zero());
+
+        let mut wrong_chain = network.clone();
+        wrong_chain.chain_id = 1;
+        assert!(RpcProvider::connect(&wrong_chain).await.is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! Plaintext registry of the accounts in a keystore directory
+//!
+//! The `.dat` files hold secrets and need a passphrase to open; `index.json` next to
+//! them lists every account (address, label, derivation path, which `.dat` it lives
+//! in) so wallets can be listed and picked without decrypting anything.
+//! [`Registry::check`] reports where the index and the files disagree.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, VeroxError};
+use crate::types::WalletMeta;
+use crate::utils;
+
+pub const INDEX_FILE: &str = "index.json";
+
+/// Disagreements between `index.json` and the `.dat` files next to it
+#[derive(Debug, Default)]
+pub struct ConsistencyReport {
+    /// Index entries whose keystore file is gone
+    pub missing_files: Vec<WalletMeta>,
+    /// Keystore files no index entry points at
+    pub unindexed_files: Vec<String>,
+}
+
+impl ConsistencyReport {
+    pub fn is_clean(&self) -> bool {
+        self.missing_files.is_empty() && self.unindexed_files.is_empty()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Registry {
+    #[serde(default)]
+    pub accounts: Vec<WalletMeta>,
+}
+
+impl Registry {
+    /// Load `index.json` from `dir`, or an empty registry if there is none yet
+    pub fn load(dir: &Path) -> Result<Self> {
+        match fs::read_to_string(index_path(dir)) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Registry::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the registry through a temporary file so readers never see half of it.
+    /// Changes go through [`Registry::update`], which holds the directory lock.
+    fn save(&self, dir: &Path) -> Result<()> {
+        utils::create_private_dir(dir)?;
+        utils::write_atomic(&index_path(dir), serde_json::to_string_pretty(self)?.as_bytes())
+    }
+
+    /// Load the registry from `dir`, apply `change` and save it, all under the
+    /// directory lock so concurrent writers can't drop each other's entries.
+    /// Nothing is saved if `change` fails.
+    pub fn update<T>(dir: &Path, change: impl FnOnce(&mut Registry) -> Result<T>) -> Result<T> {
+        let _lock = utils::lock_dir(dir)?;
+        let mut registry = Registry::load(dir)?;
+        let result = change(&mut registry)?;
+        registry.save(dir)?;
+        Ok(result)
+    }
+
+    /// Look an account up by address (case-insensitive)
+    pub fn find(&self, address: &str) -> Option<&WalletMeta> {
+        self.accounts.iter().find(|meta| meta.address.eq_ignore_ascii_case(address))
+    }
+
+    pub fn find_mut(&mut self, address: &str) -> Option<&mut WalletMeta> {
+        self.accounts.iter_mut().find(|meta| meta.address.eq_ignore_ascii_case(address))
+    }
+
+    /// Resolve an address, or failing that a label, to the address of a single account
+    pub fn resolve(&self, selector: &str) -> Result<String> {
+        if let Some(meta) = self.find(selector) {
+            return Ok(meta.address.clone());
+        }
+        let mut labelled = self
+            .accounts
+            .iter()
+            .filter(|meta| meta.label.as_deref().is_some_and(|label| label.eq_ignore_ascii_case(selector)));
+        match (labelled.next(), labelled.next()) {
+            (Some(meta), None) => Ok(meta.address.clone()),
+            (Some(_), Some(_)) => Err(VeroxError::InvalidArgument(format!(
+                "several accounts are labelled '{}', use the address",
+                selector
+            ))),
+            _ => Err(VeroxError::WalletNotFound(format!("no account matches '{}'", selector))),
+        }
+    }
+
+    /// Add an account, replacing an existing entry for the same address
+    pub fn insert(&mut self, meta: WalletMeta) {
+        match self.find_mut(&meta.address) {
+            Some(existing) => *existing = meta,
+            None => self.accounts.push(meta),
+        }
+    }
+
+    /// Remove an account's entry
+    pub fn remove(&mut self, address: &str) -> Option<WalletMeta> {
+        let position = self.accounts.iter().position(|meta| meta.address.eq_ignore_ascii_case(address))?;
+        Some(self.accounts.remove(position))
+    }
+
+    /// Accounts whose secret lives in keystore `file`
+    pub fn accounts_in<'a>(&'a self, file: &'a str) -> impl Iterator<Item = &'a WalletMeta> + 'a {
+        self.accounts.iter().filter(move |meta| meta.file.as_deref() == Some(file))
+    }
+
+    /// Compare the entries with the keystore files actually present in `dir`
+    pub fn check(&self, dir: &Path) -> Result<ConsistencyReport> {
+        let files = keystore_files(dir)?;
+        Ok(ConsistencyReport {
+            missing_files: self
+                .accounts
+                .iter()
+                .filter(|meta| meta.file.as_ref().is_some_and(|file| !files.contains(file)))
+                .cloned()
+                .collect(),
+            unindexed_files: files
+                .into_iter()
+                .filter(|file| self.accounts_in(file).next().is_none())
+                .collect(),
+        })
+    }
+}
+
+pub fn index_path(dir: &Path) -> PathBuf {
+    dir.join(INDEX_FILE)
+}
+
+/// Names of the `.dat` keystore files in `dir`, sorted
+pub fn keystore_files(dir: &Path) -> Result<Vec<String>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files: Vec<String> = fs::read_dir(dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|ext| ext.to_str()) == Some("dat"))
+        .filter_map(|path| path.file_name()?.to_str().map(str::to_string))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::KeyType;
+
+    fn meta(address: &str, file: &str, index: u32) -> WalletMeta {
+        WalletMeta {
+            address: address.to_string(),
+            created_at: "2024-01-01T00:00:00Z".to_string(),
+            file: Some(file.to_string()),
+            label: None,
+            key_type: KeyType::Mnemonic,
+            derivation_path: Some(format!("m/44'/60'/0'/0/{}", index)),
+            index: Some(index),
+            hidden: false,
+            last_used: None,
+        }
+    }
+
+    #[test]
+    fn test_registry_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Registry::load(dir.path()).unwrap().accounts.is_empty());
+
+        let mut registry = Registry::default();
+        registry.insert(meta("0xAbC", "seed.dat", 0));
+        registry.insert(meta("0xDeF", "seed.dat", 1));
+        registry.insert(meta("0x123", "other.dat", 0));
+        registry.find_mut("0xabc").unwrap().label = Some("main".to_string());
+        registry.save(dir.path()).unwrap();
+
+        let mut loaded = Registry::load(dir.path()).unwrap();
+        assert_eq!(loaded.accounts.len(), 3);
+        assert_eq!(loaded.find("0xABC").unwrap().label.as_deref(), Some("main"));
+        assert_eq!(loaded.accounts_in("seed.dat").count(), 2);
+        assert_eq!(loaded.resolve("Main").unwrap(), "0xAbC");
+        assert!(matches!(loaded.resolve("savings"), Err(VeroxError::WalletNotFound(_))));
+
+        // Re-inserting an address replaces its entry instead of duplicating it
+        loaded.insert(meta("0xdef", "seed.dat", 1));
+        assert_eq!(loaded.accounts.len(), 3);
+        assert_eq!(loaded.remove("0xDEF").unwrap().index, Some(1));
+        assert!(loaded.remove("0xdef").is_none());
+    }
+
+    #[test]
+    fn test_concurrent_updates() {
+        let dir = tempfile::tempdir().unwrap();
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let dir = dir.path().to_path_buf();
+                std::thread::spawn(move || {
+                    Registry::update(&dir, |registry| {
+                        registry.insert(meta(&format!("0x{}", i), "seed.dat", i));
+                        Ok(())
+                    })
+                    .unwrap()
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(Registry::load(dir.path()).unwrap().accounts.len(), 8);
+
+        // A failed change leaves the index as it was
+        let failed = Registry::update(dir.path(), |registry| -> Result<()> {
+            registry.accounts.clear();
+            Err(VeroxError::InvalidArgument("no".to_string()))
+        });
+        assert!(failed.is_err());
+        assert_eq!(Registry::load(dir.path()).unwrap().accounts.len(), 8);
+        let leftovers = fs::read_dir(dir.path()).unwrap().flatten();
+        assert!(leftovers.map(|entry| entry.file_name()).all(|name| !name.to_string_lossy().ends_with(".tmp")));
+    }
+
+    #[test]
+    fn test_consistency_check() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("seed.dat"), b"").unwrap();
+        fs::write(dir.path().join("stray.dat"), b"").unwrap();
+
+        let mut registry = Registry::default();
+        registry.insert(meta("0xAbC", "seed.dat", 0));
+        registry.insert(meta("0x123", "gone.dat", 0));
+        let mut watch_only = meta("0x456", "", 0);
+        watch_only.file = None;
+        watch_only.key_type = KeyType::WatchOnly;
+        registry.insert(watch_only);
+
+        let report = registry.check(dir.path()).unwrap();
+        assert!(!report.is_clean());
+        assert_eq!(report.missing_files.len(), 1);
+        assert_eq!(report.missing_files[0].address, "0x123");
+        assert_eq!(report.unindexed_files, vec!["stray.dat".to_string()]);
+    }
+}
@@ -0,0 +1,227 @@
+//! Speeding up and cancelling pending transactions
+//!
+//! Both re-sign a tracked transaction's nonce: a speed-up with the same payload, a
+//! cancel as a zero-value transfer to the sender. Nodes only accept a replacement
+//! that raises every fee by at least [`REPLACEMENT_BUMP_PERCENT`], so the new fees
+//! are the larger of that bump and the current preset for the chosen speed.
+//! Whichever of the two transactions is mined, the other ends up `replaced` in
+//! the pending store.
+
+use ethers::providers::JsonRpcClient;
+use ethers::types::transaction::eip2718::TypedTransaction;
+use ethers::types::{Bytes, H256, U256};
+use ethers::utils::rlp::Rlp;
+use std::fmt;
+use std::path::Path;
+
+use crate::agent::UnlockedAccount;
+use crate::error::{Result, VeroxError};
+use crate::fees::{self, FeeEstimate, FeeSpeed};
+use crate::pending::{self, TrackedTx, TxStatus, TxStore};
+use crate::provider::RpcProvider;
+use crate::transaction::{TxParams, TxRequest, TxType};
+
+/// Minimum fee increase for a node to accept a replacement (geth's default price bump)
+pub const REPLACEMENT_BUMP_PERCENT: u64 = 10;
+
+/// Gas for a plain transfer, all a cancel needs
+const TRANSFER_GAS: u64 = 21000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replacement {
+    /// Same payload, higher fees
+    SpeedUp,
+    /// Zero-value transfer to the sender, higher fees
+    Cancel,
+}
+
+impl fmt::Display for Replacement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Replacement::SpeedUp => f.write_str("speed-up"),
+            Replacement::Cancel => f.write_str("cancel"),
+        }
+    }
+}
+
+/// Decode a tracked transaction's signed envelope back into a request
+pub fn decode_request(tx: &TrackedTx) -> Result<TxRequest> {
+    let (typed, _) = TypedTransaction::decode_signed(&Rlp::new(&tx.raw))
+        .map_err(|e| VeroxError::Parse(format!("cannot decode transaction {:?}: {}", tx.hash, e)))?;
+    let (tx_type, gas_price, max_fee_per_gas, max_priority_fee_per_gas) = match &typed {
+        TypedTransaction::Legacy(legacy) => (TxType::Legacy, legacy.gas_price, None, None),
+        TypedTransaction::Eip2930(eip2930) => (TxType::Eip2930, eip2930.tx.gas_price, None, None),
+        TypedTransaction::Eip1559(eip1559) => (
+            TxType::Eip1559,
+            None,
+            eip1559.max_fee_per_gas,
+            eip1559.max_priority_fee_per_gas,
+        ),
+    };
+    Ok(TxRequest {
+        tx_type: Some(tx_type),
+        from: Some(tx.from),
+        chain_id: Some(tx.chain_id),
+        nonce: Some(tx.nonce.into()),
+        to: typed.to().and_then(|to| to.as_address().copied()),
+        value: typed.value().copied().unwrap_or_default(),
+        data: typed.data().cloned().unwrap_or_default(),
+        gas_limit: typed.gas().copied(),
+        gas_price,
+        max_fee_per_gas,
+        max_priority_fee_per_gas,
+        access_list: typed.access_list().cloned(),
+    })
+}
+
+/// The transaction replacing `original`, priced from `estimate`. Fails with
+/// `FEE_TOO_HIGH` if the new fee is above the estimate's cap, unless `allow_high_fee`.
+pub fn replacement_request(
+    original: &TrackedTx,
+    kind: Replacement,
+    estimate: &FeeEstimate,
+    speed: FeeSpeed,
+    allow_high_fee: bool,
+) -> Result<TxRequest> {
+    let mut request = decode_request(original)?;
+    if kind == Replacement::Cancel {
+        request.to = Some(original.from);
+        request.value = U256::zero();
+        request.data = Bytes::default();
+        request.gas_limit = Some(TRANSFER_GAS.into());
+        request.access_list = None;
+    }
+
+    let preset = estimate.preset(speed);
+    let bump = |fee: Option<U256>, market: U256| bumped(fee.unwrap_or_default()).max(market);
+    if request.resolved_type() == TxType::Eip1559 {
+        let tip = bump(request.max_priority_fee_per_gas, preset.max_priority_fee_per_gas);
+        let max_fee = bump(request.max_fee_per_gas, preset.max_fee_per_gas).max(tip);
+        request.max_priority_fee_per_gas = Some(tip);
+        request.max_fee_per_gas = Some(max_fee);
+    } else {
+        request.gas_price = Some(bump(request.gas_price, preset.max_fee_per_gas));
+    }
+
+    if !allow_high_fee {
+        estimate.check_cap(request.max_fee_per_gas.or(request.gas_price).unwrap_or_default())?;
+    }
+    Ok(request)
+}
+
+/// Sign and broadcast a replacement for the pending transaction `hash` in `dir`'s
+/// store, and record it there
+pub async fn replace<P: JsonRpcClient>(
+    rpc: &RpcProvider<P>,
+    dir: &Path,
+    wallet: &UnlockedAccount,
+    hash: H256,
+    kind: Replacement,
+    speed: FeeSpeed,
+    allow_high_fee: bool,
+) -> Result<(TxParams, TrackedTx)> {
+    let original = TxStore::load(dir)?
+        .get(hash)
+        .cloned()
+        .ok_or_else(|| VeroxError::InvalidArgument(format!("transaction {:?} is not tracked", hash)))?;
+    if original.status != TxStatus::Pending {
+        return Err(VeroxError::InvalidArgument(format!(
+            "transaction {:?} is {}, only pending transactions can be replaced",
+            hash, original.status
+        )));
+    }
+    if original.from != wallet.address() {
+        return Err(VeroxError::InvalidArgument(format!(
+            "transaction {:?} was not sent by the unlocked account",
+            hash
+        )));
+    }
+
+    let estimate = fees::estimate_fees(rpc).await?;
+    let params = replacement_request(&original, kind, &estimate, speed, allow_high_fee)?.into_params()?;
+    let signed = wallet.sign_transaction(&params)?;
+    let mut tracked = pending::broadcast(rpc, &params, &signed).await?;
+    tracked.replaces = Some(original.hash);
+    TxStore::update(dir, |store| store.upsert(tracked.clone()))?;
+    Ok((params, tracked))
+}
+
+/// `fee` raised by [`REPLACEMENT_BUMP_PERCENT`], rounded up
+fn bumped(fee: U256) -> U256 {
+    (fee * (100 + REPLACEMENT_BUMP_PERCENT) + 99) / 100
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::sign_transaction;
+    use chrono::Utc;
+    use ethers::signers::LocalWallet;
+    use ethers::types::Address;
+
+    const KEY: &str = "4c0883a69102937d6231471b5ecb1c5a0a8a4f2a6e1d0b51c7bce6ad8e7fa0a1";
+    const GWEI: u64 = 1_000_000_000;
+
+    fn tracked(request: TxRequest) -> TrackedTx {
+        let wallet: LocalWallet = KEY.parse().unwrap();
+        let params = request.into_params().unwrap();
+        TrackedTx::new(&params, &sign_transaction(&wallet, &params).unwrap(), Utc::now())
+    }
+
+    fn transfer() -> TxRequest {
+        TxRequest {
+            chain_id: Some(1),
+            nonce: Some(9.into()),
+            to: Some(Address::repeat_byte(0x11)),
+            value: U256::exp10(17),
+            data: Bytes::from(vec![0xde, 0xad]),
+            gas_limit: Some(30000.into()),
+            max_fee_per_gas: Some((40 * GWEI).into()),
+            max_priority_fee_per_gas: Some((2 * GWEI).into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_speed_up() {
+        let original = tracked(transfer());
+        let decoded = decode_request(&original).unwrap();
+        assert_eq!((decoded.nonce, decoded.to, decoded.value), (Some(9.into()), transfer().to, U256::exp10(17)));
+
+        // A quiet market: the 10% bump sets both fees
+        let quiet = fees::estimate_from_gas_price(GWEI.into());
+        let request = replacement_request(&original, Replacement::SpeedUp, &quiet, FeeSpeed::Fast, true).unwrap();
+        assert_eq!(request.max_fee_per_gas, Some((44 * GWEI).into()));
+        assert_eq!(request.max_priority_fee_per_gas, Some(U256::from(22 * GWEI / 10)));
+        assert_eq!((request.nonce, request.data.clone()), (Some(9.into()), transfer().data));
+
+        // Gas spiked: the preset is above the bump and wins
+        let spike = fees::estimate_from_gas_price((100 * GWEI).into());
+        let request = replacement_request(&original, Replacement::SpeedUp, &spike, FeeSpeed::Fast, true).unwrap();
+        assert_eq!(request.max_fee_per_gas, Some((125 * GWEI).into()));
+        // ... unless that is above the cap
+        let capped = FeeEstimate { fee_cap: (100 * GWEI).into(), ..spike };
+        assert!(matches!(
+            replacement_request(&original, Replacement::SpeedUp, &capped, FeeSpeed::Fast, false),
+            Err(VeroxError::FeeTooHigh(_))
+        ));
+    }
+
+    #[test]
+    fn test_cancel_legacy() {
+        let original = tracked(TxRequest {
+            max_fee_per_gas: None,
+            max_priority_fee_per_gas: None,
+            gas_price: Some((30 * GWEI).into()),
+            ..transfer()
+        });
+        let estimate = fees::estimate_from_gas_price((20 * GWEI).into());
+        let request = replacement_request(&original, Replacement::Cancel, &estimate, FeeSpeed::Normal, false).unwrap();
+        assert_eq!(request.tx_type, Some(TxType::Legacy));
+        assert_eq!(request.to, Some(original.from));
+        assert_eq!((request.value, request.data.len()), (U256::zero(), 0));
+        assert_eq!(request.gas_limit, Some(TRANSFER_GAS.into()));
+        assert_eq!(request.gas_price, Some((33 * GWEI).into()));
+        assert_eq!(request.nonce, Some(9.into()));
+    }
+}
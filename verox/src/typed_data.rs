@@ -0,0 +1,271 @@
+//! EIP-712 typed structured data (`eth_signTypedData_v4`)
+//!
+//! Hashing follows the v4 rules (nested structs, arrays of structs and atomic
+//! types) through ethers' `TypedData`; the signed digest is
+//! `keccak256(0x1901 || domainSeparator || hashStruct(message))`.
+//!
+//! Before signing, the payload is rendered as a tree of [`TypedField`]s so the user
+//! sees the domain and every field of the message, not an opaque hash.
+
+use ethers::signers::LocalWallet;
+use ethers::types::transaction::eip712::{Eip712, TypedData, Types};
+use ethers::types::{Address, Signature, H256, U256};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+
+use crate::error::{Result, VeroxError};
+use crate::wallet::address_string;
+
+/// Domain separator, struct hash and the digest that gets signed
+#[derive(Debug, Clone, Copy)]
+pub struct TypedDataHashes {
+    pub domain_separator: H256,
+    pub struct_hash: H256,
+    pub digest: H256,
+}
+
+/// One field of the payload as shown to the user.
+/// Structs and arrays have `children`; atomic values have a `value`.
+#[derive(Debug, Clone, Serialize)]
+pub struct TypedField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TypedField>,
+}
+
+/// Parse a v4 payload, given either as the JSON object or as a JSON string of it
+/// (ethers.js and most dapps send the stringified form)
+pub fn parse_typed_data(json: &Value) -> Result<TypedData> {
+    serde_json::from_value(json.clone())
+        .map_err(|e| VeroxError::InvalidArgument(format!("invalid EIP-712 payload: {}", e)))
+}
+
+pub fn hash_typed_data(typed_data: &TypedData) -> Result<TypedDataHashes> {
+    let invalid = |e: ethers::types::transaction::eip712::Eip712Error| {
+        VeroxError::InvalidArgument(format!("cannot encode EIP-712 payload: {}", e))
+    };
+    Ok(TypedDataHashes {
+        domain_separator: H256(typed_data.domain_separator().map_err(invalid)?),
+        struct_hash: H256(typed_data.struct_hash().map_err(invalid)?),
+        digest: H256(typed_data.encode_eip712().map_err(invalid)?),
+    })
+}
+
+/// Sign the EIP-712 digest of `typed_data`
+pub fn sign_typed_data(wallet: &LocalWallet, typed_data: &TypedData) -> Result<Signature> {
+    let hashes = hash_typed_data(typed_data)?;
+    wallet
+        .sign_hash(hashes.digest)
+        .map_err(|e| VeroxError::InvalidArgument(format!("cannot sign typed data: {}", e)))
+}
+
+/// The domain and the message as two trees, in declaration order
+pub fn field_tree(typed_data: &TypedData) -> Result<Vec<TypedField>> {
+    let domain = serde_json::to_value(&typed_data.domain)?;
+    let domain_fields = [
+        ("name", "string"),
+        ("version", "string"),
+        ("chainId", "uint256"),
+        ("verifyingContract", "address"),
+        ("salt", "bytes32"),
+    ]
+    .iter()
+    .filter(|(name, _)| !domain[name].is_null())
+    .map(|(name, type_name)| {
+        let value = match *name {
+            "salt" => typed_data.domain.salt.map(|salt| format!("0x{}", hex::encode(salt))),
+            _ => Some(format_atomic(type_name, &domain[name])),
+        };
+        TypedField {
+            name: name.to_string(),
+            type_name: type_name.to_string(),
+            value,
+            children: Vec::new(),
+        }
+    })
+    .collect();
+
+    let message = Value::Object(typed_data.message.clone().into_iter().collect());
+    Ok(vec![
+        TypedField {
+            name: "domain".to_string(),
+            type_name: "EIP712Domain".to_string(),
+            value: None,
+            children: domain_fields,
+        },
+        build_field("message", &typed_data.primary_type, &message, &typed_data.types, 0)?,
+    ])
+}
+
+// Deeply nested payloads are rejected rather than overflowing the stack
+const MAX_DEPTH: usize = 32;
+
+fn build_field(name: &str, type_name: &str, value: &Value, types: &Types, depth: usize) -> Result<TypedField> {
+    if depth > MAX_DEPTH {
+        return Err(VeroxError::InvalidArgument("EIP-712 payload is nested too deeply".to_string()));
+    }
+    let mut field = TypedField {
+        name: name.to_string(),
+        type_name: type_name.to_string(),
+        value: None,
+        children: Vec::new(),
+    };
+
+    if let Some(element_type) = type_name.strip_suffix(']').and_then(|t| t.rsplit_once('[')).map(|(t, _)| t) {
+        let items = value
+            .as_array()
+            .ok_or_else(|| VeroxError::InvalidArgument(format!("{} should be an array", name)))?;
+        for (i, item) in items.iter().enumerate() {
+            field.children.push(build_field(&format!("[{}]", i), element_type, item, types, depth + 1)?);
+        }
+    } else if let Some(members) = types.get(type_name) {
+        for member in members {
+            field
+                .children
+                .push(build_field(&member.name, &member.r#type, &value[&member.name], types, depth + 1)?);
+        }
+    } else {
+        field.value = Some(format_atomic(type_name, value));
+    }
+    Ok(field)
+}
+
+fn format_atomic(type_name: &str, value: &Value) -> String {
+    let text = match value {
+        Value::String(s) => s.clone(),
+        Value::Null => return "null".to_string(),
+        other => other.to_string(),
+    };
+    if type_name == "address" {
+        if let Ok(address) = text.parse::<Address>() {
+            return address_string(address);
+        }
+    }
+    // Quantities may arrive as hex; show them in decimal
+    if type_name.starts_with("uint") {
+        if let Some(quantity) = text.strip_prefix("0x").and_then(|hex| U256::from_str_radix(hex, 16).ok()) {
+            return quantity.to_string();
+        }
+    }
+    text
+}
+
+impl fmt::Display for TypedField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn write_field(f: &mut fmt::Formatter<'_>, field: &TypedField, indent: usize) -> fmt::Result {
+            let pad = "  ".repeat(indent);
+            match &field.value {
+                Some(value) => writeln!(f, "{}{}: {}", pad, field.name, value)?,
+                None => writeln!(f, "{}{} ({})", pad, field.name, field.type_name)?,
+            }
+            field.children.iter().try_for_each(|child| write_field(f, child, indent + 1))
+        }
+        write_field(f, self, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::signers::Signer;
+    use ethers::utils::keccak256;
+    use serde_json::json;
+
+    // The example from EIP-712 itself
+    fn mail() -> Value {
+        json!({
+            "types": {
+                "EIP712Domain": [
+                    { "name": "name", "type": "string" },
+                    { "name": "version", "type": "string" },
+                    { "name": "chainId", "type": "uint256" },
+                    { "name": "verifyingContract", "type": "address" }
+                ],
+                "Person": [
+                    { "name": "name", "type": "string" },
+                    { "name": "wallet", "type": "address" }
+                ],
+                "Mail": [
+                    { "name": "from", "type": "Person" },
+                    { "name": "to", "type": "Person" },
+                    { "name": "contents", "type": "string" }
+                ]
+            },
+            "primaryType": "Mail",
+            "domain": {
+                "name": "Ether Mail",
+                "version": "1",
+                "chainId": 1,
+                "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+            },
+            "message": {
+                "from": { "name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826" },
+                "to": { "name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB" },
+                "contents": "Hello, Bob!"
+            }
+        })
+    }
+
+    #[test]
+    fn test_eip712_reference_vector() {
+        let typed_data = parse_typed_data(&mail()).unwrap();
+        let hashes = hash_typed_data(&typed_data).unwrap();
+        assert_eq!(
+            format!("{:?}", hashes.domain_separator),
+            "0xf2cee375fa42b42143804025fc449deafd50cc031ca257e0b194a650a912090f"
+        );
+        assert_eq!(
+            format!("{:?}", hashes.struct_hash),
+            "0xc52c0ee5d84264471806290a3f2c4cecfc5490626bf912d01f240d7a274b371e"
+        );
+        assert_eq!(
+            format!("{:?}", hashes.digest),
+            "0xbe609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2"
+        );
+
+        // The spec signs with keccak256("cow") as the private key
+        let wallet = LocalWallet::from_bytes(&keccak256("cow")).unwrap();
+        assert_eq!(address_string(wallet.address()), "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826");
+        let signature = sign_typed_data(&wallet, &typed_data).unwrap();
+        assert_eq!(
+            format!("0x{}", signature),
+            "0x4355c47d63924e8a72e509b65029052eb6c299d53a04e167c5775fd466751c9d\
+             07299936d304c153f6443dfa05f40ff007d72911b6f72307f996231605b915621c"
+        );
+
+        // Dapps usually send the payload JSON-encoded as a string
+        let stringified = parse_typed_data(&Value::String(mail().to_string())).unwrap();
+        assert_eq!(hash_typed_data(&stringified).unwrap().digest, hashes.digest);
+    }
+
+    #[test]
+    fn test_field_tree() {
+        let mut payload = mail();
+        // v4 arrays of structs
+        payload["types"]["Mail"][1] = json!({ "name": "to", "type": "Person[]" });
+        payload["message"]["to"] = json!([
+            { "name": "Bob", "wallet": "0xbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb" },
+            { "name": "Alice", "wallet": "0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa" }
+        ]);
+        let typed_data = parse_typed_data(&payload).unwrap();
+        hash_typed_data(&typed_data).unwrap();
+
+        let tree = field_tree(&typed_data).unwrap();
+        assert_eq!(tree[0].children[2].value.as_deref(), Some("1"));
+        let rendered = format!("{}{}", tree[0], tree[1]);
+        assert!(rendered.contains("  verifyingContract: 0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"));
+        assert!(rendered.contains("message (Mail)\n  from (Person)\n    name: Cow\n"));
+        assert!(rendered.contains("  to (Person[])\n    [0] (Person)\n      name: Bob\n"));
+        // Addresses are shown checksummed
+        assert!(rendered.contains("wallet: 0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"));
+
+        payload["message"]["to"] = json!("not an array");
+        assert!(field_tree(&parse_typed_data(&payload).unwrap()).is_err());
+        assert!(parse_typed_data(&json!({ "primaryType": "Mail" })).is_err());
+    }
+}
@@ -1,4 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+/// Where an account's key came from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyType {
+    /// A bare random key; keystores from before the registry are assumed to be these
+    #[default]
+    Random,
+    /// Derived from a BIP-39 recovery phrase
+    Mnemonic,
+    /// Imported from another wallet (keystore v3 JSON)
+    Imported,
+    /// Address only, no key; can be watched but not used to sign
+    WatchOnly,
+}
+
+impl std::fmt::Display for KeyType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            KeyType::Random => "random",
+            KeyType::Mnemonic => "mnemonic",
+            KeyType::Imported => "imported",
+            KeyType::WatchOnly => "watch-only",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// One account in the keystore registry.
+///
+/// Accounts derived from the same seed share a keystore `file`; the derivation path
+/// and index say where in the seed each one lives.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WalletMeta {
     pub address: String,
     pub created_at: String,
+    /// Keystore file name (relative to the keystore directory) holding the secret;
+    /// `None` for watch-only accounts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub key_type: KeyType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub derivation_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+    /// Hidden accounts stay in the registry but aren't listed by default
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hidden: bool,
+    /// Last successful unlock
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<String>,
+}
+
+/// Plaintext stored inside an encrypted `.dat` keystore.
+///
+/// Wallets created from a recovery phrase keep the phrase, the optional BIP-39
+/// passphrase and the derivation path so the same seed can be restored anywhere;
+/// imported keys only have `private_key`.
+#[derive(Serialize, Deserialize)]
+pub struct KeystoreSecret {
+    pub address: String,
+    pub private_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mnemonic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bip39_passphrase: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub derivation_path: Option<String>,
 }
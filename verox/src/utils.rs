@@ -1,5 +1,148 @@
-pub fn get_data_dir() -> String {
-    let path = "./keystore";
-    println!(" Data directory: {}", path);
-    path.to_string()
+//! Per-user data directory shared by the CLI and the native host
+//!
+//! Chrome starts the native host with its own working directory, so nothing may be
+//! resolved relative to the CWD. Everything Verox stores lives under one directory:
+//! `$VEROX_HOME` when set (the CLI's `--home` sets it), otherwise the platform data
+//! directory — `$XDG_DATA_HOME/verox` (`~/.local/share/verox`) on Linux,
+//! `~/Library/Application Support/verox` on macOS and `%APPDATA%\verox` on Windows.
+//!
+//! The CLI and the native host can run at the same time; state they both
+//! read-modify-write is changed under [`lock_dir`].
+
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Result, VeroxError};
+
+/// Environment variable overriding the data directory
+pub const HOME_ENV: &str = "VEROX_HOME";
+
+const APP_DIR: &str = "verox";
+
+/// Lock file taken by [`lock_dir`]
+pub const LOCK_FILE: &str = ".lock";
+
+/// An exclusive lock on a data directory, released when dropped
+pub struct DirLock {
+    _file: fs::File,
+}
+
+/// Block until this process holds the exclusive lock on `dir`. The lock is an
+/// advisory `flock`/`LockFileEx` on [`LOCK_FILE`], so it also excludes other
+/// threads of this process that take it, and it is released if the holder dies.
+pub fn lock_dir(dir: &Path) -> Result<DirLock> {
+    create_private_dir(dir)?;
+    let file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(LOCK_FILE))?;
+    #[cfg(not(target_arch = "wasm32"))]
+    fs2::FileExt::lock_exclusive(&file)?;
+    Ok(DirLock { _file: file })
+}
+
+/// Replace `path` with `contents` in one step. They are written to a temp file next to
+/// it, named for this process and call so concurrent writers never share one, which is
+/// then renamed over `path` (or removed if that fails).
+pub fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let tmp = path.with_file_name(format!("{}.{}-{:016x}.tmp", name, std::process::id(), rand::random::<u64>()));
+    fs::write(&tmp, contents)?;
+    if let Err(e) = fs::rename(&tmp, path) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// The data directory, without creating it
+pub fn get_data_dir() -> Result<PathBuf> {
+    data_dir_from(std::env::var_os(HOME_ENV))
+}
+
+/// The data directory, created with owner-only permissions if it doesn't exist
+pub fn ensure_data_dir() -> Result<PathBuf> {
+    let dir = get_data_dir()?;
+    create_private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Create `dir` (and missing parents) readable by the owner only.
+/// An existing directory is tightened to 0700 as well.
+pub fn create_private_dir(dir: &Path) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+        fs::DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    #[cfg(not(unix))]
+    fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+fn data_dir_from(home: Option<OsString>) -> Result<PathBuf> {
+    if let Some(home) = home.filter(|home| !home.is_empty()) {
+        return Ok(PathBuf::from(home));
+    }
+    dirs::data_dir()
+        .map(|dir| dir.join(APP_DIR))
+        .ok_or_else(|| VeroxError::InvalidArgument(format!("cannot find a data directory, set {}", HOME_ENV)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_dir_resolution() {
+        assert_eq!(data_dir_from(Some("/tmp/verox-home".into())).unwrap(), PathBuf::from("/tmp/verox-home"));
+
+        // An empty override falls back to the platform directory
+        if let Some(platform_dir) = dirs::data_dir() {
+            assert_eq!(data_dir_from(Some("".into())).unwrap(), platform_dir.join(APP_DIR));
+            assert_eq!(data_dir_from(None).unwrap(), platform_dir.join(APP_DIR));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_private_dir_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("home").join("keystore");
+        create_private_dir(&dir).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        create_private_dir(&dir).unwrap();
+        assert_eq!(fs::metadata(&dir).unwrap().permissions().mode() & 0o777, 0o700);
+    }
+
+    #[test]
+    fn test_dir_lock_is_exclusive() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let dir = tempfile::tempdir().unwrap();
+        let released = Arc::new(AtomicBool::new(false));
+        let lock = lock_dir(dir.path()).unwrap();
+
+        let waiter = {
+            let dir = dir.path().to_path_buf();
+            let released = released.clone();
+            std::thread::spawn(move || {
+                let _lock = lock_dir(&dir).unwrap();
+                released.load(Ordering::SeqCst)
+            })
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        released.store(true, Ordering::SeqCst);
+        drop(lock);
+        // The second lock was only granted after the first was dropped
+        assert!(waiter.join().unwrap());
+    }
 }
@@ -1,103 +1,902 @@
 use ethers::signers::{LocalWallet, Signer};
-use ethers::core::k256::SecretKey;
-use rand::thread_rng;
-use std::fs;
+use ethers::types::Address;
+use ethers::utils::to_checksum;
+use std::fs::{self, File};
 use std::io::{self, Write};
-use std::path::Path;
-use hex;
+use std::path::{Path, PathBuf};
 use rpassword::read_password;
-use serde_json::json;
 
-use crate::crypto::{encrypt_keystore, decrypt_keystore};
+use crate::crypto::{encrypt_keystore, decrypt_keystore, Argon2Params, KdfProfile};
+use crate::error::{Result, VeroxError};
+use crate::agent::{self, UnlockedAccount};
+use crate::events;
+use crate::mnemonic::{self, DerivationScheme, DEFAULT_DERIVATION_PATH};
+use crate::registry::{self, ConsistencyReport, Registry};
+use crate::types::{KeyType, KeystoreSecret, WalletMeta};
+use crate::utils;
+use crate::web3_keystore;
 
-/// Generate and store a new Ethereum wallet (encrypted with passphrase)
-pub fn init_wallet() {
-    println!("Generating a new Ethereum wallet...");
+/// Subdirectory of the data directory holding the `.dat` keystores and their index
+const KEYSTORE_DIR: &str = "keystore";
+
+/// Calibrated KDF parameters saved by `verox calibrate-kdf --save`
+const KDF_CONFIG_FILE: &str = "kdf.json";
+
+/// How many times the CLI asks again after a wrong passphrase
+const UNLOCK_ATTEMPTS: usize = 3;
 
-    let mut rng = thread_rng();
-    let secret = SecretKey::random(&mut rng);
-    let wallet = LocalWallet::from(secret);
+/// Generate a BIP-39 mnemonic and store the wallet derived from it (encrypted with passphrase)
+pub fn init_wallet(
+    kdf_params: Argon2Params,
+    word_count: usize,
+    bip39_passphrase: Option<&str>,
+) -> Result<PathBuf> {
+    println!("Generating a new Ethereum wallet...");
 
-    let address = wallet.address();
-    let private_key_hex = format!("0x{}", hex::encode(wallet.signer().to_bytes()));
+    let phrase = mnemonic::generate_mnemonic(word_count)?;
+    let wallet = mnemonic::wallet_from_mnemonic(&phrase, bip39_passphrase, DEFAULT_DERIVATION_PATH)?;
 
     println!("New wallet created!");
-    println!("Address: {}", address);
-    println!("Private Key: {}", private_key_hex);
+    println!("Address: {}", address_string(wallet.address()));
+    println!("Recovery phrase ({} words, write it down and keep it offline):", word_count);
+    println!("{}", phrase);
 
     print!("Enter passphrase to encrypt wallet: ");
-    io::stdout().flush().unwrap();
+    io::stdout().flush()?;
     let mut passphrase = String::new();
-    io::stdin().read_line(&mut passphrase).unwrap();
+    io::stdin().read_line(&mut passphrase)?;
     let passphrase = passphrase.trim();
 
-    let keystore = json!({
-        "address": address.to_string(),
-        "private_key": private_key_hex,
-    });
-
-    let json = serde_json::to_string_pretty(&keystore).unwrap();
-    let encrypted_bytes = encrypt_keystore(json.as_bytes(), passphrase.as_bytes());
+    println!("Deriving key with {}...", kdf_params);
+    let (_, filename) = restore_wallet(&phrase, bip39_passphrase, passphrase, kdf_params)?;
+    println!("Keystore saved to: {}", filename.display());
+    Ok(filename)
+}
 
-    fs::create_dir_all("keystore").unwrap();
-    // filename contains the address so multiple wallets are supported
-    let filename = format!("keystore/{}.dat", address);
-    fs::write(&filename, &encrypted_bytes).expect("Failed to write keystore file");
+/// Derive the wallet for a recovery phrase and store it together with the phrase
+pub fn restore_wallet(
+    phrase: &str,
+    bip39_passphrase: Option<&str>,
+    passphrase: &str,
+    kdf_params: Argon2Params,
+) -> Result<(Address, PathBuf)> {
+    let phrase = mnemonic::normalize_mnemonic(phrase);
+    let wallet = mnemonic::wallet_from_mnemonic(&phrase, bip39_passphrase, DEFAULT_DERIVATION_PATH)?;
 
-    println!("Keystore saved to: {}", filename);
+    let secret = KeystoreSecret {
+        address: address_string(wallet.address()),
+        private_key: format!("0x{}", hex::encode(wallet.signer().to_bytes())),
+        mnemonic: Some(phrase),
+        bip39_passphrase: bip39_passphrase.filter(|p| !p.is_empty()).map(str::to_string),
+        derivation_path: Some(DEFAULT_DERIVATION_PATH.to_string()),
+    };
+    let filename = store_secret(&secret, passphrase, kdf_params)?;
+    Ok((wallet.address(), filename))
 }
 
-/// Unlock and load the wallet (finds first .dat in keystore/)
-pub fn unlock_wallet() {
-    let keystore_dir = Path::new("keystore");
+/// Unlock and load a wallet.
+///
+/// `file` picks a keystore file directly; `account` (an address or label) picks an
+/// account from the registry, or an account inside `file` when both are given. With
+/// neither, a lone account is used as is and several are offered in a picker.
+/// A wrong passphrase is asked for again up to `UNLOCK_ATTEMPTS` times. A running
+/// agent signs with keys it holds without a prompt, and takes the ones unlocked here.
+pub fn unlock_wallet(file: Option<&Path>, account: Option<&str>) -> Result<UnlockedAccount> {
+    let (wallet_file, address) = match file {
+        Some(file) => (file.to_path_buf(), account.map(str::to_string)),
+        None => {
+            let candidates = find_accounts(account)?;
+            let meta = match candidates.len() {
+                1 => candidates.into_iter().next().unwrap(),
+                _ => pick_account(candidates)?,
+            };
+            (account_file(&meta)?, Some(meta.address))
+        }
+    };
 
-    if !keystore_dir.exists() {
-        eprintln!("No keystore directory found. Run `verox init` first.");
-        return;
+    let dir = utils::get_data_dir()?;
+    if let Some(address) = address.as_deref().and_then(|address| address.parse().ok()) {
+        if let Ok(account) = agent::account(&dir, address) {
+            println!("Unlocked by the agent: {}", address_string(address));
+            return Ok(account);
+        }
     }
 
-    // Find the first `.dat` file in the keystore folder
-    let wallet_file = match fs::read_dir(keystore_dir)
-        .expect("Failed to read keystore directory")
-        .find_map(|entry| {
-            let entry = entry.ok()?;
-            let path = entry.path();
-            if path.extension()?.to_str()? == "dat" {
-                Some(path)
-            } else {
-                None
+    let mut attempt = 1;
+    let wallet = loop {
+        let passphrase = prompt_passphrase("Enter passphrase: ")?;
+        match load_account(&wallet_file, &passphrase, address.as_deref()) {
+            Ok(wallet) => break wallet,
+            Err(VeroxError::WrongPassphrase) if attempt < UNLOCK_ATTEMPTS => {
+                eprintln!("Wrong passphrase, try again.");
+                attempt += 1;
             }
-        })
-    {
-        Some(path) => path,
-        None => {
-            eprintln!("No wallet file found in keystore directory.");
-            return;
+            Err(e) => return Err(e),
         }
     };
 
-    print!("Enter passphrase: ");
-    io::stdout().flush().unwrap();
-    let passphrase = read_password().expect("Failed to read passphrase");
+    println!("Wallet unlocked successfully!");
+    println!("Address: {}", address_string(wallet.address()));
+    if let Err(e) = mark_used(&wallet_file, wallet.address()) {
+        eprintln!("Could not update keystore index: {}", e);
+    }
+    // Connected pages see the accounts again
+    if let Err(e) = events::set_locked(&dir, false) {
+        eprintln!("Could not unlock for connected pages: {}", e);
+    }
+    match agent::add(&dir, &wallet) {
+        Ok(true) => println!("Added to the agent"),
+        Ok(false) => {}
+        Err(e) => eprintln!("Could not add the key to the agent: {}", e),
+    }
+    Ok(wallet.into())
+}
 
-    let encrypted_data = fs::read(&wallet_file).expect("Failed to read wallet file");
-    let decrypted_bytes = decrypt_keystore(&encrypted_data, passphrase.as_bytes());
+/// Accounts matching an address or label (case-insensitive), hidden ones included.
+/// Without a selector every visible account is a candidate.
+pub fn find_accounts(selector: Option<&str>) -> Result<Vec<WalletMeta>> {
+    let accounts = match selector {
+        Some(selector) => list_accounts(true)?
+            .into_iter()
+            .filter(|meta| {
+                meta.address.eq_ignore_ascii_case(selector)
+                    || meta.label.as_deref().is_some_and(|label| label.eq_ignore_ascii_case(selector))
+            })
+            .collect(),
+        None => list_accounts(false)?,
+    };
 
-    let keystore_json = String::from_utf8(decrypted_bytes)
-        .expect("Invalid UTF-8 in decrypted wallet");
+    if accounts.is_empty() {
+        return Err(VeroxError::WalletNotFound(match selector {
+            Some(selector) => format!("no account matches '{}'", selector),
+            None => "no wallet in keystore directory, run `verox create-wallet` first".to_string(),
+        }));
+    }
+    Ok(accounts)
+}
 
-    // Parse JSON and load private key
-    let parsed: serde_json::Value =
-        serde_json::from_str(&keystore_json).expect("Invalid keystore JSON format");
+/// Candidates for an operation on a whole keystore file (re-encrypting it, deriving
+/// from its seed). A selector narrows them down as in [`find_accounts`]; without one
+/// each file counts once, by its primary account, and watch-only accounts are left out.
+pub fn find_keystores(selector: Option<&str>) -> Result<Vec<WalletMeta>> {
+    let accounts = find_accounts(selector)?;
+    if selector.is_some() {
+        return Ok(accounts);
+    }
 
-    let private_key_hex = parsed["private_key"]
-        .as_str()
-        .expect("Private key missing in keystore");
+    let mut files = std::collections::HashSet::new();
+    let keystores: Vec<WalletMeta> = accounts
+        .into_iter()
+        .filter(|meta| meta.file.as_ref().is_some_and(|file| files.insert(file.clone())))
+        .collect();
+    if keystores.is_empty() {
+        return Err(VeroxError::WalletNotFound("no keystore file, only watch-only accounts".to_string()));
+    }
+    Ok(keystores)
+}
+
+/// Ask on the terminal which of several accounts to use
+pub fn pick_account(accounts: Vec<WalletMeta>) -> Result<WalletMeta> {
+    println!("Several wallets found:");
+    for (i, meta) in accounts.iter().enumerate() {
+        println!("  {}) {}  {}", i + 1, meta.address, meta.label.as_deref().unwrap_or(""));
+    }
+    print!("Choose a wallet [1-{}]: ", accounts.len());
+    io::stdout().flush()?;
+
+    let mut choice = String::new();
+    io::stdin().read_line(&mut choice)?;
+    choice
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .filter(|n| (1..=accounts.len()).contains(n))
+        .and_then(|n| accounts.into_iter().nth(n - 1))
+        .ok_or_else(|| VeroxError::InvalidArgument(format!("invalid choice '{}'", choice.trim())))
+}
+
+/// Decrypt `wallet_file` and return the key for `address`.
+///
+/// `None` or the keystore's own address gives the stored key; any other address must
+/// be a registered account derived from the same seed.
+pub fn load_account(wallet_file: &Path, passphrase: &str, address: Option<&str>) -> Result<LocalWallet> {
+    let secret = load_secret(wallet_file, passphrase)?;
+    let address = match address {
+        Some(address) if !address.eq_ignore_ascii_case(&secret.address) => address,
+        _ => return parse_private_key(&secret.private_key),
+    };
+
+    let registry = Registry::load(registry_dir(wallet_file))?;
+    let meta = registry
+        .find(address)
+        .filter(|meta| meta.file.as_deref() == wallet_file.file_name().and_then(|name| name.to_str()))
+        .ok_or_else(|| {
+            VeroxError::WalletNotFound(format!("no account {} in {}", address, wallet_file.display()))
+        })?;
+    let (phrase, derivation_path) = match (&secret.mnemonic, &meta.derivation_path) {
+        (Some(phrase), Some(path)) => (phrase, path),
+        _ => return Err(VeroxError::WalletNotFound(format!("no account {} in {}", address, wallet_file.display()))),
+    };
+
+    let wallet = mnemonic::wallet_from_mnemonic(phrase, secret.bip39_passphrase.as_deref(), derivation_path)?;
+    if !address_string(wallet.address()).eq_ignore_ascii_case(address) {
+        return Err(VeroxError::CorruptKeystore(format!(
+            "{} derives to {}, not {}",
+            derivation_path,
+            address_string(wallet.address()),
+            address
+        )));
+    }
+    Ok(wallet)
+}
+
+/// Encrypt an imported private key with the passphrase and write it to `keystore/<address>.dat`
+pub fn store_wallet(wallet: &LocalWallet, passphrase: &str, kdf_params: Argon2Params) -> Result<PathBuf> {
+    let secret = KeystoreSecret {
+        address: address_string(wallet.address()),
+        private_key: format!("0x{}", hex::encode(wallet.signer().to_bytes())),
+        mnemonic: None,
+        bip39_passphrase: None,
+        derivation_path: None,
+    };
+    store_secret(&secret, passphrase, kdf_params)
+}
+
+/// Encrypt a keystore secret, write it to `keystore/<address>.dat` and register its account
+pub fn store_secret(secret: &KeystoreSecret, passphrase: &str, kdf_params: Argon2Params) -> Result<PathBuf> {
+    write_secret(&keystore_dir()?, secret, passphrase, kdf_params)
+}
 
-    let wallet: LocalWallet = private_key_hex
+fn write_secret(dir: &Path, secret: &KeystoreSecret, passphrase: &str, kdf_params: Argon2Params) -> Result<PathBuf> {
+    let json = serde_json::to_string_pretty(secret)?;
+    let encrypted_bytes = encrypt_keystore(json.as_bytes(), passphrase.as_bytes(), kdf_params)?;
+
+    utils::create_private_dir(dir)?;
+    // filename contains the address so multiple wallets are supported
+    let file_name = format!("{}.dat", secret.address);
+    let filename = dir.join(&file_name);
+    // Never replace a keystore: an import of the same key would clobber its passphrase
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&filename)
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => {
+                VeroxError::InvalidArgument(format!("wallet {} already exists", secret.address))
+            }
+            _ => e.into(),
+        })?;
+    file.write_all(&encrypted_bytes)?;
+
+    Registry::update(dir, |registry| {
+        if registry.find(&secret.address).is_none() {
+            registry.insert(primary_account_meta(secret, file_name));
+        }
+        Ok(())
+    })?;
+
+    Ok(filename)
+}
+
+fn primary_account_meta(secret: &KeystoreSecret, file: String) -> WalletMeta {
+    WalletMeta {
+        address: secret.address.clone(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+        file: Some(file),
+        label: None,
+        key_type: if secret.mnemonic.is_some() { KeyType::Mnemonic } else { KeyType::Imported },
+        derivation_path: secret.derivation_path.clone(),
+        index: secret.derivation_path.as_ref().map(|_| 0),
+        hidden: false,
+        last_used: None,
+    }
+}
+
+/// Decrypt a keystore file and load its private key
+pub fn load_wallet(wallet_file: &Path, passphrase: &str) -> Result<LocalWallet> {
+    parse_private_key(&load_secret(wallet_file, passphrase)?.private_key)
+}
+
+fn parse_private_key(private_key_hex: &str) -> Result<LocalWallet> {
+    private_key_hex
         .parse()
-        .expect("Failed to parse private key");
+        .map_err(|_| VeroxError::CorruptKeystore("failed to parse private key".to_string()))
+}
 
-    println!("Wallet unlocked successfully!");
-    println!("Address: {}", wallet.address());
+/// Decrypt a keystore file, including the recovery phrase if it has one
+pub fn load_secret(wallet_file: &Path, passphrase: &str) -> Result<KeystoreSecret> {
+    let encrypted_data = read_keystore_file(wallet_file)?;
+    let decrypted_bytes = decrypt_keystore(&encrypted_data, passphrase.as_bytes())?;
+
+    serde_json::from_slice(&decrypted_bytes)
+        .map_err(|e| VeroxError::CorruptKeystore(format!("invalid wallet payload: {}", e)))
+}
+
+/// Derive another account from the seed in `wallet_file` and add it to the registry.
+///
+/// Without an explicit `index` the first index whose path isn't registered yet is used.
+/// Adding an account that exists but is hidden just unhides it.
+pub fn add_account(
+    wallet_file: &Path,
+    passphrase: &str,
+    scheme: &DerivationScheme,
+    index: Option<u32>,
+    label: Option<String>,
+) -> Result<WalletMeta> {
+    let secret = load_secret(wallet_file, passphrase)?;
+    let phrase = secret.mnemonic.as_deref().ok_or_else(|| {
+        VeroxError::InvalidArgument(format!(
+            "{} holds a single imported key, not a recovery phrase",
+            wallet_file.display()
+        ))
+    })?;
+
+    let dir = registry_dir(wallet_file);
+    let file = file_name(wallet_file)?;
+    Registry::update(dir, |registry| {
+        match registry.find_mut(&secret.address) {
+            // Entries migrated from a bare `.dat` don't know their path until decrypted
+            Some(primary) if primary.derivation_path.is_none() => {
+                primary.key_type = KeyType::Mnemonic;
+                primary.derivation_path = secret.derivation_path.clone();
+                primary.index = secret.derivation_path.as_ref().map(|_| 0);
+            }
+            Some(_) => {}
+            None => registry.insert(primary_account_meta(&secret, file.clone())),
+        }
+
+        let index = match index {
+            Some(index) => index,
+            None if scheme.is_indexed() => (0..=u32::MAX)
+                .find(|i| {
+                    let path = scheme.path(*i);
+                    registry.accounts_in(&file).all(|meta| meta.derivation_path.as_deref() != Some(path.as_str()))
+                })
+                .unwrap_or(0),
+            None => 0,
+        };
+        let derivation_path = scheme.path(index);
+        let wallet = mnemonic::wallet_from_mnemonic(phrase, secret.bip39_passphrase.as_deref(), &derivation_path)?;
+        let address = address_string(wallet.address());
+
+        if let Some(existing) = registry.find_mut(&address) {
+            if !existing.hidden {
+                return Err(VeroxError::InvalidArgument(format!("account {} already exists", address)));
+            }
+            existing.hidden = false;
+            if label.is_some() {
+                existing.label = label;
+            }
+            return Ok(existing.clone());
+        }
+
+        let meta = WalletMeta {
+            address,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            file: Some(file.clone()),
+            label,
+            key_type: KeyType::Mnemonic,
+            derivation_path: Some(derivation_path),
+            index: scheme.is_indexed().then_some(index),
+            hidden: false,
+            last_used: None,
+        };
+        registry.insert(meta.clone());
+        Ok(meta)
+    })
+}
+
+/// All registered accounts, hidden ones only if `include_hidden`
+pub fn list_accounts(include_hidden: bool) -> Result<Vec<WalletMeta>> {
+    let registry = load_registry(&keystore_dir()?)?;
+    Ok(registry
+        .accounts
+        .into_iter()
+        .filter(|meta| include_hidden || !meta.hidden)
+        .collect())
+}
+
+/// Register an address without a key, to follow it without being able to sign
+pub fn add_watch_only(address: &str, label: Option<String>) -> Result<WalletMeta> {
+    let address: Address = address
+        .parse()
+        .map_err(|_| VeroxError::InvalidArgument(format!("invalid address '{}'", address)))?;
+    let address = address_string(address);
+
+    let dir = keystore_dir()?;
+    Registry::update(&dir, |registry| {
+        index_unindexed_files(&dir, registry)?;
+        if registry.find(&address).is_some() {
+            return Err(VeroxError::InvalidArgument(format!("account {} already exists", address)));
+        }
+        let meta = WalletMeta {
+            address,
+            created_at: chrono::Utc::now().to_rfc3339(),
+            file: None,
+            label,
+            key_type: KeyType::WatchOnly,
+            derivation_path: None,
+            index: None,
+            hidden: false,
+            last_used: None,
+        };
+        registry.insert(meta.clone());
+        Ok(meta)
+    })
+}
+
+/// Set or clear an account's label; the account is picked by address or current label
+pub fn set_account_label(address: &str, label: Option<String>) -> Result<WalletMeta> {
+    update_account(address, |meta| meta.label = label)
+}
+
+/// Hide an account from listings, or show it again
+pub fn set_account_hidden(address: &str, hidden: bool) -> Result<WalletMeta> {
+    update_account(address, |meta| meta.hidden = hidden)
+}
+
+fn update_account(selector: &str, update: impl FnOnce(&mut WalletMeta)) -> Result<WalletMeta> {
+    let dir = keystore_dir()?;
+    Registry::update(&dir, |registry| {
+        index_unindexed_files(&dir, registry)?;
+        let address = registry.resolve(selector)?;
+        let meta = registry
+            .find_mut(&address)
+            .ok_or_else(|| VeroxError::WalletNotFound(format!("no account {}", address)))?;
+        update(meta);
+        Ok(meta.clone())
+    })
+}
+
+/// Remove an account and return the index entries that went with it.
+///
+/// A derived or watch-only account only loses its entry. Deleting the account a
+/// keystore file is named after deletes the file, and with it every account derived
+/// from that seed; that needs the file's `passphrase`, checked before anything is removed.
+pub fn delete_account(selector: &str, passphrase: Option<&str>) -> Result<Vec<WalletMeta>> {
+    delete_account_in(&keystore_dir()?, selector, passphrase)
+}
+
+fn delete_account_in(dir: &Path, selector: &str, passphrase: Option<&str>) -> Result<Vec<WalletMeta>> {
+    Registry::update(dir, |registry| {
+        index_unindexed_files(dir, registry)?;
+        let address = registry.resolve(selector)?;
+        let meta = registry
+            .find(&address)
+            .cloned()
+            .ok_or_else(|| VeroxError::WalletNotFound(format!("no account {}", address)))?;
+
+        match meta.file.as_deref().filter(|_| owns_keystore(&meta)) {
+            Some(file) => {
+                let wallet_file = dir.join(file);
+                if wallet_file.exists() {
+                    let passphrase = passphrase.ok_or_else(|| {
+                        VeroxError::InvalidArgument(format!("deleting {} needs its passphrase", file))
+                    })?;
+                    load_secret(&wallet_file, passphrase)?;
+                    fs::remove_file(&wallet_file)?;
+                }
+                let removed: Vec<WalletMeta> = registry.accounts_in(file).cloned().collect();
+                registry.accounts.retain(|meta| meta.file.as_deref() != Some(file));
+                Ok(removed)
+            }
+            None => Ok(registry.remove(&address).into_iter().collect()),
+        }
+    })
+}
+
+/// Whether the account's keystore file was stored under its address (`<address>.dat`),
+/// i.e. deleting the account deletes the file
+pub fn owns_keystore(meta: &WalletMeta) -> bool {
+    meta.file
+        .as_deref()
+        .and_then(|file| file.strip_suffix(".dat"))
+        .is_some_and(|stem| stem.eq_ignore_ascii_case(&meta.address))
+}
+
+/// Record a successful unlock in the index
+pub fn mark_used(wallet_file: &Path, address: Address) -> Result<()> {
+    Registry::update(registry_dir(wallet_file), |registry| {
+        if let Some(meta) = registry.find_mut(&address_string(address)) {
+            meta.last_used = Some(chrono::Utc::now().to_rfc3339());
+        }
+        Ok(())
+    })
+}
+
+/// Compare the index with the keystore files. With `fix`, entries whose file is gone
+/// are dropped and unindexed files are added, then the report of what was found is returned.
+pub fn check_keystore(fix: bool) -> Result<ConsistencyReport> {
+    let dir = keystore_dir()?;
+    if !fix {
+        return Registry::load(&dir)?.check(&dir);
+    }
+    Registry::update(&dir, |registry| {
+        let report = registry.check(&dir)?;
+        registry
+            .accounts
+            .retain(|meta| !report.missing_files.iter().any(|missing| missing.address == meta.address));
+        index_unindexed_files(&dir, registry)?;
+        Ok(report)
+    })
+}
+
+/// Keystore file holding an account's key
+pub fn account_file(meta: &WalletMeta) -> Result<PathBuf> {
+    match &meta.file {
+        Some(file) => Ok(keystore_dir()?.join(file)),
+        None => Err(VeroxError::InvalidArgument(format!(
+            "{} is watch-only and has no key to unlock",
+            meta.address
+        ))),
+    }
+}
+
+/// The registry of a keystore directory, with `.dat` files missing from it added
+/// as their primary account
+fn load_registry(dir: &Path) -> Result<Registry> {
+    let mut registry = Registry::load(dir)?;
+    index_unindexed_files(dir, &mut registry)?;
+    Ok(registry)
+}
+
+// Files written before the registry existed came from the random-key `create-wallet`
+fn index_unindexed_files(dir: &Path, registry: &mut Registry) -> Result<()> {
+    for file in registry::keystore_files(dir)? {
+        if registry.accounts_in(&file).next().is_some() {
+            continue;
+        }
+        let path = dir.join(&file);
+        let address = path.file_stem().and_then(|stem| stem.to_str()).unwrap_or_default().to_string();
+        let created_at = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .map(|modified| chrono::DateTime::<chrono::Utc>::from(modified).to_rfc3339())
+            .unwrap_or_default();
+        registry.insert(WalletMeta {
+            address,
+            created_at,
+            file: Some(file),
+            label: None,
+            key_type: KeyType::Random,
+            derivation_path: None,
+            index: None,
+            hidden: false,
+            last_used: None,
+        });
+    }
+    Ok(())
+}
+
+fn registry_dir(wallet_file: &Path) -> &Path {
+    wallet_file
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+}
+
+fn file_name(wallet_file: &Path) -> Result<String> {
+    wallet_file
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(str::to_string)
+        .ok_or_else(|| VeroxError::InvalidArgument(format!("invalid keystore path {}", wallet_file.display())))
+}
+
+/// Re-encrypt a wallet under a new passphrase with a fresh salt, nonce and `kdf_params`.
+///
+/// The new keystore is written to `<file>.tmp` and verified by decrypting it, then
+/// atomically renamed over the original. A `<file>.bak` copy of the original is kept
+/// until the replaced file has been verified too, and restored if that check fails.
+pub fn change_passphrase(
+    wallet_file: &Path,
+    old_passphrase: &str,
+    new_passphrase: &str,
+    kdf_params: Argon2Params,
+) -> Result<()> {
+    let encrypted_data = read_keystore_file(wallet_file)?;
+    let plaintext = decrypt_keystore(&encrypted_data, old_passphrase.as_bytes())?;
+    let reencrypted = encrypt_keystore(&plaintext, new_passphrase.as_bytes(), kdf_params)?;
+
+    let tmp_file = wallet_file.with_extension("dat.tmp");
+    let backup_file = wallet_file.with_extension("dat.bak");
+
+    write_synced(&tmp_file, &reencrypted)?;
+    if let Err(e) = verify_keystore(&tmp_file, new_passphrase, &plaintext) {
+        let _ = fs::remove_file(&tmp_file);
+        return Err(e);
+    }
+
+    write_synced(&backup_file, &encrypted_data)?;
+    fs::rename(&tmp_file, wallet_file)?;
+    sync_parent_dir(wallet_file)?;
+
+    match verify_keystore(wallet_file, new_passphrase, &plaintext) {
+        Ok(()) => {
+            fs::remove_file(&backup_file)?;
+            Ok(())
+        }
+        Err(e) => {
+            fs::rename(&backup_file, wallet_file)?;
+            Err(e)
+        }
+    }
+}
+
+/// Check that a keystore decrypts to the expected plaintext
+fn verify_keystore(path: &Path, passphrase: &str, expected: &[u8]) -> Result<()> {
+    let decrypted = decrypt_keystore(&fs::read(path)?, passphrase.as_bytes())?;
+    if decrypted != expected {
+        return Err(VeroxError::CorruptKeystore(format!(
+            "{} did not round-trip after re-encryption",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+fn read_keystore_file(wallet_file: &Path) -> Result<Vec<u8>> {
+    match fs::read(wallet_file) {
+        Ok(data) => Ok(data),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Err(VeroxError::WalletNotFound(wallet_file.display().to_string()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Write and fsync so a crash can't leave a half-written keystore behind
+fn write_synced(path: &Path, bytes: &[u8]) -> Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Persist a rename by syncing the directory entry (no-op where unsupported)
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        File::open(parent)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Import a Web3 Secret Storage (keystore v3) JSON document into the Verox keystore
+pub fn import_v3_keystore(
+    v3_json: &str,
+    v3_password: &str,
+    passphrase: &str,
+    kdf_params: Argon2Params,
+) -> Result<(Address, PathBuf)> {
+    let private_key = web3_keystore::decrypt(v3_json, v3_password.as_bytes())?;
+    let wallet = LocalWallet::from_bytes(&private_key)
+        .map_err(|e| VeroxError::CorruptKeystore(format!("invalid private key: {}", e)))?;
+
+    let filename = store_wallet(&wallet, passphrase, kdf_params)?;
+    Ok((wallet.address(), filename))
+}
+
+/// Export a Verox wallet as a Web3 Secret Storage (keystore v3) JSON document
+pub fn export_v3_keystore(
+    wallet_file: &Path,
+    account: Option<&str>,
+    passphrase: &str,
+    v3_password: &str,
+) -> Result<(Address, String)> {
+    let wallet = load_account(wallet_file, passphrase, account)?;
+    let address = address_string(wallet.address());
+
+    let v3_json = web3_keystore::encrypt(&wallet.signer().to_bytes(), v3_password.as_bytes(), &address)?;
+    Ok((wallet.address(), v3_json))
+}
+
+/// Directory holding the `.dat` keystores and their registry
+pub fn keystore_dir() -> Result<PathBuf> {
+    Ok(utils::get_data_dir()?.join(KEYSTORE_DIR))
+}
+
+/// KDF parameters for new keystores when none are given explicitly.
+///
+/// `VEROX_KDF_PROFILE` (interactive, moderate or paranoid) wins, so CI can pin a cheap
+/// profile; otherwise parameters saved by `calibrate-kdf --save` are used, falling back
+/// to the interactive profile.
+pub fn default_kdf_params() -> Result<Argon2Params> {
+    if let Ok(profile) = std::env::var("VEROX_KDF_PROFILE") {
+        return Ok(profile.parse::<KdfProfile>()?.params());
+    }
+
+    let config_path = utils::get_data_dir()?.join(KDF_CONFIG_FILE);
+    match fs::read_to_string(&config_path) {
+        Ok(contents) => {
+            let params: Argon2Params = serde_json::from_str(&contents)?;
+            params.validate()?;
+            Ok(params)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(KdfProfile::Interactive.params()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Persist calibrated KDF parameters as the default for new keystores
+pub fn save_kdf_params(params: Argon2Params) -> Result<PathBuf> {
+    params.validate()?;
+    let config_path = utils::ensure_data_dir()?.join(KDF_CONFIG_FILE);
+    fs::write(&config_path, serde_json::to_string_pretty(&params)?)?;
+    Ok(config_path)
+}
+
+/// geth-style file name for an exported v3 keystore
+pub fn v3_keystore_filename(address: Address) -> String {
+    format!(
+        "UTC--{}--{}",
+        chrono::Utc::now().format("%Y-%m-%dT%H-%M-%S%.9fZ"),
+        hex::encode(address.as_bytes())
+    )
+}
+
+/// Full EIP-55 checksummed address (`Address`'s `Display` abbreviates it)
+pub fn address_string(address: Address) -> String {
+    to_checksum(&address, None)
+}
+
+/// Prompt on stdout and read a passphrase without echoing it
+pub fn prompt_passphrase(prompt: &str) -> Result<String> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    Ok(read_password()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters so the tests don't spend their time in Argon2
+    const TEST_KDF: Argon2Params = Argon2Params { m_cost: 8 * 1024, t_cost: 1, p_cost: 1 };
+
+    #[test]
+    fn test_change_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let wallet_file = dir.path().join("wallet.dat");
+        fs::write(&wallet_file, encrypt_keystore(b"{}", b"old", TEST_KDF).unwrap()).unwrap();
+
+        assert!(matches!(
+            change_passphrase(&wallet_file, "wrong", "new", TEST_KDF),
+            Err(VeroxError::WrongPassphrase)
+        ));
+
+        change_passphrase(&wallet_file, "old", "new", TEST_KDF).unwrap();
+        let encrypted = fs::read(&wallet_file).unwrap();
+        assert_eq!(decrypt_keystore(&encrypted, b"new").unwrap(), b"{}");
+        assert!(matches!(decrypt_keystore(&encrypted, b"old"), Err(VeroxError::WrongPassphrase)));
+
+        // Only the replaced keystore is left behind
+        let leftovers: Vec<_> = fs::read_dir(dir.path()).unwrap().collect();
+        assert_eq!(leftovers.len(), 1);
+    }
+
+    #[test]
+    fn test_secret_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let wallet_file = dir.path().join("wallet.dat");
+        let secret = KeystoreSecret {
+            address: "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266".to_string(),
+            private_key: "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80".to_string(),
+            mnemonic: Some("test test test test test test test test test test test junk".to_string()),
+            bip39_passphrase: None,
+            derivation_path: Some(DEFAULT_DERIVATION_PATH.to_string()),
+        };
+        let json = serde_json::to_vec(&secret).unwrap();
+        fs::write(&wallet_file, encrypt_keystore(&json, b"pass", TEST_KDF).unwrap()).unwrap();
+
+        let loaded = load_secret(&wallet_file, "pass").unwrap();
+        assert_eq!(loaded.mnemonic, secret.mnemonic);
+        assert_eq!(address_string(load_wallet(&wallet_file, "pass").unwrap().address()), secret.address);
+
+        // Keystores written before mnemonics only carry the key
+        let legacy = br#"{"address":"0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266","private_key":"0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"}"#;
+        fs::write(&wallet_file, encrypt_keystore(legacy, b"pass", TEST_KDF).unwrap()).unwrap();
+        assert!(load_secret(&wallet_file, "pass").unwrap().mnemonic.is_none());
+    }
+
+    #[test]
+    fn test_add_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let phrase = "test test test test test test test test test test test junk";
+        let primary = mnemonic::wallet_from_mnemonic(phrase, None, DEFAULT_DERIVATION_PATH).unwrap();
+        let secret = KeystoreSecret {
+            address: address_string(primary.address()),
+            private_key: format!("0x{}", hex::encode(primary.signer().to_bytes())),
+            mnemonic: Some(phrase.to_string()),
+            bip39_passphrase: None,
+            derivation_path: Some(DEFAULT_DERIVATION_PATH.to_string()),
+        };
+        let wallet_file = write_secret(dir.path(), &secret, "pass", TEST_KDF).unwrap();
+
+        let second = add_account(&wallet_file, "pass", &DerivationScheme::Bip44, None, Some("savings".to_string())).unwrap();
+        assert_eq!(second.address, "0x70997970C51812dc3A010C7d01b50e0d17dc79C8");
+        assert_eq!(second.index, Some(1));
+
+        let ledger = add_account(&wallet_file, "pass", &DerivationScheme::LedgerLive, Some(1), None).unwrap();
+        assert_eq!(ledger.derivation_path.as_deref(), Some("m/44'/60'/1'/0/0"));
+
+        // The same path twice is refused, and a wrong passphrase never gets that far
+        assert!(add_account(&wallet_file, "pass", &DerivationScheme::Bip44, Some(1), None).is_err());
+        assert!(matches!(
+            add_account(&wallet_file, "nope", &DerivationScheme::Bip44, None, None),
+            Err(VeroxError::WrongPassphrase)
+        ));
+
+        let registry = Registry::load(dir.path()).unwrap();
+        assert_eq!(registry.accounts.len(), 3);
+        assert!(registry.accounts.iter().all(|meta| meta.file == Some(secret.address.clone() + ".dat")));
+        assert!(registry.accounts.iter().all(|meta| meta.key_type == KeyType::Mnemonic));
+        assert_eq!(registry.find(&secret.address).unwrap().index, Some(0));
+
+        // Any registered account of the seed can be unlocked from the one file
+        let unlocked = load_account(&wallet_file, "pass", Some(&second.address.to_lowercase())).unwrap();
+        assert_eq!(address_string(unlocked.address()), second.address);
+        let unlocked = load_account(&wallet_file, "pass", None).unwrap();
+        assert_eq!(address_string(unlocked.address()), secret.address);
+        assert!(matches!(
+            load_account(&wallet_file, "pass", Some("0x3C44CdDdB6a900fa2b585dd299e03d12FA4293BC")),
+            Err(VeroxError::WalletNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_existing_wallet_is_not_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = KeystoreSecret {
+            address: "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266".to_string(),
+            private_key: "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80".to_string(),
+            mnemonic: None,
+            bip39_passphrase: None,
+            derivation_path: None,
+        };
+        let wallet_file = write_secret(dir.path(), &secret, "pass", TEST_KDF).unwrap();
+        let original = fs::read(&wallet_file).unwrap();
+
+        let err = write_secret(dir.path(), &secret, "other", TEST_KDF).unwrap_err();
+        assert!(err.to_string().contains("already exists"));
+        assert_eq!(fs::read(&wallet_file).unwrap(), original);
+        assert_eq!(Registry::load(dir.path()).unwrap().accounts.len(), 1);
+    }
+
+    #[test]
+    fn test_delete_account() {
+        let dir = tempfile::tempdir().unwrap();
+        let phrase = "test test test test test test test test test test test junk";
+        let primary = mnemonic::wallet_from_mnemonic(phrase, None, DEFAULT_DERIVATION_PATH).unwrap();
+        let secret = KeystoreSecret {
+            address: address_string(primary.address()),
+            private_key: format!("0x{}", hex::encode(primary.signer().to_bytes())),
+            mnemonic: Some(phrase.to_string()),
+            bip39_passphrase: None,
+            derivation_path: Some(DEFAULT_DERIVATION_PATH.to_string()),
+        };
+        let wallet_file = write_secret(dir.path(), &secret, "pass", TEST_KDF).unwrap();
+        let second = add_account(&wallet_file, "pass", &DerivationScheme::Bip44, None, None).unwrap();
+        add_account(&wallet_file, "pass", &DerivationScheme::Bip44, None, None).unwrap();
+
+        // A derived account only loses its index entry
+        let removed = delete_account_in(dir.path(), &second.address, None).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert!(wallet_file.exists());
+
+        // The seed's own account takes the file and the remaining accounts with it
+        assert!(matches!(
+            delete_account_in(dir.path(), &secret.address, None),
+            Err(VeroxError::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            delete_account_in(dir.path(), &secret.address, Some("wrong")),
+            Err(VeroxError::WrongPassphrase)
+        ));
+        assert!(wallet_file.exists());
+        assert_eq!(delete_account_in(dir.path(), &secret.address, Some("pass")).unwrap().len(), 2);
+        assert!(!wallet_file.exists());
+        assert!(Registry::load(dir.path()).unwrap().accounts.is_empty());
+    }
 }
@@ -0,0 +1,432 @@
+//! Web3 Secret Storage (Ethereum keystore v3 JSON)
+//!
+//! This is the format shared by geth, Foundry (`cast wallet`) and MetaMask:
+//! a scrypt or PBKDF2-HMAC-SHA256 derived key, AES-128-CTR encryption of the
+//! raw private key and a Keccak-256 MAC over the ciphertext.
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use ethers::utils::keccak256;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use crate::error::{Result, VeroxError};
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+const CIPHER: &str = "aes-128-ctr";
+const DKLEN: u32 = 32;
+/// Only the first 32 bytes are used; anything longer just costs an allocation
+const MAX_DKLEN: u32 = 64;
+/// Most memory a keystore's scrypt parameters may ask for (geth's standard is 256 MiB)
+const MAX_SCRYPT_MEMORY: u64 = 1024 * 1024 * 1024;
+/// Most scrypt work, N·r·p, a keystore may ask for: four times geth's standard 2^21
+const MAX_SCRYPT_WORK: u128 = 1 << 23;
+const MAX_SCRYPT_P: u32 = 16;
+/// Most PBKDF2 rounds a keystore may ask for, 16 times the usual 262144
+const MAX_PBKDF2_ROUNDS: u32 = 1 << 22;
+
+/// scrypt cost geth uses for its "standard" keystores (N = 2^18)
+pub const DEFAULT_SCRYPT_LOG_N: u8 = 18;
+const DEFAULT_SCRYPT_R: u32 = 8;
+const DEFAULT_SCRYPT_P: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct V3Keystore {
+    pub version: u8,
+    pub id: String,
+    /// Lowercase hex address without `0x`, as geth writes it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub address: Option<String>,
+    /// Some older geth files capitalize this key
+    #[serde(alias = "Crypto")]
+    pub crypto: V3Crypto,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct V3Crypto {
+    pub cipher: String,
+    pub cipherparams: V3CipherParams,
+    pub ciphertext: String,
+    pub kdf: String,
+    pub kdfparams: V3KdfParams,
+    pub mac: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct V3CipherParams {
+    pub iv: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum V3KdfParams {
+    Scrypt {
+        dklen: u32,
+        n: u32,
+        p: u32,
+        r: u32,
+        salt: String,
+    },
+    Pbkdf2 {
+        c: u32,
+        dklen: u32,
+        prf: String,
+        salt: String,
+    },
+}
+
+/// Decrypt a v3 JSON keystore and return the raw 32-byte private key
+/// A MAC mismatch is reported as `WrongPassphrase`.
+pub fn decrypt(json: &str, password: &[u8]) -> Result<Vec<u8>> {
+    let keystore: V3Keystore = serde_json::from_str(json)?;
+
+    if keystore.version != 3 {
+        return Err(VeroxError::UnsupportedVersion(format!("keystore v{}", keystore.version)));
+    }
+    let crypto = &keystore.crypto;
+    if crypto.cipher != CIPHER {
+        return Err(VeroxError::UnsupportedVersion(format!("cipher {}", crypto.cipher)));
+    }
+
+    let key = derive_key(&crypto.kdf, &crypto.kdfparams, password)?;
+    let ciphertext = decode_hex("ciphertext", &crypto.ciphertext)?;
+    let mac = decode_hex("mac", &crypto.mac)?;
+
+    if !bool::from(compute_mac(&key, &ciphertext).ct_eq(mac.as_slice())) {
+        return Err(VeroxError::WrongPassphrase);
+    }
+
+    let iv = decode_hex("iv", &crypto.cipherparams.iv)?;
+    let mut private_key = ciphertext;
+    Aes128Ctr::new_from_slices(&key[..16], &iv)
+        .map_err(|_| VeroxError::CorruptKeystore("invalid IV length".to_string()))?
+        .apply_keystream(&mut private_key);
+
+    Ok(private_key)
+}
+
+/// Encrypt a raw private key as a v3 JSON keystore using geth's standard scrypt cost
+pub fn encrypt(private_key: &[u8], password: &[u8], address: &str) -> Result<String> {
+    encrypt_with_scrypt(private_key, password, address, DEFAULT_SCRYPT_LOG_N)
+}
+
+/// Same as `encrypt` with an explicit scrypt work factor (N = 2^log_n)
+pub fn encrypt_with_scrypt(
+    private_key: &[u8],
+    password: &[u8],
+    address: &str,
+    log_n: u8,
+) -> Result<String> {
+    let mut salt = [0u8; 32];
+    OsRng.fill_bytes(&mut salt);
+    let mut iv = [0u8; 16];
+    OsRng.fill_bytes(&mut iv);
+
+    let kdfparams = V3KdfParams::Scrypt {
+        dklen: DKLEN,
+        n: 1u32 << log_n,
+        p: DEFAULT_SCRYPT_P,
+        r: DEFAULT_SCRYPT_R,
+        salt: hex::encode(salt),
+    };
+    let key = derive_key("scrypt", &kdfparams, password)?;
+
+    let mut ciphertext = private_key.to_vec();
+    Aes128Ctr::new_from_slices(&key[..16], &iv)
+        .map_err(|_| VeroxError::CorruptKeystore("invalid IV length".to_string()))?
+        .apply_keystream(&mut ciphertext);
+    let mac = compute_mac(&key, &ciphertext);
+
+    let keystore = V3Keystore {
+        version: 3,
+        id: uuid::Uuid::new_v4().to_string(),
+        address: Some(address.trim_start_matches("0x").to_lowercase()),
+        crypto: V3Crypto {
+            cipher: CIPHER.to_string(),
+            cipherparams: V3CipherParams { iv: hex::encode(iv) },
+            ciphertext: hex::encode(ciphertext),
+            kdf: "scrypt".to_string(),
+            kdfparams,
+            mac: hex::encode(mac),
+        },
+    };
+
+    Ok(serde_json::to_string_pretty(&keystore)?)
+}
+
+fn derive_key(kdf: &str, params: &V3KdfParams, password: &[u8]) -> Result<Vec<u8>> {
+    match (kdf, params) {
+        ("scrypt", V3KdfParams::Scrypt { dklen, n, p, r, salt }) => {
+            let memory = (*n as u64 + *p as u64) * *r as u64 * 128;
+            let work = *n as u128 * *r as u128 * *p as u128;
+            if !(DKLEN..=MAX_DKLEN).contains(dklen)
+                || !n.is_power_of_two()
+                || *n < 2
+                || *r == 0
+                || !(1..=MAX_SCRYPT_P).contains(p)
+                || memory > MAX_SCRYPT_MEMORY
+                || work > MAX_SCRYPT_WORK
+            {
+                return Err(VeroxError::CorruptKeystore("invalid scrypt parameters".to_string()));
+            }
+            let salt = decode_hex("salt", salt)?;
+            let mut key = vec![0u8; *dklen as usize];
+            let log_n = n.trailing_zeros() as u8;
+            if (log_n as u32) < *r * 16 {
+                let params = scrypt::Params::new(log_n, *r, *p)
+                    .map_err(|e| VeroxError::CorruptKeystore(format!("invalid scrypt parameters: {}", e)))?;
+                scrypt::scrypt(password, &salt, &params, &mut key)
+                    .map_err(|e| VeroxError::CorruptKeystore(format!("scrypt failed: {}", e)))?;
+            } else {
+                scrypt_beyond_rfc_bound(password, &salt, *n as usize, *r as usize, *p as usize, &mut key);
+            }
+            Ok(key)
+        }
+        ("pbkdf2", V3KdfParams::Pbkdf2 { c, dklen, prf, salt }) => {
+            if prf != "hmac-sha256" {
+                return Err(VeroxError::UnsupportedVersion(format!("PBKDF2 PRF {}", prf)));
+            }
+            if !(DKLEN..=MAX_DKLEN).contains(dklen) || !(1..=MAX_PBKDF2_ROUNDS).contains(c) {
+                return Err(VeroxError::CorruptKeystore("invalid PBKDF2 parameters".to_string()));
+            }
+            let salt = decode_hex("salt", salt)?;
+            let mut key = vec![0u8; *dklen as usize];
+            pbkdf2::pbkdf2_hmac::<Sha256>(password, &salt, *c, &mut key);
+            Ok(key)
+        }
+        _ => Err(VeroxError::UnsupportedVersion(format!("KDF {}", kdf))),
+    }
+}
+
+/// scrypt for parameters with N >= 2^(16r), which RFC 7914 rules out but geth accepts
+/// and the spec's own test vector uses (N = 2^18, r = 1).
+///
+/// The `scrypt` crate can't be used for these: `Params::new` enforces the RFC's bound and
+/// there is no unchecked constructor or exposed ROMix to call instead. This follows RFC
+/// 7914 section 5 directly, and the tests check it against the crate wherever both apply.
+/// Callers have already bounded the memory and work this takes.
+fn scrypt_beyond_rfc_bound(password: &[u8], salt: &[u8], n: usize, r: usize, p: usize, output: &mut [u8]) {
+    let words = 32 * r;
+    let mut b = vec![0u8; p * words * 4];
+    pbkdf2::pbkdf2_hmac::<Sha256>(password, salt, 1, &mut b);
+
+    let mut v = vec![0u32; n * words];
+    let mut x = vec![0u32; words];
+    let mut y = vec![0u32; words];
+    for chunk in b.chunks_mut(words * 4) {
+        for (word, bytes) in x.iter_mut().zip(chunk.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        // ROMix
+        for block in v.chunks_mut(words) {
+            block.copy_from_slice(&x);
+            block_mix(&x, &mut y);
+            std::mem::swap(&mut x, &mut y);
+        }
+        for _ in 0..n {
+            let j = x[words - 16] as usize & (n - 1);
+            for (a, b) in x.iter_mut().zip(&v[j * words..(j + 1) * words]) {
+                *a ^= b;
+            }
+            block_mix(&x, &mut y);
+            std::mem::swap(&mut x, &mut y);
+        }
+        for (bytes, word) in chunk.chunks_exact_mut(4).zip(&x) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    pbkdf2::pbkdf2_hmac::<Sha256>(password, &b, 1, output);
+}
+
+/// scrypt's BlockMix, writing the even blocks then the odd ones to `output`
+fn block_mix(input: &[u32], output: &mut [u32]) {
+    let half = input.len() / 2;
+    let mut x = [0u32; 16];
+    x.copy_from_slice(&input[input.len() - 16..]);
+    for (i, block) in input.chunks_exact(16).enumerate() {
+        for (a, b) in x.iter_mut().zip(block) {
+            *a ^= b;
+        }
+        salsa20_8(&mut x);
+        let pos = (i / 2) * 16 + (i % 2) * half;
+        output[pos..pos + 16].copy_from_slice(&x);
+    }
+}
+
+/// The Salsa20/8 core
+fn salsa20_8(block: &mut [u32; 16]) {
+    let mut x = *block;
+    let quarter = |x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize| {
+        x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+        x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+        x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+        x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+    };
+    for _ in 0..4 {
+        quarter(&mut x, 0, 4, 8, 12);
+        quarter(&mut x, 5, 9, 13, 1);
+        quarter(&mut x, 10, 14, 2, 6);
+        quarter(&mut x, 15, 3, 7, 11);
+        quarter(&mut x, 0, 1, 2, 3);
+        quarter(&mut x, 5, 6, 7, 4);
+        quarter(&mut x, 10, 11, 8, 9);
+        quarter(&mut x, 15, 12, 13, 14);
+    }
+    for (out, word) in block.iter_mut().zip(x) {
+        *out = out.wrapping_add(word);
+    }
+}
+
+fn compute_mac(key: &[u8], ciphertext: &[u8]) -> [u8; 32] {
+    let mut preimage = key[16..32].to_vec();
+    preimage.extend_from_slice(ciphertext);
+    keccak256(preimage)
+}
+
+fn decode_hex(field: &str, value: &str) -> Result<Vec<u8>> {
+    hex::decode(value.trim_start_matches("0x"))
+        .map_err(|e| VeroxError::CorruptKeystore(format!("invalid {} hex: {}", field, e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test vectors from the Web3 Secret Storage Definition
+    const VECTOR_PRIVATE_KEY: &str =
+        "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d";
+    const VECTOR_PASSWORD: &str = "testpassword";
+
+    const VECTOR_PBKDF2: &str = r#"{
+        "crypto" : {
+            "cipher" : "aes-128-ctr",
+            "cipherparams" : { "iv" : "6087dab2f9fdbbfaddc31a909735c1e6" },
+            "ciphertext" : "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+            "kdf" : "pbkdf2",
+            "kdfparams" : {
+                "c" : 262144,
+                "dklen" : 32,
+                "prf" : "hmac-sha256",
+                "salt" : "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+            },
+            "mac" : "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+        },
+        "id" : "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version" : 3
+    }"#;
+
+    const VECTOR_SCRYPT: &str = r#"{
+        "crypto" : {
+            "cipher" : "aes-128-ctr",
+            "cipherparams" : { "iv" : "83dbcc02d8ccb40e466191a123791e0e" },
+            "ciphertext" : "d172bf743a674da9cdad04534d56926ef8358534d458fffccd4e6ad2fbde479c",
+            "kdf" : "scrypt",
+            "kdfparams" : {
+                "dklen" : 32,
+                "n" : 262144,
+                "p" : 8,
+                "r" : 1,
+                "salt" : "ab0c7876052600dd703518d6fc3fe8984592145b591fc8fb5c6d43190334ba19"
+            },
+            "mac" : "2103ac29920d71da29f15d75b4a16dbe95cfd7ff8faea1056c33131d846e3097"
+        },
+        "id" : "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version" : 3
+    }"#;
+
+    #[test]
+    fn test_decrypt_pbkdf2_vector() {
+        let key = decrypt(VECTOR_PBKDF2, VECTOR_PASSWORD.as_bytes()).unwrap();
+        assert_eq!(hex::encode(key), VECTOR_PRIVATE_KEY);
+        assert!(matches!(
+            decrypt(VECTOR_PBKDF2, b"wrongpassword"),
+            Err(VeroxError::WrongPassphrase)
+        ));
+    }
+
+    #[test]
+    fn test_decrypt_scrypt_vector() {
+        let key = decrypt(VECTOR_SCRYPT, VECTOR_PASSWORD.as_bytes()).unwrap();
+        assert_eq!(hex::encode(key), VECTOR_PRIVATE_KEY);
+    }
+
+    #[test]
+    fn test_rejects_invalid_scrypt_params() {
+        let with = |from: &str, to: &str| VECTOR_SCRYPT.replace(from, to);
+        for hostile in [
+            with("\"dklen\" : 32", "\"dklen\" : 4294967295"),
+            with("\"dklen\" : 32", "\"dklen\" : 16"),
+            with("\"n\" : 262144", "\"n\" : 262143"),
+            with("\"n\" : 262144", "\"n\" : 2147483648"),
+            with("\"r\" : 1", "\"r\" : 0"),
+        ] {
+            assert!(matches!(
+                decrypt(&hostile, VECTOR_PASSWORD.as_bytes()),
+                Err(VeroxError::CorruptKeystore(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_rejects_excessive_work() {
+        let with = |from: &str, to: &str| VECTOR_SCRYPT.replace(from, to);
+        for hostile in [
+            // 2^18 · 4 · 16 fits the memory cap but is eight times the standard work
+            with("\"r\" : 1", "\"r\" : 4").replace("\"p\" : 8", "\"p\" : 16"),
+            with("\"p\" : 8", "\"p\" : 4294967295"),
+            with("\"p\" : 8", "\"p\" : 0"),
+            VECTOR_PBKDF2.replace("\"c\" : 262144", "\"c\" : 4294967295"),
+            VECTOR_PBKDF2.replace("\"c\" : 262144", "\"c\" : 0"),
+        ] {
+            assert!(matches!(
+                decrypt(&hostile, VECTOR_PASSWORD.as_bytes()),
+                Err(VeroxError::CorruptKeystore(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_mac_mismatch() {
+        let tampered = VECTOR_PBKDF2.replace("\"mac\" : \"517e", "\"mac\" : \"517f");
+        assert!(matches!(decrypt(&tampered, VECTOR_PASSWORD.as_bytes()), Err(VeroxError::WrongPassphrase)));
+        let truncated = VECTOR_PBKDF2.replace("e9b2\"", "\"");
+        assert!(matches!(decrypt(&truncated, VECTOR_PASSWORD.as_bytes()), Err(VeroxError::WrongPassphrase)));
+    }
+
+    #[test]
+    fn test_scrypt_beyond_rfc_bound_matches_crate() {
+        // RFC 7914's first test vector
+        let mut key = [0u8; 64];
+        scrypt_beyond_rfc_bound(b"", b"", 16, 1, 1, &mut key);
+        assert_eq!(
+            hex::encode(key),
+            "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+             fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906"
+        );
+
+        // Wherever the crate accepts the parameters, both agree
+        for (log_n, r, p) in [(4u8, 1u32, 1u32), (10, 8, 16), (8, 2, 3), (14, 1, 1), (12, 4, 2)] {
+            let mut expected = [0u8; 32];
+            let params = scrypt::Params::new(log_n, r, p).unwrap();
+            scrypt::scrypt(b"password", b"NaCl", &params, &mut expected).unwrap();
+            let mut key = [0u8; 32];
+            scrypt_beyond_rfc_bound(b"password", b"NaCl", 1 << log_n, r as usize, p as usize, &mut key);
+            assert_eq!(key, expected, "N = 2^{}, r = {}, p = {}", log_n, r, p);
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let private_key = hex::decode(VECTOR_PRIVATE_KEY).unwrap();
+        let json = encrypt_with_scrypt(&private_key, b"roundtrip", "0xABCDEF", 10).unwrap();
+
+        let parsed: V3Keystore = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.address.as_deref(), Some("abcdef"));
+        assert_eq!(decrypt(&json, b"roundtrip").unwrap(), private_key);
+        assert!(decrypt(&json, b"other").is_err());
+    }
+}
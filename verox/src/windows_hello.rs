@@ -36,7 +36,7 @@ pub fn can_evaluate_windows_hello() -> Result<bool, String> {
     
     // For now, we'll assume Windows Hello is available if we're on Windows 10+
     // In a production environment, you'd check the Windows version and Hello availability
-    println!("Checking Windows Hello availability...");
+    eprintln!("Checking Windows Hello availability...");
     
     // Simple check - if we can access credential manager, assume Hello is available
     Ok(true)
@@ -171,10 +171,10 @@ fn prompt_windows_hello(reason: &str) -> Result<bool, String> {
         );
         
         if result == IDYES {
-            println!("✓ Windows Hello authentication simulated successfully");
+            eprintln!("✓ Windows Hello authentication simulated successfully");
             Ok(true)
         } else {
-            println!("Windows Hello authentication cancelled");
+            eprintln!("Windows Hello authentication cancelled");
             Ok(false)
         }
     }
@@ -183,25 +183,25 @@ fn prompt_windows_hello(reason: &str) -> Result<bool, String> {
 /// Register Windows Hello authentication
 #[cfg(target_os = "windows")]
 pub fn register() -> Result<(), String> {
-    println!("Setting up Windows Hello authentication for Verox Wallet...");
+    eprintln!("Setting up Windows Hello authentication for Verox Wallet...");
     
     // Check if Windows Hello is available
     if !can_evaluate_windows_hello()? {
         return Err("Windows Hello is not available. Please ensure Windows Hello is set up in Windows Settings.".to_string());
     }
     
-    println!("Please authenticate with Windows Hello to register...");
+    eprintln!("Please authenticate with Windows Hello to register...");
     
     // Prompt for Windows Hello
     match prompt_windows_hello("Register your biometric authentication for Verox Wallet") {
         Ok(true) => {
-            println!("✓ Windows Hello authentication successful");
+            eprintln!("✓ Windows Hello authentication successful");
             
             // Store secret in credential manager
             match store_secret_in_credential_manager(SECRET_DATA) {
                 Ok(_) => {
-                    println!("Biometric authentication registered successfully!");
-                    println!("   Your Windows Hello is now linked to your Verox Wallet.");
+                    eprintln!("Biometric authentication registered successfully!");
+                    eprintln!("   Your Windows Hello is now linked to your Verox Wallet.");
                     Ok(())
                 }
                 Err(e) => Err(format!("Failed to store biometric secret: {}", e)),
@@ -211,7 +211,7 @@ pub fn register() -> Result<(), String> {
             Err("Windows Hello authentication was cancelled".to_string())
         }
         Err(e) => {
-            println!("Windows Hello registration failed: {}", e);
+            eprintln!("Windows Hello registration failed: {}", e);
             Err(e)
         }
     }
@@ -220,7 +220,7 @@ pub fn register() -> Result<(), String> {
 /// Verify Windows Hello authentication
 #[cfg(target_os = "windows")]
 pub fn verify() -> Result<bool, String> {
-    println!("Verifying Windows Hello authentication...");
+    eprintln!("Verifying Windows Hello authentication...");
     
     // Check if Windows Hello is available
     if !can_evaluate_windows_hello()? {
@@ -231,36 +231,36 @@ pub fn verify() -> Result<bool, String> {
     match get_secret_from_credential_manager() {
         Ok(_) => {
             // Secret exists, proceed with Windows Hello verification
-            println!("Please authenticate with Windows Hello...");
+            eprintln!("Please authenticate with Windows Hello...");
             
             // Prompt for Windows Hello
             match prompt_windows_hello("Authenticate with Windows Hello to access your Verox Wallet") {
                 Ok(true) => {
-                    println!("✓ Windows Hello authentication successful");
+                    eprintln!("✓ Windows Hello authentication successful");
                     
                     // Verify the secret
                     match get_secret_from_credential_manager() {
                         Ok(stored_secret) => {
                             if stored_secret == SECRET_DATA {
-                                println!("Biometric verification successful!");
+                                eprintln!("Biometric verification successful!");
                                 Ok(true)
                             } else {
-                                println!("Credential verification failed: Invalid secret");
+                                eprintln!("Credential verification failed: Invalid secret");
                                 Ok(false)
                             }
                         }
                         Err(e) => {
-                            println!("Secret verification failed: {}", e);
+                            eprintln!("Secret verification failed: {}", e);
                             Ok(false)
                         }
                     }
                 }
                 Ok(false) => {
-                    println!("Windows Hello authentication was cancelled");
+                    eprintln!("Windows Hello authentication was cancelled");
                     Ok(false)
                 }
                 Err(e) => {
-                    println!("Windows Hello verification failed: {}", e);
+                    eprintln!("Windows Hello verification failed: {}", e);
                     Err(e)
                 }
             }
@@ -273,15 +273,15 @@ pub fn verify() -> Result<bool, String> {
 
 #[cfg(target_os = "windows")]
 pub fn unregister() -> Result<(), String> {
-    println!("Removing Windows Hello registration...");
+    eprintln!("Removing Windows Hello registration...");
     
     match delete_secret_from_credential_manager() {
         Ok(_) => {
-            println!("Windows Hello registration removed successfully!");
+            eprintln!("Windows Hello registration removed successfully!");
             Ok(())
         }
         Err(e) => {
-            println!("Failed to remove Windows Hello registration: {}", e);
+            eprintln!("Failed to remove Windows Hello registration: {}", e);
             Err(e)
         }
     }